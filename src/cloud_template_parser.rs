@@ -1,30 +1,102 @@
-extern crate nom;
-use nom::{
-  IResult,
-};
 // use crate::terraform::root;
 use crate::terraform::{
   root,
+  tfvars,
 };
 
+use crate::terraform_json;
+use crate::cloudformation;
+use crate::terraform_plan;
 use crate::structs::terraform_block::{
     TerraformBlock,
 };
+use crate::structs::attributes::Attribute;
+use crate::structs::parse_error::ParseError;
+use crate::glob::glob_match;
 
 use std::fs;
+use std::path::{Path, PathBuf};
 
 /// CloudTemplateParser -> (ResourceTree)
 /// - reads in (Terraform, Cloudformation) templates -- might be replaced by separate FileReader Entity at some point.
+/// - Terraform is read in either its HCL syntax or, for `*.tf.json` files, its JSON syntax.
+/// - the JSON of `terraform show -json`, for a plan or a state, is read too when a file is named.
 /// - returns a ResourceTree representing all of the resources in the CloudTemplate
 /// - uses nom to create a ResourceTree.
+/// - parse errors don't stop the run, they are collected as diagnostics next to the resources which did parse.
+/// - given files and directories, it discovers the templates and .tfvars files in them and merges
+///   everything into one ResourceTree. Every block keeps the file it came from in its span.
 
 #[derive(Debug)]
-pub struct ResourceTree {}
+pub struct ResourceTree {
+    pub resources: Vec<TerraformBlock>,
+    pub diagnostics: Vec<ParseError>,
+    /// assignments read from any .tfvars files found alongside the templates
+    pub variables: Vec<Attribute>,
+}
 
 impl ResourceTree {
-    pub fn new() -> ResourceTree {
-        ResourceTree {}
+    pub fn new(resources: Vec<TerraformBlock>, diagnostics: Vec<ParseError>) -> ResourceTree {
+        ResourceTree { resources, diagnostics, variables: vec![] }
+    }
+
+    pub fn merge(&mut self, other: ResourceTree) {
+        self.resources.extend(other.resources);
+        self.diagnostics.extend(other.diagnostics);
+        self.variables.extend(other.variables);
+    }
+}
+
+/// the files read from a directory when no include globs are given. Of the `.json`, `.yaml`, `.yml`
+/// and `.template` files found, only those which look like CloudFormation templates are read.
+const DEFAULT_INCLUDES: [&str; 8] = ["*.tf", "*.tf.json", "*.tfvars", "*.tfvars.json", "*.json", "*.yaml", "*.yml", "*.template"];
+
+/// the languages templates are written in
+#[derive(Debug, PartialEq)]
+enum Syntax {
+    Hcl,
+    TerraformJson,
+    CloudFormation,
+    /// the output of `terraform show -json`
+    Plan,
+}
+
+/// choose a parser by the file's extension, or by sniffing its contents when the extension could be either
+fn syntax(path: &Path, contents: &str) -> Syntax {
+    let name = path.to_string_lossy();
+
+    if name.ends_with(".tf") {
+        Syntax::Hcl
+    } else if name.ends_with(".tf.json") {
+        Syntax::TerraformJson
+    } else if terraform_plan::is_plan(contents) {
+        Syntax::Plan
+    } else if name.ends_with(".yaml") || name.ends_with(".yml") || cloudformation::is_cloudformation(contents) {
+        Syntax::CloudFormation
+    } else if contents.trim_start().starts_with('{') {
+        Syntax::TerraformJson
+    } else {
+        Syntax::Hcl
+    }
+}
+
+/// whether a file found in a directory, rather than named, should be read
+fn is_template(path: &Path) -> Result<bool, std::io::Error> {
+    let name = path.to_string_lossy();
+    if [".tf", ".tf.json", ".tfvars", ".tfvars.json"].iter().any(|extension| name.ends_with(extension)) {
+        return Ok(true);
     }
+    Ok(cloudformation::is_cloudformation(&fs::read_to_string(path)?))
+}
+
+fn is_tfvars(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tfvars") || name.ends_with(".tfvars.json")
+}
+
+/// files written in Terraform's JSON syntax, `*.tf.json` and `*.tfvars.json`
+fn is_json(path: &Path) -> bool {
+    path.extension().map_or(false, |extension| extension == "json")
 }
 
 #[derive(Debug)]
@@ -35,14 +107,115 @@ impl CloudTemplateParser {
         CloudTemplateParser {}
     }
 
-    pub fn handle(&self, filename: std::path::PathBuf) -> Vec<TerraformBlock> {
+    pub fn handle(&self, filename: std::path::PathBuf) -> Result<ResourceTree, std::io::Error> {
         // let contents = fs::read_to_string("example_files/discovery-minus-bad-bits.tf")
         //     .expect("Something went wrong reading the file");
-        let contents = fs::read_to_string(filename)
-            .expect("Something went wrong reading the file");
+        let contents = fs::read_to_string(&filename)?;
+
+        let (mut resources, errors) = match syntax(&filename, &contents) {
+            Syntax::Hcl => root(contents.as_str()),
+            Syntax::TerraformJson => terraform_json::root(contents.as_str()),
+            Syntax::CloudFormation => cloudformation::root(contents.as_str()),
+            Syntax::Plan => terraform_plan::root(contents.as_str()),
+        };
+        let file = filename.display().to_string();
+        let diagnostics = errors.into_iter().map(|error| error.with_file(&file)).collect();
+
+        for resource in resources.iter_mut() {
+            resource.for_each_span_mut(&mut |span| span.file = Some(file.clone()));
+        }
+
+        Ok(ResourceTree::new(resources, diagnostics))
+    }
+
+    /// parse every template and .tfvars file found in `paths` into one ResourceTree
+    pub fn handle_paths(&self, paths: &[PathBuf], include: &[String], exclude: &[String]) -> Result<ResourceTree, std::io::Error> {
+        let mut tree = ResourceTree::new(vec![], vec![]);
+
+        for file in self.discover(paths, include, exclude)? {
+            if is_tfvars(&file) {
+                let (assignments, diagnostics) = self.handle_tfvars(file)?;
+                tree.variables.extend(assignments);
+                tree.diagnostics.extend(diagnostics);
+            } else {
+                tree.merge(self.handle(file)?);
+            }
+        }
+
+        Ok(tree)
+    }
+
+    /// parse the templates directly inside a module's directory. Its subdirectories hold other modules.
+    pub fn handle_module(&self, directory: &Path) -> Result<ResourceTree, std::io::Error> {
+        self.handle_paths(&[directory.to_path_buf()], &[String::from("*.tf"), String::from("*.tf.json")], &[String::from("*/**")])
+    }
+
+    /// Files named in `paths` are always read, unless excluded. Directories are searched recursively,
+    /// skipping hidden entries such as `.terraform`, for files matching an `include` glob
+    /// (terraform, tfvars and CloudFormation files by default). Globs are matched against paths relative to the directory.
+    pub fn discover(&self, paths: &[PathBuf], include: &[String], exclude: &[String]) -> Result<Vec<PathBuf>, std::io::Error> {
+        let includes: Vec<&str> = if include.is_empty() {
+            DEFAULT_INCLUDES.to_vec()
+        } else {
+            include.iter().map(String::as_str).collect()
+        };
+        let excluded = |relative: &str| exclude.iter().any(|pattern| glob_match(pattern, relative));
+
+        let mut files = vec![];
+        for path in paths {
+            if path.is_dir() {
+                let mut found = vec![];
+                Self::walk(path, &mut found)?;
+
+                for file in found {
+                    let relative = file.strip_prefix(path).unwrap_or(&file).to_string_lossy().replace('\\', "/");
+                    if includes.iter().any(|pattern| glob_match(pattern, &relative)) && !excluded(&relative) && is_template(&file)? {
+                        files.push(file);
+                    }
+                }
+            } else if !excluded(&path.to_string_lossy().replace('\\', "/")) {
+                files.push(path.to_path_buf());
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn walk(directory: &Path, found: &mut Vec<PathBuf>) -> Result<(), std::io::Error> {
+        let mut entries = fs::read_dir(directory)?.collect::<Result<Vec<fs::DirEntry>, std::io::Error>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
 
-        let (_, result) = root(contents.as_str()).unwrap();
-        result
+        for entry in entries {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                Self::walk(&path, found)?;
+            } else {
+                found.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// read the variable assignments of a .tfvars file
+    pub fn handle_tfvars(&self, filename: std::path::PathBuf) -> Result<(Vec<Attribute>, Vec<ParseError>), std::io::Error> {
+        let contents = fs::read_to_string(&filename)?;
+
+        let (mut assignments, errors) = if is_json(&filename) {
+            terraform_json::tfvars(contents.as_str())
+        } else {
+            tfvars(contents.as_str())
+        };
+        let file = filename.display().to_string();
+        let diagnostics = errors.into_iter().map(|error| error.with_file(&file)).collect();
+
+        for assignment in assignments.iter_mut() {
+            assignment.for_each_span_mut(&mut |span| span.file = Some(file.clone()));
+        }
+
+        Ok((assignments, diagnostics))
     }
 }
 
@@ -50,6 +223,73 @@ impl CloudTemplateParser {
 mod tests {
     use super::*;
 
+    #[test]
+    fn handle_discovery_template() {
+        let parser = CloudTemplateParser::new();
+        let result = parser.handle(std::path::PathBuf::from("./example_files/discovery.tf")).unwrap();
+
+        assert_eq!(result.resources.len(), 258);
+        assert_eq!(result.diagnostics, vec![]);
+        assert_eq!(result.resources[0].span().file, Some(String::from("./example_files/discovery.tf")))
+    }
+
+    #[test]
+    fn handle_discovery_tfvars() {
+        let parser = CloudTemplateParser::new();
+        let (assignments, diagnostics) = parser.handle_tfvars(std::path::PathBuf::from("./example_files/discovery.tfvars")).unwrap();
+
+        let names: Vec<&str> = assignments.iter().map(|assignment| assignment.key.as_str()).collect();
+        assert_eq!(names, vec!["component_name", "environment", "environment_bucket"]);
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    fn discover_example_files() {
+        let parser = CloudTemplateParser::new();
+        let files = parser.discover(&[PathBuf::from("./example_files")], &[], &[String::from("discovery*.tf")]).unwrap();
+
+        assert_eq!(files, vec![
+            PathBuf::from("./example_files/basic.tf"),
+            PathBuf::from("./example_files/discovery.tfvars"),
+            PathBuf::from("./example_files/orders.yaml"),
+            PathBuf::from("./example_files/variables.tf"),
+        ]);
+    }
+
+    #[test]
+    fn handle_cloudformation_template() {
+        let parser = CloudTemplateParser::new();
+        let result = parser.handle(PathBuf::from("./example_files/orders.yaml")).unwrap();
+
+        let addresses: Vec<String> = result.resources.iter().map(|resource| resource.address()).collect();
+        assert_eq!(addresses, vec![
+            "variable.Environment",
+            "AWS::SQS::Queue.OrdersQueue",
+            "AWS::SQS::Queue.OrdersDeadLetterQueue",
+            "AWS::Lambda::Function.OrdersFunction",
+            "AWS::Lambda::EventSourceMapping.OrdersMapping",
+            "output.OrdersQueueUrl",
+        ]);
+        assert_eq!(result.diagnostics, vec![]);
+        assert_eq!(syntax(Path::new("template.json"), "{\"AWSTemplateFormatVersion\": \"2010-09-09\"}"), Syntax::CloudFormation);
+        assert_eq!(syntax(Path::new("generated.json"), "{\"resource\": {}}"), Syntax::TerraformJson);
+        assert_eq!(syntax(Path::new("plan.json"), "{\"format_version\": \"1.2\", \"terraform_version\": \"1.5.7\"}"), Syntax::Plan);
+    }
+
+    #[test]
+    fn handle_paths_merges_files() {
+        let parser = CloudTemplateParser::new();
+        let paths = vec![PathBuf::from("./example_files"), PathBuf::from("./example_files/discovery.tf")];
+        let result = parser.handle_paths(&paths, &[String::from("*.tf"), String::from("*.tfvars")], &[String::from("discovery*.tf")]).unwrap();
+
+        let files: Vec<String> = result.resources.iter().map(|resource| resource.span().file.clone().unwrap()).collect();
+        assert_eq!(result.resources.len(), 5);
+        assert_eq!(files[0], "./example_files/basic.tf");
+        assert_eq!(files[4], "./example_files/variables.tf");
+        assert_eq!(result.variables.len(), 3);
+        assert_eq!(result.diagnostics, vec![]);
+    }
+
     // #[test]
     // fn parse_test() {
     //     let parser = CloudTemplateParser::new();
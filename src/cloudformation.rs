@@ -0,0 +1,653 @@
+//! CloudFormation templates, in JSON or YAML.
+//! - JSON is read with the located JSON parser of `terraform_json`, YAML with yaml-rust's event parser,
+//!   both into the same tree of nodes which remember where they were read from
+//! - `Resources` become `resource` blocks typed by their `Type`, eg. `resource "AWS::SQS::Queue" "Queue"`,
+//!   with their `Properties` as attributes
+//! - `Parameters` become `variable` blocks and `Outputs` become `output` blocks, so the Resolver can
+//!   substitute parameter defaults as it does for terraform variables
+//! - intrinsic functions, in their long (`Fn::GetAtt`) and short (`!GetAtt`) forms, become expressions.
+//!   `Ref` and `GetAtt` of a resource refer to it the way a terraform traversal does, eg. `AWS::SQS::Queue.Queue.Arn`,
+//!   `!Sub` becomes a template and `!Join` a call to `join`
+
+use std::collections::HashMap;
+
+use yaml_rust::parser::{ Event, MarkedEventReceiver, Parser };
+use yaml_rust::scanner::{ Marker, TScalarStyle, TokenType };
+
+use crate::structs::terraform_block::{
+    TerraformBlock,
+    TerraformBlockWithOneIdentifier,
+    TerraformBlockWithTwoIdentifiers,
+};
+use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::template_string::{ BuiltInFunction, Expression, TemplatePart, Traversal, TraversalStep };
+use crate::structs::parse_error::ParseError;
+use crate::structs::span::{ LineIndex, Position, Span };
+use crate::terraform_json::{ self, Located, Value };
+
+/// A JSON or YAML value, with the `!Tag` written before it in YAML
+#[derive(Debug, Clone)]
+struct Node {
+    value: NodeValue,
+    tag: Option<String>,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+enum NodeValue {
+    Str(String),
+    Num(f64),
+    Boolean(bool),
+    Null,
+    Sequence(Vec<Node>),
+    /// entries in the order written, each with the span from its key to the end of its value
+    Mapping(Vec<(String, Span, Node)>),
+}
+
+impl Node {
+    fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            NodeValue::Mapping(entries) => entries.iter().find(|(name, _, _)| name == key).map(|(_, _, node)| node),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            NodeValue::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// whether a document looks like a CloudFormation template rather than a terraform one
+pub fn is_cloudformation(contents: &str) -> bool {
+    contents.contains("AWSTemplateFormatVersion") || (contents.contains("Resources") && contents.contains("AWS::"))
+}
+
+/// Parse a CloudFormation template, JSON when it starts with `{` and YAML otherwise, into the blocks it declares.
+/// Resources which are shaped wrongly produce a ParseError and are skipped.
+pub fn root(i: &str) -> (Vec<TerraformBlock>, Vec<ParseError>) {
+    let document = if i.trim_start().starts_with('{') { json_document(i) } else { yaml_document(i) };
+
+    match document {
+        Ok(document) => Template::new(&document).blocks(i),
+        Err(error) => (vec![], vec![error]),
+    }
+}
+
+fn json_document(i: &str) -> Result<Node, ParseError> {
+    let document = terraform_json::document(i)?;
+    Ok(json_node(&document, &LineIndex::new(i)))
+}
+
+fn json_node(located: &Located, index: &LineIndex) -> Node {
+    let span = |start: usize, end: usize| {
+        let mut span = Span::remaining(start, end);
+        span.resolve(index);
+        span
+    };
+
+    let value = match &located.value {
+        Value::Str(value) => NodeValue::Str(value.to_string()),
+        Value::Num(value) => NodeValue::Num(*value),
+        Value::Boolean(value) => NodeValue::Boolean(*value),
+        Value::Null => NodeValue::Null,
+        Value::Array(items) => NodeValue::Sequence(items.iter().map(|item| json_node(item, index)).collect()),
+        Value::Object(properties) => NodeValue::Mapping(properties.iter()
+            .map(|(key, key_start, value)| (key.to_string(), span(*key_start, value.end), json_node(value, index)))
+            .collect()),
+    };
+
+    Node { value, tag: None, span: span(located.start, located.end) }
+}
+
+fn yaml_document(i: &str) -> Result<Node, ParseError> {
+    let mut builder = YamlBuilder::new(i);
+    let mut parser = Parser::new(i.chars());
+
+    if let Err(error) = parser.load(&mut builder, false) {
+        let position = builder.position(error.marker());
+        let message = error.to_string();
+        let reason = message.split(" at line ").next().unwrap_or("");
+        return Err(ParseError::at(i, position.offset, &format!("valid YAML ({})", reason)));
+    }
+
+    builder.document.ok_or_else(|| ParseError::at(i, 0, "a YAML document"))
+}
+
+/// an open sequence or mapping, with its tag and where it started
+struct Collection {
+    nodes: Vec<Node>,
+    tag: Option<String>,
+    start: Marker,
+    mapping: bool,
+}
+
+/// builds the Node tree from yaml-rust's events
+struct YamlBuilder {
+    chars: Vec<char>,
+    /// the byte offset of each character, yaml-rust's markers count characters
+    offsets: Vec<usize>,
+    open: Vec<Collection>,
+    document: Option<Node>,
+}
+
+impl YamlBuilder {
+    fn new(input: &str) -> YamlBuilder {
+        let mut offsets: Vec<usize> = input.char_indices().map(|(offset, _)| offset).collect();
+        offsets.push(input.len());
+
+        YamlBuilder { chars: input.chars().collect(), offsets, open: vec![], document: None }
+    }
+
+    fn position(&self, marker: &Marker) -> Position {
+        Position {
+            offset: self.offsets[marker.index().min(self.offsets.len() - 1)],
+            line: marker.line(),
+            column: marker.col() + 1,
+        }
+    }
+
+    fn span(&self, start: &Marker, end: &Marker) -> Span {
+        Span { file: None, start: self.position(start), end: self.position(end) }
+    }
+
+    /// yaml-rust drops the tags of sequences and mappings, so look for one written just before the collection
+    fn tag_before(&self, marker: &Marker) -> Option<String> {
+        let before = &self.chars[..marker.index().min(self.chars.len())];
+        let end = before.iter().rposition(|c| !c.is_whitespace())? + 1;
+        let start = before[..end].iter().rposition(|c| c.is_whitespace()).map_or(0, |position| position + 1);
+
+        let word: String = before[start..end].iter().collect();
+        if word.starts_with('!') { Some(word) } else { None }
+    }
+
+    fn push(&mut self, node: Node) {
+        match self.open.last_mut() {
+            Some(collection) => collection.nodes.push(node),
+            None => self.document = Some(node),
+        }
+    }
+
+    fn scalar(&self, value: String, style: TScalarStyle, tag: Option<TokenType>, marker: &Marker) -> Node {
+        let quoted = matches!(style, TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted);
+        let tag = match tag {
+            Some(TokenType::Tag(handle, suffix)) => Some(format!("{}{}", handle, suffix)),
+            _ => None,
+        };
+
+        // the end is where the scalar would finish if it were written on one line without escapes
+        let length = value.chars().count() + if quoted { 2 } else { 0 };
+        let end = self.position(marker);
+        let end_offset = self.offsets[(marker.index() + length).min(self.offsets.len() - 1)];
+        let span = Span {
+            file: None,
+            start: self.position(marker),
+            end: Position { offset: end_offset, line: end.line, column: end.column + end_offset - end.offset },
+        };
+
+        let value = match value.as_str() {
+            _ if quoted || tag.as_deref() == Some("!!str") => NodeValue::Str(value),
+            "true" | "True" | "TRUE" => NodeValue::Boolean(true),
+            "false" | "False" | "FALSE" => NodeValue::Boolean(false),
+            "~" | "null" | "Null" | "NULL" | "" => NodeValue::Null,
+            number => match yaml_number(number) {
+                Some(number) => NodeValue::Num(number),
+                None => NodeValue::Str(value),
+            },
+        };
+
+        Node { value, tag, span }
+    }
+}
+
+impl MarkedEventReceiver for YamlBuilder {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::Scalar(value, style, _, tag) => {
+                let node = self.scalar(value, style, tag, &marker);
+                self.push(node);
+            },
+            Event::SequenceStart(_) | Event::MappingStart(_) => {
+                let tag = self.tag_before(&marker);
+                let mapping = matches!(event, Event::MappingStart(_));
+                self.open.push(Collection { nodes: vec![], tag, start: marker, mapping });
+            },
+            Event::SequenceEnd | Event::MappingEnd => {
+                let collection = match self.open.pop() {
+                    Some(collection) => collection,
+                    None => return,
+                };
+                let span = self.span(&collection.start, &marker);
+
+                let value = if collection.mapping {
+                    let mut entries = vec![];
+                    let mut nodes = collection.nodes.into_iter();
+                    while let (Some(key), Some(value)) = (nodes.next(), nodes.next()) {
+                        let name = match key.value {
+                            NodeValue::Str(name) => name,
+                            NodeValue::Num(number) => number.to_string(),
+                            NodeValue::Boolean(boolean) => boolean.to_string(),
+                            _ => String::new(),
+                        };
+                        let entry_span = Span { file: None, start: key.span.start, end: value.span.end };
+                        entries.push((name, entry_span, value));
+                    }
+                    NodeValue::Mapping(entries)
+                } else {
+                    NodeValue::Sequence(collection.nodes)
+                };
+
+                self.push(Node { value, tag: collection.tag, span });
+            },
+            // CloudFormation doesn't allow anchors, so aliases are taken to be empty
+            Event::Alias(_) => self.push(Node { value: NodeValue::Null, tag: None, span: self.span(&marker, &marker) }),
+            _ => (),
+        }
+    }
+}
+
+/// a plain scalar written as a decimal number. Anything else, eg. `012` or `.inf`, is kept as a string.
+fn yaml_number(text: &str) -> Option<f64> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits[1..].starts_with('.');
+
+    if digits.starts_with(|c: char| c.is_ascii_digit()) && !leading_zero {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// the intrinsic function a node calls, from either a `!Tag` or a single `Fn::` key, with its argument
+fn intrinsic(node: &Node) -> Option<(String, Node)> {
+    if let Some(tag) = &node.tag {
+        let name = tag.trim_start_matches('!');
+        if !tag.starts_with("!!") {
+            let name = if name == "Ref" || name == "Condition" { name.to_string() } else { format!("Fn::{}", name) };
+            return Some((name, Node { tag: None, ..node.clone() }));
+        }
+    }
+
+    match &node.value {
+        NodeValue::Mapping(entries) if entries.len() == 1 && (entries[0].0 == "Ref" || entries[0].0.starts_with("Fn::")) => {
+            Some((entries[0].0.to_string(), entries[0].2.clone()))
+        },
+        _ => None,
+    }
+}
+
+/// the entries of a top-level section such as `Resources`
+fn section(document: &Node, name: &str) -> Vec<(String, Span, Node)> {
+    match document.get(name).map(|node| &node.value) {
+        Some(NodeValue::Mapping(entries)) => entries.clone(),
+        _ => vec![],
+    }
+}
+
+/// what the logical ids used in the template name
+struct Template<'a> {
+    document: &'a Node,
+    resource_types: HashMap<String, String>,
+    parameters: Vec<String>,
+}
+
+impl<'a> Template<'a> {
+    fn new(document: &'a Node) -> Template<'a> {
+        let resource_types = section(document, "Resources").into_iter()
+            .filter_map(|(id, _, resource)| Some((id, resource.get("Type")?.as_str()?.to_string())))
+            .collect();
+        let parameters = section(document, "Parameters").into_iter().map(|(name, _, _)| name).collect();
+
+        Template { document, resource_types, parameters }
+    }
+
+    fn section(&self, name: &str) -> Vec<(String, Span, Node)> {
+        section(self.document, name)
+    }
+
+    fn blocks(&self, i: &str) -> (Vec<TerraformBlock>, Vec<ParseError>) {
+        let mut blocks = vec![];
+        let mut errors = vec![];
+
+        if !matches!(self.document.value, NodeValue::Mapping(_)) {
+            return (blocks, vec![ParseError::at(i, self.document.span.start.offset, "a mapping of template sections")]);
+        }
+
+        for (name, span, parameter) in self.section("Parameters") {
+            let attributes = self.attributes(&parameter).into_iter()
+                .map(|attribute| match attribute.key.as_str() {
+                    "Default" => Attribute { key: String::from("default"), ..attribute },
+                    _ => attribute,
+                })
+                .collect();
+            blocks.push(TerraformBlock::WithOneIdentifier(TerraformBlockWithOneIdentifier {
+                block_type: String::from("variable"),
+                first_identifier: name,
+                attributes,
+                span,
+            }));
+        }
+
+        for (id, span, resource) in self.section("Resources") {
+            let resource_type = match resource.get("Type").and_then(Node::as_str) {
+                Some(resource_type) => resource_type.to_string(),
+                None => {
+                    errors.push(ParseError::at(i, resource.span.start.offset, "a resource with a `Type`"));
+                    continue;
+                },
+            };
+
+            let mut attributes = match resource.get("Properties") {
+                Some(properties) => self.attributes(properties),
+                None => vec![],
+            };
+            if let Some(depends_on) = resource.get("DependsOn") {
+                let names = match &depends_on.value {
+                    NodeValue::Sequence(names) => names.iter().filter_map(Node::as_str).collect(),
+                    NodeValue::Str(name) => vec![name.as_str()],
+                    _ => vec![],
+                };
+                attributes.push(Attribute {
+                    key: String::from("DependsOn"),
+                    value: AttributeType::Array(names.into_iter().map(|name| AttributeType::Expression(self.reference(name))).collect()),
+                    span: depends_on.span.clone(),
+                });
+            }
+
+            blocks.push(TerraformBlock::WithTwoIdentifiers(TerraformBlockWithTwoIdentifiers {
+                block_type: String::from("resource"),
+                first_identifier: resource_type,
+                second_identifier: id,
+                attributes,
+                span,
+                module_path: vec![],
+                planned_actions: vec![],
+                references: vec![],
+            }));
+        }
+
+        for (name, span, output) in self.section("Outputs") {
+            let attributes = self.attributes(&output).into_iter()
+                .map(|attribute| match attribute.key.as_str() {
+                    "Value" => Attribute { key: String::from("value"), ..attribute },
+                    _ => attribute,
+                })
+                .collect();
+            blocks.push(TerraformBlock::WithOneIdentifier(TerraformBlockWithOneIdentifier {
+                block_type: String::from("output"),
+                first_identifier: name,
+                attributes,
+                span,
+            }));
+        }
+
+        (blocks, errors)
+    }
+
+    fn attributes(&self, node: &Node) -> Vec<Attribute> {
+        match &node.value {
+            NodeValue::Mapping(entries) => entries.iter()
+                .map(|(key, span, value)| Attribute { key: key.to_string(), value: self.value(value), span: span.clone() })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn value(&self, node: &Node) -> AttributeType {
+        if intrinsic(node).is_some() {
+            return AttributeType::Expression(self.expression(node));
+        }
+
+        match &node.value {
+            NodeValue::Str(value) => AttributeType::Str(value.to_string()),
+            NodeValue::Num(value) => AttributeType::Num(*value),
+            NodeValue::Boolean(value) => AttributeType::Boolean(*value),
+            NodeValue::Null => AttributeType::Expression(Expression::Null),
+            NodeValue::Sequence(items) => AttributeType::Array(items.iter().map(|item| self.value(item)).collect()),
+            NodeValue::Mapping(_) => AttributeType::Block(self.attributes(node)),
+        }
+    }
+
+    fn expression(&self, node: &Node) -> Expression {
+        if let Some((function, argument)) = intrinsic(node) {
+            return self.call(&function, &argument);
+        }
+
+        match &node.value {
+            NodeValue::Str(value) => Expression::Str(value.to_string()),
+            NodeValue::Num(value) => Expression::Num(*value),
+            NodeValue::Boolean(value) => Expression::Boolean(*value),
+            NodeValue::Null => Expression::Null,
+            NodeValue::Sequence(items) => Expression::Tuple(items.iter().map(|item| self.expression(item)).collect()),
+            NodeValue::Mapping(entries) => Expression::Object(entries.iter()
+                .map(|(key, _, value)| (Expression::Str(key.to_string()), self.expression(value)))
+                .collect()),
+        }
+    }
+
+    fn call(&self, function: &str, argument: &Node) -> Expression {
+        let items = match &argument.value {
+            NodeValue::Sequence(items) => items.clone(),
+            _ => vec![argument.clone()],
+        };
+
+        match (function, argument.as_str()) {
+            ("Ref", Some(name)) => self.reference(name),
+            ("Fn::GetAtt", Some(path)) => {
+                let mut names = path.split('.');
+                self.attribute(names.next().unwrap_or_default(), names.collect())
+            },
+            ("Fn::GetAtt", None) if items.len() == 2 && items.iter().all(|item| item.as_str().is_some()) => {
+                self.attribute(items[0].as_str().unwrap(), items[1].as_str().unwrap().split('.').collect())
+            },
+            ("Fn::Sub", Some(text)) => self.substitute(text, &HashMap::new()),
+            ("Fn::Sub", None) if items.len() == 2 && items[0].as_str().is_some() => {
+                let variables = match &items[1].value {
+                    NodeValue::Mapping(entries) => entries.iter().map(|(name, _, value)| (name.to_string(), self.expression(value))).collect(),
+                    _ => HashMap::new(),
+                };
+                self.substitute(items[0].as_str().unwrap(), &variables)
+            },
+            ("Fn::Join", None) if items.len() == 2 => Expression::FunctionCall(BuiltInFunction {
+                name: String::from("join"),
+                args: vec![self.expression(&items[0]), self.expression(&items[1])],
+                expand_final: false,
+            }),
+            _ => Expression::FunctionCall(BuiltInFunction {
+                name: function.to_string(),
+                args: items.iter().map(|item| self.expression(item)).collect(),
+                expand_final: false,
+            }),
+        }
+    }
+
+    /// `Ref` of a resource, a parameter or a pseudo parameter such as `AWS::Region`
+    fn reference(&self, name: &str) -> Expression {
+        let steps = |names: &[&str]| names.iter().map(|name| TraversalStep::Attribute(name.to_string())).collect();
+
+        match self.resource_types.get(name) {
+            Some(resource_type) => Expression::Variable(Traversal::new(resource_type, steps(&[name]))),
+            None if self.parameters.iter().any(|parameter| parameter == name) => Expression::Variable(Traversal::new("var", steps(&[name]))),
+            None => Expression::Variable(Traversal::new(name, vec![])),
+        }
+    }
+
+    /// `GetAtt` of a resource's attribute, eg. `Queue.Arn`
+    fn attribute(&self, id: &str, attribute: Vec<&str>) -> Expression {
+        match self.reference(id) {
+            Expression::Variable(mut traversal) => {
+                traversal.steps.extend(attribute.into_iter().map(|name| TraversalStep::Attribute(name.to_string())));
+                Expression::Variable(traversal)
+            },
+            expression => expression,
+        }
+    }
+
+    /// `!Sub`, whose `${Name}` and `${Name.Attribute}` are references unless given in `variables`, and `${!Text}` is literal
+    fn substitute(&self, text: &str, variables: &HashMap<String, Expression>) -> Expression {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find("${") {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            literal.push_str(&rest[..start]);
+            let name = &rest[start + 2..end];
+
+            if let Some(text) = name.strip_prefix('!') {
+                literal.push_str(&format!("${{{}}}", text));
+            } else {
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                }
+                let expression = match (variables.get(name), name.split_once('.')) {
+                    (Some(expression), _) => expression.clone(),
+                    (None, Some((id, attribute))) => self.attribute(id, attribute.split('.').collect()),
+                    (None, None) => self.reference(name),
+                };
+                parts.push(TemplatePart::Interpolation(expression));
+            }
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+
+        if parts.is_empty() {
+            return Expression::Str(literal);
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Expression::Template(parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"AWSTemplateFormatVersion: "2010-09-09"
+Parameters:
+  Environment:
+    Type: String
+    Default: sandbox
+Resources:
+  Queue:
+    Type: AWS::SQS::Queue
+    Properties:
+      QueueName: !Sub "${Environment}-orders"
+      DelaySeconds: 90
+      FifoQueue: false
+  Mapping:
+    Type: AWS::Lambda::EventSourceMapping
+    DependsOn: Queue
+    Properties:
+      EventSourceArn: !GetAtt Queue.Arn
+      FunctionName: !Ref Function
+      Tags: !Join
+        - ","
+        - [!Ref "AWS::Region", !GetAtt [Queue, QueueName]]
+Outputs:
+  QueueUrl:
+    Value: !Ref Queue
+"#;
+
+    const JSON: &str = r#"{
+  "AWSTemplateFormatVersion": "2010-09-09",
+  "Parameters": {
+    "Environment": { "Type": "String", "Default": "sandbox" }
+  },
+  "Resources": {
+    "Queue": {
+      "Type": "AWS::SQS::Queue",
+      "Properties": {
+        "QueueName": { "Fn::Sub": "${Environment}-orders" },
+        "DelaySeconds": 90,
+        "FifoQueue": false
+      }
+    },
+    "Mapping": {
+      "Type": "AWS::Lambda::EventSourceMapping",
+      "DependsOn": "Queue",
+      "Properties": {
+        "EventSourceArn": { "Fn::GetAtt": ["Queue", "Arn"] },
+        "FunctionName": { "Ref": "Function" },
+        "Tags": { "Fn::Join": [",", [{ "Ref": "AWS::Region" }, { "Fn::GetAtt": "Queue.QueueName" }]] }
+      }
+    }
+  },
+  "Outputs": {
+    "QueueUrl": { "Value": { "Ref": "Queue" } }
+  }
+}"#;
+
+    fn value<'a>(blocks: &'a [TerraformBlock], address: &str, key: &str) -> &'a AttributeType {
+        let block = blocks.iter().find(|block| block.address() == address).unwrap();
+        &block.attributes().iter().find(|attr| attr.key == key).unwrap().value
+    }
+
+    #[test]
+    fn yaml_and_json_templates_agree() {
+        let (yaml, errors) = root(YAML);
+        assert_eq!(errors, vec![]);
+        let (json, errors) = root(JSON);
+        assert_eq!(errors, vec![]);
+
+        let addresses: Vec<String> = yaml.iter().map(|block| block.address()).collect();
+        assert_eq!(addresses, vec![
+            "variable.Environment",
+            "AWS::SQS::Queue.Queue",
+            "AWS::Lambda::EventSourceMapping.Mapping",
+            "output.QueueUrl",
+        ]);
+        assert_eq!(yaml, json);
+    }
+
+    #[test]
+    fn intrinsics_become_expressions() {
+        let (blocks, _) = root(YAML);
+
+        let mapping = "AWS::Lambda::EventSourceMapping.Mapping";
+        assert_eq!(value(&blocks, "AWS::SQS::Queue.Queue", "DelaySeconds"), &AttributeType::Num(90.0));
+        assert_eq!(value(&blocks, "variable.Environment", "default"), &AttributeType::Str(String::from("sandbox")));
+
+        let rendered = |address: &str, key: &str| match value(&blocks, address, key) {
+            AttributeType::Expression(expression) => expression.to_string(),
+            other => panic!("expected an expression, got {:?}", other),
+        };
+        assert_eq!(rendered("AWS::SQS::Queue.Queue", "QueueName"), r#""${var.Environment}-orders""#);
+        assert_eq!(rendered(mapping, "EventSourceArn"), "AWS::SQS::Queue.Queue.Arn");
+        assert_eq!(rendered(mapping, "FunctionName"), "Function");
+        assert_eq!(rendered(mapping, "Tags"), r#"join(",", [AWS::Region, AWS::SQS::Queue.Queue.QueueName])"#);
+        assert_eq!(rendered("output.QueueUrl", "value"), "AWS::SQS::Queue.Queue");
+
+        match value(&blocks, mapping, "EventSourceArn") {
+            AttributeType::Expression(expression) => {
+                let addresses: Vec<String> = expression.references().iter().map(|reference| reference.address()).collect();
+                assert_eq!(addresses, vec!["AWS::SQS::Queue.Queue"]);
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn spans_and_errors_are_located() {
+        let (blocks, _) = root(YAML);
+        assert_eq!(blocks[1].span().start.line, 7);
+        assert_eq!(blocks[1].attributes()[1].span.start.line, 11);
+        assert_eq!(blocks[1].attributes()[1].span.start.column, 7);
+
+        let (blocks, errors) = root("Resources:\n  Queue:\n    Properties: {}\n");
+        assert_eq!(blocks, vec![]);
+        assert_eq!(errors[0].expected, "a resource with a `Type`");
+        assert_eq!(errors[0].line, 3);
+
+        let (_, errors) = root("Resources:\n  Queue: [unclosed\n");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].expected.starts_with("valid YAML"));
+    }
+}
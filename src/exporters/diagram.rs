@@ -0,0 +1,346 @@
+//! Diagrams of an InfrastructureGraph, as Graphviz DOT or Mermaid, for a picture of the relationships in code review.
+//! - resources are clustered by their resource type, or by the VPC and subnet they are in
+//! - relationships are labelled with the IAM actions a permission grants, or their label
+//! - permissions are dashed, and references dotted
+//! - resources which fail a policy are coloured red, in DOT with the failed policies as their tooltip
+//! - addresses relationships point at which have no resource, such as a bucket named by an ARN, are drawn dashed
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::exporters::{ drawn, missing_nodes, violations };
+use crate::graph::{ Edge, EdgeKind, InfrastructureGraph, Node, label_names };
+use crate::policy_evaluator::PolicyResult;
+use crate::structs::attributes::{ Attribute, AttributeType };
+
+const VIOLATION_FILL: &str = "#f8d7da";
+const VIOLATION_STROKE: &str = "#c0392b";
+
+/// what resources are grouped by in a diagram
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ClusterBy {
+    #[default]
+    Type,
+    Network,
+}
+
+impl FromStr for ClusterBy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ClusterBy, String> {
+        match name {
+            "type" => Ok(ClusterBy::Type),
+            "network" => Ok(ClusterBy::Network),
+            _ => Err(format!("unknown clustering '{}', expected type or network", name)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Cluster<'a> {
+    label: String,
+    nodes: Vec<&'a Node>,
+    clusters: Vec<Cluster<'a>>,
+}
+
+impl<'a> Cluster<'a> {
+    fn new(label: &str) -> Cluster<'a> {
+        Cluster { label: label.to_string(), nodes: vec![], clusters: vec![] }
+    }
+}
+
+/// the cluster labelled, added after the others if there isn't one yet
+fn cluster<'a, 'b>(clusters: &'b mut Vec<Cluster<'a>>, label: &str) -> &'b mut Cluster<'a> {
+    let position = match clusters.iter().position(|cluster| cluster.label == label) {
+        Some(position) => position,
+        None => {
+            clusters.push(Cluster::new(label));
+            clusters.len() - 1
+        },
+    };
+    &mut clusters[position]
+}
+
+/// the values of the attributes named, at any depth, as written in the template
+fn find_values(attributes: &[Attribute], keys: &[&str], found: &mut Vec<String>) {
+    for attribute in attributes {
+        let matched = keys.contains(&attribute.key.as_str());
+        let mut values = vec![&attribute.value];
+        while let Some(value) = values.pop() {
+            match value {
+                AttributeType::Str(text) if matched => found.push(text.to_string()),
+                AttributeType::TemplatedString(template) if matched => found.push(template.to_string()),
+                AttributeType::Expression(expression) if matched => found.push(expression.to_string()),
+                AttributeType::Array(items) => values.extend(items.iter().rev()),
+                AttributeType::Block(nested) => find_values(nested, keys, found),
+                AttributeType::TFBlock(block) => find_values(block.attributes(), keys, found),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// the resource a value refers to, eg. `aws_subnet.private` for `${aws_subnet.private.id}`, or else the value itself, eg. `subnet-0a1b`
+fn referred(graph: &InfrastructureGraph, value: &str) -> String {
+    let reference = value.trim().trim_start_matches("${").trim_end_matches('}');
+    let segments: Vec<&str> = reference.split('.').collect();
+    (1..=segments.len()).rev()
+        .map(|length| segments[..length].join("."))
+        .find(|address| graph.node(address).is_some())
+        .unwrap_or_else(|| value.to_string())
+}
+
+/// the VPC and subnet a resource is in, when it names them
+fn network(graph: &InfrastructureGraph, node: &Node) -> (Option<String>, Option<String>) {
+    let first = |node: &Node, keys: &[&str]| {
+        let mut found = vec![];
+        find_values(&node.attributes, keys, &mut found);
+        found.first().map(|value| referred(graph, value))
+    };
+    let vpc_of = |node: &Node| first(node, &["vpc_id"]);
+
+    match node.node_type.as_str() {
+        "aws_vpc" => (Some(node.address.to_string()), None),
+        "aws_subnet" => (vpc_of(node), Some(node.address.to_string())),
+        _ => {
+            let subnet = first(node, &["subnet_id", "subnet_ids", "subnets"]);
+            let subnet_vpc = subnet.as_ref().and_then(|subnet| graph.node(subnet)).and_then(vpc_of);
+            (vpc_of(node).or(subnet_vpc), subnet)
+        },
+    }
+}
+
+/// the clusters of a graph, and the resources in none
+fn clusters(graph: &InfrastructureGraph, cluster_by: ClusterBy) -> (Vec<Cluster<'_>>, Vec<&Node>) {
+    let mut clusters = vec![];
+    let mut unclustered = vec![];
+
+    for node in graph.nodes() {
+        match cluster_by {
+            ClusterBy::Type => cluster(&mut clusters, &node.node_type).nodes.push(node),
+            ClusterBy::Network => match network(graph, node) {
+                (Some(vpc), Some(subnet)) => cluster(&mut cluster(&mut clusters, &vpc).clusters, &subnet).nodes.push(node),
+                (Some(vpc), None) => cluster(&mut clusters, &vpc).nodes.push(node),
+                (None, Some(subnet)) => cluster(&mut clusters, &subnet).nodes.push(node),
+                (None, None) => unclustered.push(node),
+            },
+        }
+    }
+
+    (clusters, unclustered)
+}
+
+fn edge_label(edge: &Edge) -> Vec<String> {
+    label_names(&edge.label)
+}
+
+fn node_label(node: &Node) -> Vec<String> {
+    match &node.name {
+        Some(name) => vec![node.node_type.to_string(), name.to_string()],
+        None => vec![node.node_type.to_string()],
+    }
+}
+
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+fn dot_cluster(cluster: &Cluster, count: &mut usize, violations: &HashMap<String, Vec<&str>>, indent: usize, dot: &mut String) {
+    let padding = "  ".repeat(indent);
+    dot.push_str(&format!("{}subgraph cluster_{} {{\n{}  label={};\n", padding, count, padding, dot_string(&cluster.label)));
+    *count += 1;
+    for node in &cluster.nodes {
+        dot.push_str(&dot_node(node, violations, indent + 1));
+    }
+    for nested in &cluster.clusters {
+        dot_cluster(nested, count, violations, indent + 1, dot);
+    }
+    dot.push_str(&format!("{}}}\n", padding));
+}
+
+fn dot_node(node: &Node, violations: &HashMap<String, Vec<&str>>, indent: usize) -> String {
+    let mut attributes = vec![format!("label={}", dot_string(&node_label(node).join("\n")))];
+    if let Some(policies) = violations.get(&node.address) {
+        attributes.push(format!("fillcolor=\"{}\", color=\"{}\"", VIOLATION_FILL, VIOLATION_STROKE));
+        attributes.push(format!("tooltip={}", dot_string(&policies.join("\n"))));
+    }
+    format!("{}{} [{}];\n", "  ".repeat(indent), dot_string(&node.address), attributes.join(", "))
+}
+
+pub fn render_dot(graph: &InfrastructureGraph, policy_results: &HashMap<String, Vec<PolicyResult>>, cluster_by: ClusterBy) -> String {
+    let violations = violations(graph, policy_results);
+    let (clusters, unclustered) = clusters(graph, cluster_by);
+
+    let mut dot = String::from("digraph infrastructure {\n  rankdir=LR;\n");
+    dot.push_str("  node [shape=box, style=\"rounded,filled\", fillcolor=\"#ffffff\", fontname=\"Helvetica\"];\n");
+    dot.push_str("  edge [fontname=\"Helvetica\", fontsize=10];\n");
+
+    let mut count = 0;
+    for cluster in &clusters {
+        dot_cluster(cluster, &mut count, &violations, 1, &mut dot);
+    }
+    for node in unclustered {
+        dot.push_str(&dot_node(node, &violations, 1));
+    }
+    for address in missing_nodes(graph) {
+        dot.push_str(&format!("  {} [style=\"rounded,dashed\"];\n", dot_string(address)));
+    }
+
+    for edge in graph.edges().iter().filter(|edge| drawn(edge)) {
+        let mut attributes = vec![];
+        let label = edge_label(edge);
+        if !label.is_empty() {
+            attributes.push(format!("label={}", dot_string(&label.join("\n"))));
+        }
+        match edge.kind {
+            EdgeKind::Relationship => (),
+            EdgeKind::Permission => attributes.push(String::from("style=dashed")),
+            EdgeKind::Reference => attributes.push(String::from("style=dotted")),
+        }
+        let attributes = match attributes.is_empty() {
+            true => String::new(),
+            false => format!(" [{}]", attributes.join(", ")),
+        };
+        dot.push_str(&format!("  {} -> {}{};\n", dot_string(&edge.source), dot_string(&edge.target), attributes));
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// text in a Mermaid label, which can't hold `"` and breaks lines with `<br/>`
+fn mermaid_string(lines: &[String]) -> String {
+    let escaped: Vec<String> = lines.iter()
+        .map(|line| line.replace('&', "#amp;").replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;"))
+        .collect();
+    format!("\"{}\"", escaped.join("<br/>"))
+}
+
+fn mermaid_cluster(cluster: &Cluster, count: &mut usize, ids: &HashMap<&str, String>, indent: usize, mermaid: &mut String) {
+    let padding = "    ".repeat(indent);
+    mermaid.push_str(&format!("{}subgraph cluster_{} [{}]\n", padding, count, mermaid_string(&[cluster.label.to_string()])));
+    *count += 1;
+    for node in &cluster.nodes {
+        mermaid.push_str(&format!("{}    {}[{}]\n", padding, ids[node.address.as_str()], mermaid_string(&node_label(node))));
+    }
+    for nested in &cluster.clusters {
+        mermaid_cluster(nested, count, ids, indent + 1, mermaid);
+    }
+    mermaid.push_str(&format!("{}end\n", padding));
+}
+
+pub fn render_mermaid(graph: &InfrastructureGraph, policy_results: &HashMap<String, Vec<PolicyResult>>, cluster_by: ClusterBy) -> String {
+    let violations = violations(graph, policy_results);
+    let (clusters, unclustered) = clusters(graph, cluster_by);
+    let missing = missing_nodes(graph);
+
+    // mermaid ids can't hold the characters addresses do, so nodes are numbered
+    let ids: HashMap<&str, String> = graph.nodes().iter().map(|node| node.address.as_str())
+        .chain(missing.iter().copied())
+        .enumerate()
+        .map(|(position, address)| (address, format!("n{}", position)))
+        .collect();
+
+    let mut mermaid = String::from("flowchart LR\n");
+    let mut count = 0;
+    for cluster in &clusters {
+        mermaid_cluster(cluster, &mut count, &ids, 1, &mut mermaid);
+    }
+    for node in unclustered {
+        mermaid.push_str(&format!("    {}[{}]\n", ids[node.address.as_str()], mermaid_string(&node_label(node))));
+    }
+    for address in &missing {
+        mermaid.push_str(&format!("    {}[{}]\n", ids[address], mermaid_string(&[address.to_string()])));
+    }
+
+    for edge in graph.edges().iter().filter(|edge| drawn(edge)) {
+        let arrow = match edge.kind {
+            EdgeKind::Relationship => "-->",
+            EdgeKind::Permission | EdgeKind::Reference => "-.->",
+        };
+        let label = match edge_label(edge) {
+            label if label.is_empty() => String::new(),
+            label => format!("|{}|", mermaid_string(&label)),
+        };
+        mermaid.push_str(&format!("    {} {}{} {}\n", ids[edge.source.as_str()], arrow, label, ids[edge.target.as_str()]));
+    }
+
+    if !missing.is_empty() {
+        mermaid.push_str("    classDef missing stroke-dasharray: 5 5\n");
+        let missing_ids: Vec<&str> = missing.iter().map(|address| ids[address].as_str()).collect();
+        mermaid.push_str(&format!("    class {} missing\n", missing_ids.join(",")));
+    }
+    if !violations.is_empty() {
+        mermaid.push_str(&format!("    classDef violation fill:{},stroke:{}\n", VIOLATION_FILL, VIOLATION_STROKE));
+        let violating: Vec<&str> = graph.nodes().iter()
+            .filter(|node| violations.contains_key(&node.address))
+            .map(|node| ids[node.address.as_str()].as_str())
+            .collect();
+        mermaid.push_str(&format!("    class {} violation\n", violating.join(",")));
+    }
+
+    mermaid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::fixtures::{ edge, graph };
+
+    fn example() -> InfrastructureGraph {
+        graph(r#"{"resource": {
+            "aws_vpc": {"main": {"cidr_block": "10.0.0.0/16"}},
+            "aws_subnet": {"private": {"vpc_id": "${aws_vpc.main.id}"}},
+            "aws_lambda_function": {"worker": {"vpc_config": {"subnet_ids": ["${aws_subnet.private.id}"]}}},
+            "aws_sqs_queue": {"queue": {"name": "orders \"eu\""}}
+        }}"#, &[], vec![
+            edge(EdgeKind::Permission, "aws_lambda_function.worker", "aws_sqs_queue.queue", "['sqs:ReceiveMessage','sqs:DeleteMessage']", "aws_iam_policy.policy"),
+            edge(EdgeKind::Relationship, "aws_sqs_queue.queue", "aws_s3_bucket.archive", "", "aws_sqs_queue.queue"),
+        ])
+    }
+
+    fn failing() -> HashMap<String, Vec<PolicyResult>> {
+        crate::exporters::fixtures::failing(&[("aws_sqs_queue_queue", &["sqs_encrypted"])])
+    }
+
+    #[test]
+    fn resources_are_clustered_by_network() {
+        let graph = example();
+        let (clusters, unclustered) = clusters(&graph, ClusterBy::Network);
+
+        let addresses = |nodes: &Vec<&Node>| nodes.iter().map(|node| node.address.to_string()).collect::<Vec<String>>();
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].label, "aws_vpc.main");
+        assert_eq!(addresses(&clusters[0].nodes), vec!["aws_vpc.main"]);
+        assert_eq!(clusters[0].clusters[0].label, "aws_subnet.private");
+        assert_eq!(addresses(&clusters[0].clusters[0].nodes), vec!["aws_subnet.private", "aws_lambda_function.worker"]);
+        assert_eq!(addresses(&unclustered), vec!["aws_sqs_queue.queue"]);
+
+        let (by_type, unclustered) = super::clusters(&graph, ClusterBy::Type);
+        assert_eq!(by_type.len(), 4);
+        assert!(unclustered.is_empty());
+    }
+
+    #[test]
+    fn dot_labels_actions_and_colours_violations() {
+        let dot = render_dot(&example(), &failing(), ClusterBy::Type);
+
+        assert!(dot.starts_with("digraph infrastructure {\n"));
+        assert!(dot.contains("  subgraph cluster_3 {\n    label=\"aws_sqs_queue\";\n    \"aws_sqs_queue.queue\" [label=\"aws_sqs_queue\\nqueue\", fillcolor=\"#f8d7da\", color=\"#c0392b\", tooltip=\"sqs_encrypted\"];\n  }\n"));
+        assert!(dot.contains("  \"aws_s3_bucket.archive\" [style=\"rounded,dashed\"];\n"));
+        assert!(dot.contains("  \"aws_lambda_function.worker\" -> \"aws_sqs_queue.queue\" [label=\"sqs:ReceiveMessage\\nsqs:DeleteMessage\", style=dashed];\n"));
+        assert!(dot.contains("  \"aws_sqs_queue.queue\" -> \"aws_s3_bucket.archive\";\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn mermaid_labels_actions_and_colours_violations() {
+        let mermaid = render_mermaid(&example(), &failing(), ClusterBy::Network);
+
+        assert!(mermaid.starts_with("flowchart LR\n    subgraph cluster_0 [\"aws_vpc.main\"]\n        n0[\"aws_vpc<br/>main\"]\n        subgraph cluster_1 [\"aws_subnet.private\"]\n"));
+        assert!(mermaid.contains("    n2 -.->|\"sqs:ReceiveMessage<br/>sqs:DeleteMessage\"| n3\n"));
+        assert!(mermaid.contains("    n3 --> n4\n"));
+        assert!(mermaid.contains("    class n4 missing\n"));
+        assert!(mermaid.contains("    class n3 violation\n"));
+    }
+}
@@ -0,0 +1,82 @@
+//! GEXF 1.2, for Gephi. Nodes and edges carry the same typed attributes as GraphML, declared at the top of the graph,
+//! and are labelled for display: nodes by their address, edges by the IAM actions a permission grants or their label.
+
+use std::collections::HashMap;
+
+use crate::exporters::{ drawn, missing_nodes, violations, xml_escape };
+use crate::exporters::graphml::{ EDGE_KEYS, NODE_KEYS, edge_values, node_values };
+use crate::graph::InfrastructureGraph;
+use crate::policy_evaluator::PolicyResult;
+
+/// the GEXF type of a GraphML key type
+fn gexf_type(key_type: &str) -> &str {
+    match key_type {
+        "int" => "integer",
+        other => other,
+    }
+}
+
+fn attvalues(values: &[(&str, String)]) -> String {
+    let attvalues: String = values.iter()
+        .map(|(key, value)| format!("<attvalue for=\"{}\" value=\"{}\"/>", key, xml_escape(value)))
+        .collect();
+    format!("<attvalues>{}</attvalues>", attvalues)
+}
+
+pub fn render(graph: &InfrastructureGraph, policy_results: &HashMap<String, Vec<PolicyResult>>) -> String {
+    let violations = violations(graph, policy_results);
+
+    let mut gexf = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    gexf.push_str("<gexf xmlns=\"http://www.gexf.net/1.2draft\" version=\"1.2\">\n");
+    gexf.push_str("  <meta><creator>infrastructure-graph</creator></meta>\n");
+    gexf.push_str("  <graph mode=\"static\" defaultedgetype=\"directed\">\n");
+    for (class, keys) in &[("node", &NODE_KEYS[..]), ("edge", &EDGE_KEYS[..])] {
+        gexf.push_str(&format!("    <attributes class=\"{}\">\n", class));
+        for (name, key_type) in keys.iter() {
+            let default = match *name {
+                "violations" => "<default>0</default>",
+                _ => "",
+            };
+            gexf.push_str(&format!("      <attribute id=\"{}\" title=\"{}\" type=\"{}\">{}</attribute>\n", name, name, gexf_type(key_type), default));
+        }
+        gexf.push_str("    </attributes>\n");
+    }
+
+    gexf.push_str("    <nodes>\n");
+    for node in graph.nodes() {
+        gexf.push_str(&format!("      <node id=\"{}\" label=\"{}\">{}</node>\n",
+            xml_escape(&node.address), xml_escape(&node.address), attvalues(&node_values(node, &violations))));
+    }
+    for address in missing_nodes(graph) {
+        gexf.push_str(&format!("      <node id=\"{}\" label=\"{}\"/>\n", xml_escape(address), xml_escape(address)));
+    }
+    gexf.push_str("    </nodes>\n    <edges>\n");
+    for (position, edge) in graph.edges().iter().filter(|edge| drawn(edge)).enumerate() {
+        let values = edge_values(edge);
+        gexf.push_str(&format!("      <edge id=\"{}\" source=\"{}\" target=\"{}\" label=\"{}\">{}</edge>\n",
+            position, xml_escape(&edge.source), xml_escape(&edge.target), xml_escape(&values[0].1), attvalues(&values)));
+    }
+    gexf.push_str("    </edges>\n  </graph>\n</gexf>\n");
+
+    gexf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::graphml::tests::{ example, failing };
+
+    #[test]
+    fn gexf_declares_typed_attributes() {
+        let gexf = render(&example(), &failing());
+
+        assert!(gexf.contains("    <attributes class=\"node\">\n      <attribute id=\"type\" title=\"type\" type=\"string\"></attribute>\n"));
+        assert!(gexf.contains("      <attribute id=\"violations\" title=\"violations\" type=\"integer\"><default>0</default></attribute>\n"));
+        assert!(gexf.contains("      <node id=\"aws_sqs_queue.queue\" label=\"aws_sqs_queue.queue\"><attvalues><attvalue for=\"type\" value=\"aws_sqs_queue\"/>\
+            <attvalue for=\"block_type\" value=\"resource\"/><attvalue for=\"name\" value=\"queue\"/><attvalue for=\"file\" value=\"queues &amp; topics.tf\"/>\
+            <attvalue for=\"violations\" value=\"2\"/></attvalues></node>\n"));
+        assert!(gexf.contains("      <edge id=\"0\" source=\"aws_lambda_function.worker\" target=\"aws_sqs_queue.queue\" label=\"sqs:ReceiveMessage, sqs:DeleteMessage\">"));
+        assert!(gexf.contains("      <edge id=\"1\" source=\"aws_lambda_function.worker\" target=\"arn:aws:s3:::&lt;archive&gt;\" label=\"\">"));
+        assert!(gexf.ends_with("    </edges>\n  </graph>\n</gexf>\n"));
+    }
+}
@@ -0,0 +1,117 @@
+//! GraphML, for yEd, NetworkX and the like. Nodes and edges carry typed data keys, declared at the top of the document:
+//! - nodes: `type`, `block_type`, `name`, `file` and `violations`, the number of policies the resource fails
+//! - edges: `label`, the IAM actions a permission grants or the relationship's label, and `kind`
+
+use std::collections::HashMap;
+
+use crate::exporters::{ drawn, missing_nodes, violations, xml_escape };
+use crate::graph::{ Edge, InfrastructureGraph, Node, kind_name, label_names };
+use crate::policy_evaluator::PolicyResult;
+
+/// the data keys of nodes and edges: their name, the element they're for and their type
+pub(crate) const NODE_KEYS: [(&str, &str); 5] = [
+    ("type", "string"),
+    ("block_type", "string"),
+    ("name", "string"),
+    ("file", "string"),
+    ("violations", "int"),
+];
+pub(crate) const EDGE_KEYS: [(&str, &str); 2] = [
+    ("label", "string"),
+    ("kind", "string"),
+];
+
+/// the values of a node's data keys, those it has none for are left out
+pub(crate) fn node_values(node: &Node, violations: &HashMap<String, Vec<&str>>) -> Vec<(&'static str, String)> {
+    let mut values = vec![
+        ("type", node.node_type.to_string()),
+        ("block_type", node.block_type.to_string()),
+    ];
+    if let Some(name) = &node.name {
+        values.push(("name", name.to_string()));
+    }
+    if let Some(file) = &node.span.file {
+        values.push(("file", file.to_string()));
+    }
+    values.push(("violations", violations.get(&node.address).map_or(0, |policies| policies.len()).to_string()));
+    values
+}
+
+pub(crate) fn edge_values(edge: &Edge) -> Vec<(&'static str, String)> {
+    vec![
+        ("label", label_names(&edge.label).join(", ")),
+        ("kind", kind_name(edge.kind).to_string()),
+    ]
+}
+
+fn data(values: &[(&str, String)]) -> String {
+    values.iter()
+        .map(|(key, value)| format!("<data key=\"{}\">{}</data>", key, xml_escape(value)))
+        .collect()
+}
+
+pub fn render(graph: &InfrastructureGraph, policy_results: &HashMap<String, Vec<PolicyResult>>) -> String {
+    let violations = violations(graph, policy_results);
+
+    let mut graphml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    graphml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+        xsi:schemaLocation=\"http://graphml.graphdrawing.org/xmlns http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd\">\n");
+    for (element, keys) in &[("node", &NODE_KEYS[..]), ("edge", &EDGE_KEYS[..])] {
+        for (name, key_type) in keys.iter() {
+            let default = match *name {
+                "violations" => "<default>0</default>",
+                _ => "",
+            };
+            graphml.push_str(&format!("  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\">{}</key>\n", name, element, name, key_type, default));
+        }
+    }
+
+    graphml.push_str("  <graph id=\"infrastructure\" edgedefault=\"directed\">\n");
+    for node in graph.nodes() {
+        graphml.push_str(&format!("    <node id=\"{}\">{}</node>\n", xml_escape(&node.address), data(&node_values(node, &violations))));
+    }
+    for address in missing_nodes(graph) {
+        graphml.push_str(&format!("    <node id=\"{}\"/>\n", xml_escape(address)));
+    }
+    for (position, edge) in graph.edges().iter().filter(|edge| drawn(edge)).enumerate() {
+        graphml.push_str(&format!("    <edge id=\"e{}\" source=\"{}\" target=\"{}\">{}</edge>\n",
+            position, xml_escape(&edge.source), xml_escape(&edge.target), data(&edge_values(edge))));
+    }
+    graphml.push_str("  </graph>\n</graphml>\n");
+
+    graphml
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::exporters::fixtures::{ edge, graph };
+    use crate::graph::EdgeKind;
+
+    pub(crate) fn example() -> InfrastructureGraph {
+        graph(r#"{"resource": {"aws_sqs_queue": {"queue": {}}, "aws_lambda_function": {"worker": {}}}}"#, &[("aws_sqs_queue.queue", "queues & topics.tf")], vec![
+            edge(EdgeKind::Permission, "aws_lambda_function.worker", "aws_sqs_queue.queue", "['sqs:ReceiveMessage','sqs:DeleteMessage']", "aws_iam_policy.policy"),
+            edge(EdgeKind::Reference, "aws_lambda_function.worker", "arn:aws:s3:::<archive>", "", "aws_lambda_function.worker"),
+        ])
+    }
+
+    pub(crate) fn failing() -> HashMap<String, Vec<PolicyResult>> {
+        crate::exporters::fixtures::failing(&[("aws_sqs_queue_queue", &["sqs_encrypted", "sqs_dead_letter_queue"])])
+    }
+
+    #[test]
+    fn graphml_declares_typed_keys() {
+        let graphml = render(&example(), &failing());
+
+        assert!(graphml.contains("  <key id=\"type\" for=\"node\" attr.name=\"type\" attr.type=\"string\"></key>\n"));
+        assert!(graphml.contains("  <key id=\"violations\" for=\"node\" attr.name=\"violations\" attr.type=\"int\"><default>0</default></key>\n"));
+        assert!(graphml.contains("  <key id=\"kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"></key>\n"));
+        assert!(graphml.contains("    <node id=\"aws_sqs_queue.queue\"><data key=\"type\">aws_sqs_queue</data><data key=\"block_type\">resource</data>\
+            <data key=\"name\">queue</data><data key=\"file\">queues &amp; topics.tf</data><data key=\"violations\">2</data></node>\n"));
+        assert!(graphml.contains("<data key=\"violations\">0</data></node>\n"));
+        assert!(graphml.contains("    <node id=\"arn:aws:s3:::&lt;archive&gt;\"/>\n"));
+        assert!(graphml.contains("    <edge id=\"e0\" source=\"aws_lambda_function.worker\" target=\"aws_sqs_queue.queue\">\
+            <data key=\"label\">sqs:ReceiveMessage, sqs:DeleteMessage</data><data key=\"kind\">permission</data></edge>\n"));
+        assert!(graphml.ends_with("  </graph>\n</graphml>\n"));
+    }
+}
@@ -0,0 +1,84 @@
+//! The graph.json document the front end reads, rendered from an InfrastructureGraph and serialised by serde_json.
+//! Its layout is described in docs/graph_json.md, and versioned by `schema_version`: a change which would break a reader
+//! of the previous version increases it.
+
+use std::collections::{ BTreeMap, HashMap };
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::graph::{ Edge, EdgeKind, InfrastructureGraph, Provenance };
+use crate::policy_evaluator::PolicyResult;
+use crate::visitors::json_visitor::JsonVisitor;
+
+/// the version of the layout of graph.json. Version 1 was written before the document carried a version.
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Serialize)]
+pub struct GraphDocument<'a> {
+    pub schema_version: u32,
+    pub resources: Vec<Value>,
+    pub relationships: Vec<RelationshipDocument<'a>>,
+    /// the failed policy results of each resource, by resource id
+    pub policy_results: BTreeMap<&'a String, &'a Vec<PolicyResult>>,
+}
+
+#[derive(Serialize)]
+pub struct RelationshipDocument<'a> {
+    #[serde(rename = "in")]
+    pub source: &'a str,
+    #[serde(rename = "out")]
+    pub target: &'a str,
+    pub label: &'a str,
+    pub kind: EdgeKind,
+    pub provenance: &'a Provenance,
+}
+
+impl<'a> From<&'a Edge> for RelationshipDocument<'a> {
+    fn from(edge: &'a Edge) -> RelationshipDocument<'a> {
+        RelationshipDocument {
+            source: &edge.source,
+            target: &edge.target,
+            label: &edge.label,
+            kind: edge.kind,
+            provenance: &edge.provenance,
+        }
+    }
+}
+
+pub fn document<'a>(graph: &'a InfrastructureGraph, policy_results: &'a HashMap<String, Vec<PolicyResult>>) -> GraphDocument<'a> {
+    let visitor = JsonVisitor{};
+
+    GraphDocument {
+        schema_version: SCHEMA_VERSION,
+        resources: graph.nodes().iter().map(|node| visitor.visit_node(node)).collect(),
+        relationships: graph.edges().iter().map(RelationshipDocument::from).collect(),
+        policy_results: policy_results.iter().collect(),
+    }
+}
+
+pub fn render(graph: &InfrastructureGraph, policy_results: &HashMap<String, Vec<PolicyResult>>) -> String {
+    serde_json::to_string(&document(graph, policy_results)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_versioned_and_valid_json() {
+        let data = r#"{"resource": {"aws_sqs_queue": {"queue": {"name": "orders \"eu\"", "delay_seconds": 30, "fifo_queue": true}}}}"#;
+        let (resources, _) = crate::terraform_json::root(data);
+        let graph = InfrastructureGraph::from_blocks(&resources);
+
+        let rendered = render(&graph, &HashMap::new());
+        let read: Value = serde_json::from_str(&rendered).unwrap();
+
+        assert_eq!(read["schema_version"], serde_json::json!(SCHEMA_VERSION));
+        assert_eq!(read["resources"][0]["body"], serde_json::json!({
+            "name": "orders \"eu\"",
+            "delay_seconds": 30,
+            "fifo_queue": true,
+        }));
+        assert_eq!(read["relationships"], serde_json::json!([]));
+    }
+}
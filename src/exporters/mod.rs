@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::graph::{ Edge, InfrastructureGraph };
+use crate::policy_evaluator::PolicyResult;
+
+pub mod diagram;
+pub mod gexf;
+pub mod graphml;
+pub mod json;
+pub mod neo4j;
+pub mod rdf;
+pub mod sparql;
+
+/// the formats a graph can be written in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Json,
+    Turtle,
+    NTriples,
+    NQuads,
+    /// SPARQL updates loading what has changed since the previous load
+    Sparql,
+    Cypher,
+    /// the node and relationship files of a neo4j-admin import
+    Neo4jCsv,
+    Dot,
+    Mermaid,
+    GraphMl,
+    Gexf,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "json" => Ok(Format::Json),
+            "turtle" | "ttl" => Ok(Format::Turtle),
+            "ntriples" | "nt" => Ok(Format::NTriples),
+            "nquads" | "nq" => Ok(Format::NQuads),
+            "sparql" => Ok(Format::Sparql),
+            "cypher" => Ok(Format::Cypher),
+            "neo4j-csv" => Ok(Format::Neo4jCsv),
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            "graphml" => Ok(Format::GraphMl),
+            "gexf" => Ok(Format::Gexf),
+            _ => Err(format!("unknown format '{}', expected one of json, turtle, ntriples, nquads, sparql, cypher, neo4j-csv, dot, mermaid, graphml or gexf", name)),
+        }
+    }
+}
+
+impl Format {
+    /// where a graph is written by default, eg. `graph.ttl`
+    pub fn default_output(&self) -> &'static str {
+        match self {
+            Format::Json => "graph.json",
+            Format::Turtle => "graph.ttl",
+            Format::NTriples => "graph.nt",
+            Format::NQuads => "graph.nq",
+            Format::Sparql => "graph.ru",
+            Format::Cypher => "graph.cypher",
+            Format::Neo4jCsv => "graph-neo4j",
+            Format::Dot => "graph.dot",
+            Format::Mermaid => "graph.mmd",
+            Format::GraphMl => "graph.graphml",
+            Format::Gexf => "graph.gexf",
+        }
+    }
+}
+
+/// what the formats with named graphs, and diagrams, need besides the graph
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+    /// load every triple into the named graph of this environment, rather than one per template
+    pub environment: Option<String>,
+    /// the quads loaded before, which SPARQL updates are made from, without them each named graph is cleared and loaded again
+    pub previous: Option<Vec<rdf::Quad>>,
+    /// what the resources of a diagram are grouped by
+    pub cluster_by: diagram::ClusterBy,
+}
+
+/// whether an edge names a resource at both ends, those found in a statement granting access to `*` don't, and are left out
+pub(crate) fn drawn(edge: &Edge) -> bool {
+    !edge.source.is_empty() && !edge.target.is_empty()
+}
+
+/// the addresses edges point at which have no node, such as a bucket named by an ARN
+pub(crate) fn missing_nodes(graph: &InfrastructureGraph) -> Vec<&str> {
+    let mut missing = vec![];
+    for edge in graph.edges().iter().filter(|edge| drawn(edge)) {
+        for address in &[edge.source.as_str(), edge.target.as_str()] {
+            if graph.node(address).is_none() && !missing.contains(address) {
+                missing.push(*address);
+            }
+        }
+    }
+    missing
+}
+
+/// the policies each resource fails, by address
+pub(crate) fn violations<'a>(graph: &InfrastructureGraph, policy_results: &'a HashMap<String, Vec<PolicyResult>>) -> HashMap<String, Vec<&'a str>> {
+    graph.nodes().iter()
+        .filter_map(|node| policy_results.get(&node.id()).map(|results| {
+            (node.address.to_string(), results.iter().map(|result| result.policy_id()).collect())
+        }))
+        .collect()
+}
+
+/// text escaped for an XML attribute or element, without the control characters XML can't hold
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // characters XML 1.0 can't hold at all
+            c if (c as u32) < 0x20 && c != '\n' && c != '\r' && c != '\t' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// a graph as written: a file, or for formats of more than one file, the files of a directory by name
+#[derive(Debug, PartialEq)]
+pub enum Rendered {
+    File(String),
+    Directory(Vec<(&'static str, String)>),
+}
+
+pub fn render(format: Format, graph: &InfrastructureGraph, policy_results: &HashMap<String, Vec<PolicyResult>>, options: &ExportOptions) -> Rendered {
+    let environment = options.environment.as_deref();
+
+    let file = match format {
+        Format::Json => json::render(graph, policy_results),
+        Format::Turtle => rdf::render_turtle(graph),
+        Format::NTriples => rdf::render_ntriples(graph),
+        Format::NQuads => rdf::render_nquads(graph, environment),
+        Format::Sparql => sparql::render(graph, environment, options.previous.as_deref()),
+        Format::Cypher => neo4j::render_cypher(graph),
+        Format::Dot => diagram::render_dot(graph, policy_results, options.cluster_by),
+        Format::Mermaid => diagram::render_mermaid(graph, policy_results, options.cluster_by),
+        Format::GraphMl => graphml::render(graph, policy_results),
+        Format::Gexf => gexf::render(graph, policy_results),
+        Format::Neo4jCsv => return Rendered::Directory(vec![
+            ("nodes.csv", neo4j::render_nodes_csv(graph)),
+            ("relationships.csv", neo4j::render_relationships_csv(graph)),
+        ]),
+    };
+    Rendered::File(file)
+}
+
+/// the graphs and policy results the exporters' tests are written against
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+    use crate::graph::{ EdgeKind, Provenance };
+    use crate::structs::span::Span;
+
+    /// a graph of the resources of a `.tf.json` document, those named in `files` read from that file, with the given edges
+    pub(crate) fn graph(resources: &str, files: &[(&str, &str)], edges: Vec<Edge>) -> InfrastructureGraph {
+        let (blocks, errors) = crate::terraform_json::root(resources);
+        assert_eq!(errors, vec![]);
+        let mut graph = InfrastructureGraph::from_blocks(&blocks);
+
+        for (address, file) in files {
+            let mut node = graph.node(address).unwrap().clone();
+            node.span = Span { file: Some(file.to_string()), ..Span::default() };
+            graph.add_node(node);
+        }
+        edges.into_iter().for_each(|edge| graph.add_edge(edge));
+        graph
+    }
+
+    /// an edge from `source` to `target`, found in the block `found_in`
+    pub(crate) fn edge(kind: EdgeKind, source: &str, target: &str, label: &str, found_in: &str) -> Edge {
+        Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            label: label.to_string(),
+            kind,
+            provenance: Provenance::new(found_in, None),
+        }
+    }
+
+    /// the policies each resource fails, by resource id
+    pub(crate) fn failing(failures: &[(&str, &[&str])]) -> HashMap<String, Vec<PolicyResult>> {
+        failures.iter()
+            .map(|(id, policies)| {
+                (id.to_string(), policies.iter().map(|policy| PolicyResult::new(vec![], policy.to_string(), false)).collect())
+            })
+            .collect()
+    }
+}
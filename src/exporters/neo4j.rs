@@ -0,0 +1,378 @@
+//! Property graph renderings of an InfrastructureGraph for Neo4j, described in docs/graph_databases.md:
+//! - a Cypher script which `MERGE`s every resource and relationship, so running it again updates the graph rather than duplicating it
+//! - the node and relationship CSV files read by `neo4j-admin database import`
+//!
+//! Every resource is a `Resource` node keyed by its address, also labelled with its resource type. Attributes are properties,
+//! nested blocks flattened into dotted names, eg. `ingress.from_port`. Each relationship's type is its label, one for each
+//! IAM action of a permission, or its kind, eg. `RELATIONSHIP`, when it has none.
+
+use std::collections::HashSet;
+
+use crate::exporters::{ drawn, missing_nodes };
+use crate::graph::{ Edge, InfrastructureGraph, Node, kind_name, label_names };
+use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::json::JsonValue;
+use crate::visitors::json_visitor::JsonVisitor;
+use crate::visitors::visitor::Visitor;
+
+/// the properties every node has, an attribute of the same name is written beneath `attributes.`
+const NODE_PROPERTIES: [&str; 5] = ["address", "block_type", "block_name", "module", "planned_actions"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Property {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    List(Vec<Property>),
+}
+
+impl Property {
+    /// the type of a neo4j-admin import column holding this value
+    fn column_type(&self) -> String {
+        match self {
+            Property::Str(_) => String::from("string"),
+            Property::Int(_) => String::from("long"),
+            Property::Float(_) => String::from("double"),
+            Property::Bool(_) => String::from("boolean"),
+            Property::List(values) => format!("{}[]", values.first().map(Property::column_type).unwrap_or_else(|| String::from("string"))),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Property::Str(text) => text.to_string(),
+            Property::Int(number) => number.to_string(),
+            Property::Float(number) => format!("{:?}", number),
+            Property::Bool(boolean) => boolean.to_string(),
+            Property::List(values) => values.iter().map(Property::text).collect::<Vec<String>>().join(";"),
+        }
+    }
+}
+
+fn number(number: f64) -> Property {
+    if number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+        Property::Int(number as i64)
+    } else if number.is_finite() {
+        Property::Float(number)
+    } else {
+        Property::Str(number.to_string())
+    }
+}
+
+/// an attribute's values as properties: nested blocks beneath its name, arrays of values as a list, and other arrays by index
+fn flatten(name: &str, value: &AttributeType, properties: &mut Vec<(String, Property)>) {
+    let nested = |attributes: &Vec<Attribute>, properties: &mut Vec<(String, Property)>| {
+        flatten_attributes(attributes, |key| format!("{}.{}", name, key), properties)
+    };
+
+    let property = match value {
+        AttributeType::Str(text) => Property::Str(text.to_string()),
+        AttributeType::TemplatedString(template) => Property::Str(template.to_string()),
+        AttributeType::Expression(expression) => Property::Str(expression.to_string()),
+        AttributeType::Boolean(boolean) => Property::Bool(*boolean),
+        AttributeType::Num(value) => number(*value),
+        AttributeType::Json(JsonValue::Str(text)) => Property::Str(text.to_string()),
+        AttributeType::Json(json) => Property::Str(JsonVisitor{}.visit_json(json).to_string()),
+        AttributeType::Block(attributes) => return nested(attributes, properties),
+        AttributeType::TFBlock(block) => return nested(block.attributes(), properties),
+        AttributeType::Array(values) => {
+            let mut items = vec![];
+            values.iter().for_each(|value| flatten(name, value, &mut items));
+            let all_values = items.len() == values.len()
+                && items.iter().all(|(key, item)| key == name && !matches!(item, Property::List(_)));
+
+            if !all_values {
+                return values.iter().enumerate()
+                    .for_each(|(position, value)| flatten(&format!("{}.{}", name, position), value, properties));
+            }
+            if items.is_empty() {
+                return;
+            }
+            // neo4j lists hold values of one type
+            let mut items: Vec<Property> = items.into_iter().map(|(_, item)| item).collect();
+            if items.iter().any(|item| item.column_type() != items[0].column_type()) {
+                items = items.iter().map(|item| Property::Str(item.text())).collect();
+            }
+            Property::List(items)
+        },
+    };
+
+    properties.push((name.to_string(), property));
+}
+
+/// attributes as properties, named by `name`, and a block repeated, eg. `ingress { ... }` twice, by the index of each, eg. `ingress.1`
+fn flatten_attributes(attributes: &[Attribute], name: impl Fn(&str) -> String, properties: &mut Vec<(String, Property)>) {
+    let mut seen: Vec<(&str, usize)> = vec![];
+    for attribute in attributes {
+        let repeats = attributes.iter().filter(|other| other.key == attribute.key).count();
+        let key = match repeats {
+            1 => attribute.key.to_string(),
+            _ => {
+                let position = match seen.iter_mut().find(|(key, _)| *key == attribute.key) {
+                    Some((_, count)) => { *count += 1; *count },
+                    None => { seen.push((&attribute.key, 0)); 0 },
+                };
+                format!("{}.{}", attribute.key, position)
+            },
+        };
+        flatten(&name(&key), &attribute.value, properties);
+    }
+}
+
+/// the properties of a node, those every node has followed by its attributes
+pub fn properties(node: &Node) -> Vec<(String, Property)> {
+    let mut properties = vec![
+        (String::from("address"), Property::Str(node.address.to_string())),
+        (String::from("block_type"), Property::Str(node.block_type.to_string())),
+    ];
+    if let Some(name) = &node.name {
+        properties.push((String::from("block_name"), Property::Str(name.to_string())));
+    }
+    if let Some(module) = &node.module {
+        properties.push((String::from("module"), Property::Str(module.to_string())));
+    }
+    if !node.planned_actions.is_empty() {
+        let actions = node.planned_actions.iter().map(|action| Property::Str(action.to_string())).collect();
+        properties.push((String::from("planned_actions"), Property::List(actions)));
+    }
+
+    let name = |key: &str| match NODE_PROPERTIES.contains(&key) {
+        true => format!("attributes.{}", key),
+        false => key.to_string(),
+    };
+    flatten_attributes(&node.attributes, name, &mut properties);
+
+    properties
+}
+
+/// the types of an edge's relationships, none when it names no resource at one end
+pub fn relationship_types(edge: &Edge) -> Vec<String> {
+    if !drawn(edge) {
+        return vec![];
+    }
+
+    match label_names(&edge.label) {
+        names if names.is_empty() => vec![kind_name(edge.kind).to_uppercase()],
+        names => names,
+    }
+}
+
+fn relationship_properties(edge: &Edge) -> Vec<(String, Property)> {
+    let mut properties = vec![
+        (String::from("kind"), Property::Str(kind_name(edge.kind).to_string())),
+        (String::from("label"), Property::Str(edge.label.to_string())),
+        (String::from("found_in"), Property::Str(edge.provenance.block.to_string())),
+    ];
+    if let Some(attribute) = &edge.provenance.attribute {
+        properties.push((String::from("found_in_attribute"), Property::Str(attribute.to_string())));
+    }
+    properties
+}
+
+/// a label, relationship type or property name, quoted in backticks unless it is a plain identifier
+fn cypher_name(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match plain {
+        true => name.to_string(),
+        false => format!("`{}`", name.replace('`', "``")),
+    }
+}
+
+fn cypher_value(property: &Property) -> String {
+    match property {
+        Property::Str(text) => {
+            let mut escaped = String::from("'");
+            for c in text.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\'' => escaped.push_str("\\'"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    '\t' => escaped.push_str("\\t"),
+                    c => escaped.push(c),
+                }
+            }
+            escaped.push('\'');
+            escaped
+        },
+        Property::List(values) => format!("[{}]", values.iter().map(cypher_value).collect::<Vec<String>>().join(", ")),
+        other => other.text(),
+    }
+}
+
+fn cypher_map(properties: &[(String, Property)]) -> String {
+    let entries: Vec<String> = properties.iter()
+        .map(|(name, value)| format!("{}: {}", cypher_name(name), cypher_value(value)))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+fn merge_resource(variable: &str, address: &str) -> String {
+    format!("MERGE ({}:Resource {{address: {}}})", variable, cypher_value(&Property::Str(address.to_string())))
+}
+
+/// A Cypher script of one statement a line: every node is merged by address and has its properties replaced,
+/// then every relationship is merged between its nodes
+pub fn render_cypher(graph: &InfrastructureGraph) -> String {
+    let mut statements = vec![
+        String::from("CREATE CONSTRAINT resource_address IF NOT EXISTS FOR (resource:Resource) REQUIRE resource.address IS UNIQUE"),
+    ];
+
+    for node in graph.nodes() {
+        statements.push(format!("{} SET resource = {}, resource:{}",
+            merge_resource("resource", &node.address), cypher_map(&properties(node)), cypher_name(&node.node_type)));
+    }
+    for address in missing_nodes(graph) {
+        statements.push(merge_resource("resource", address));
+    }
+    for edge in graph.edges() {
+        for relationship_type in relationship_types(edge) {
+            statements.push(format!("{} {} MERGE (source)-[relationship:{}]->(target) SET relationship = {}",
+                merge_resource("source", &edge.source), merge_resource("target", &edge.target),
+                cypher_name(&relationship_type), cypher_map(&relationship_properties(edge))));
+        }
+    }
+
+    statements.iter().map(|statement| format!("{};\n", statement)).collect()
+}
+
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+fn csv_row(fields: &[String]) -> String {
+    format!("{}\n", fields.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(","))
+}
+
+/// The `nodes.csv` file of a neo4j-admin import: a column for each property found on any node, typed by the values in it,
+/// or `string` where they differ. Lists are separated by `;`, the import's default array delimiter.
+pub fn render_nodes_csv(graph: &InfrastructureGraph) -> String {
+    let rows: Vec<(&Node, Vec<(String, Property)>)> = graph.nodes().iter().map(|node| (node, properties(node))).collect();
+
+    let mut columns: Vec<(String, String)> = vec![];
+    for (_, properties) in &rows {
+        for (name, property) in properties.iter().filter(|(name, _)| name != "address") {
+            match columns.iter_mut().find(|(column, _)| column == name) {
+                Some((_, column_type)) if *column_type != property.column_type() => {
+                    let both_numbers = [column_type.as_str(), property.column_type().as_str()].iter().all(|found| *found == "long" || *found == "double");
+                    *column_type = String::from(if both_numbers { "double" } else { "string" });
+                },
+                Some(_) => (),
+                None => columns.push((name.to_string(), property.column_type())),
+            }
+        }
+    }
+
+    let mut header = vec![String::from("address:ID"), String::from(":LABEL")];
+    header.extend(columns.iter().map(|(name, column_type)| format!("{}:{}", name, column_type)));
+    let mut csv = csv_row(&header);
+
+    for (node, properties) in &rows {
+        let mut fields = vec![node.address.to_string(), format!("Resource;{}", node.node_type)];
+        fields.extend(columns.iter().map(|(name, _)| {
+            properties.iter().find(|(property, _)| property == name).map(|(_, value)| value.text()).unwrap_or_default()
+        }));
+        csv.push_str(&csv_row(&fields));
+    }
+    for address in missing_nodes(graph) {
+        let mut fields = vec![address.to_string(), String::from("Resource")];
+        fields.extend(columns.iter().map(|_| String::new()));
+        csv.push_str(&csv_row(&fields));
+    }
+
+    csv
+}
+
+/// the `relationships.csv` file of a neo4j-admin import
+pub fn render_relationships_csv(graph: &InfrastructureGraph) -> String {
+    let mut csv = csv_row(&[":START_ID", ":END_ID", ":TYPE", "kind", "label", "found_in", "found_in_attribute"].iter().map(|name| name.to_string()).collect::<Vec<String>>());
+    let mut written = HashSet::new();
+
+    for edge in graph.edges() {
+        for relationship_type in relationship_types(edge) {
+            if !written.insert((&edge.source, &edge.target, relationship_type.to_string())) {
+                continue;
+            }
+            csv.push_str(&csv_row(&[
+                edge.source.to_string(),
+                edge.target.to_string(),
+                relationship_type,
+                kind_name(edge.kind).to_string(),
+                edge.label.to_string(),
+                edge.provenance.block.to_string(),
+                edge.provenance.attribute.clone().unwrap_or_default(),
+            ]));
+        }
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::fixtures::{ edge, graph };
+    use crate::graph::EdgeKind;
+
+    fn example() -> InfrastructureGraph {
+        graph(r#"{"resource": {
+            "aws_sqs_queue": {"queue": {"name": "orders", "delay_seconds": 30, "tags": {"team": "payments, eu"}}},
+            "aws_security_group": {"web": {"ingress": [{"from_port": 80}, {"from_port": 443}], "cidr_blocks": ["10.0.0.0/8", 1]}}
+        }}"#, &[], vec![
+            edge(EdgeKind::Permission, "aws_security_group.web", "aws_sqs_queue.queue", "['sqs:SendMessage','sqs:DeleteMessage']", "aws_iam_policy.policy"),
+            edge(EdgeKind::Relationship, "aws_sqs_queue.queue", "aws_lambda_function.worker", "", "aws_lambda_event_source_mapping.mapping"),
+        ])
+    }
+
+    #[test]
+    fn attributes_are_flattened_into_properties() {
+        let graph = example();
+        let queue = properties(graph.node("aws_sqs_queue.queue").unwrap());
+        assert_eq!(queue, vec![
+            (String::from("address"), Property::Str(String::from("aws_sqs_queue.queue"))),
+            (String::from("block_type"), Property::Str(String::from("resource"))),
+            (String::from("block_name"), Property::Str(String::from("queue"))),
+            (String::from("name"), Property::Str(String::from("orders"))),
+            (String::from("delay_seconds"), Property::Int(30)),
+            (String::from("tags.team"), Property::Str(String::from("payments, eu"))),
+        ]);
+
+        let web = properties(graph.node("aws_security_group.web").unwrap());
+        assert!(web.contains(&(String::from("ingress.1.from_port"), Property::Int(443))));
+        assert!(web.contains(&(String::from("cidr_blocks"), Property::List(vec![Property::Str(String::from("10.0.0.0/8")), Property::Str(String::from("1"))]))));
+    }
+
+    #[test]
+    fn cypher_merges_nodes_and_relationships() {
+        let cypher = render_cypher(&example());
+        let statements: Vec<&str> = cypher.lines().collect();
+
+        assert_eq!(statements[1], "MERGE (resource:Resource {address: 'aws_sqs_queue.queue'}) SET resource = {address: 'aws_sqs_queue.queue', block_type: 'resource', block_name: 'queue', name: 'orders', delay_seconds: 30, `tags.team`: 'payments, eu'}, resource:aws_sqs_queue;");
+        assert_eq!(statements[3], "MERGE (resource:Resource {address: 'aws_lambda_function.worker'});");
+        assert_eq!(statements[4], "MERGE (source:Resource {address: 'aws_security_group.web'}) MERGE (target:Resource {address: 'aws_sqs_queue.queue'}) MERGE (source)-[relationship:`sqs:SendMessage`]->(target) SET relationship = {kind: 'permission', label: '[\\'sqs:SendMessage\\',\\'sqs:DeleteMessage\\']', found_in: 'aws_iam_policy.policy'};");
+        assert!(statements[6].contains("-[relationship:RELATIONSHIP]->"));
+        assert_eq!(statements.len(), 7);
+    }
+
+    #[test]
+    fn csv_files_follow_the_neo4j_admin_layout() {
+        let graph = example();
+        let nodes = render_nodes_csv(&graph);
+        let lines: Vec<&str> = nodes.lines().collect();
+        assert_eq!(lines[0], "address:ID,:LABEL,block_type:string,block_name:string,name:string,delay_seconds:long,tags.team:string,ingress.0.from_port:long,ingress.1.from_port:long,cidr_blocks:string[]");
+        assert_eq!(lines[1], "aws_sqs_queue.queue,Resource;aws_sqs_queue,resource,queue,orders,30,\"payments, eu\",,,");
+        assert_eq!(lines[2], "aws_security_group.web,Resource;aws_security_group,resource,web,,,,80,443,10.0.0.0/8;1");
+        assert_eq!(lines[3], "aws_lambda_function.worker,Resource,,,,,,,,");
+
+        let relationships = render_relationships_csv(&graph);
+        let lines: Vec<&str> = relationships.lines().collect();
+        assert_eq!(lines[0], ":START_ID,:END_ID,:TYPE,kind,label,found_in,found_in_attribute");
+        assert_eq!(lines[2], "aws_security_group.web,aws_sqs_queue.queue,sqs:DeleteMessage,permission,\"['sqs:SendMessage','sqs:DeleteMessage']\",aws_iam_policy.policy,");
+        assert_eq!(lines.len(), 4);
+    }
+}
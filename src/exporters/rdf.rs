@@ -0,0 +1,531 @@
+//! RDF renderings of an InfrastructureGraph, as Turtle or N-Triples, for loading into a triple store.
+//! The vocabulary is described in docs/graph_databases.md:
+//! - each resource is an IRI under `res:` typed by its resource type, a class under `type:`
+//! - attributes are datatype properties under `attr:`, nested blocks are flattened into dotted names, eg. `attr:ingress.from_port`
+//! - every relationship is an object property named by its kind, eg. `ig:permission`, and by each of its labels under `rel:`
+//! - the classes and properties used are declared at the top of the document
+//!
+//! As N-Quads, each template's triples are in a named graph of their own, or all in one for the environment given,
+//! and N-Quads can be read back, to work out what has changed since they were loaded.
+
+use std::collections::{ BTreeSet, HashMap };
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::exporters::drawn;
+use crate::graph::{ Edge, EdgeKind, InfrastructureGraph, Node, kind_name, label_names };
+use crate::structs::attributes::AttributeType;
+use crate::structs::span::Span;
+use crate::structs::json::JsonValue;
+use crate::visitors::json_visitor::JsonVisitor;
+use crate::visitors::visitor::Visitor;
+
+pub const BASE: &str = "https://github.com/jamesw201/infrastructure-graph/";
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const RDFS: &str = "http://www.w3.org/2000/01/rdf-schema#";
+const OWL: &str = "http://www.w3.org/2002/07/owl#";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// the prefixes written in Turtle, with the namespaces they stand for beneath BASE or in full
+const PREFIXES: [(&str, &str); 9] = [
+    ("ig", "vocab#"),
+    ("type", "vocab/type/"),
+    ("attr", "vocab/attribute/"),
+    ("rel", "vocab/relationship/"),
+    ("res", "resource/"),
+    ("rdf", RDF),
+    ("rdfs", RDFS),
+    ("owl", OWL),
+    ("xsd", XSD),
+];
+
+fn namespace(prefix: &str) -> String {
+    let (_, path) = PREFIXES.iter().find(|(name, _)| *name == prefix).unwrap();
+    if path.starts_with("http") {
+        path.to_string()
+    } else {
+        format!("{}{}", BASE, path)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
+pub enum Term {
+    Iri(String),
+    Literal { lexical: String, datatype: Option<String> },
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Triple {
+    pub subject: Term,
+    pub predicate: Term,
+    pub object: Term,
+}
+
+/// the IRI of `name` within the namespace of `prefix`
+fn iri(prefix: &str, name: &str) -> Term {
+    Term::Iri(format!("{}{}", namespace(prefix), local_name(name)))
+}
+
+fn string(value: &str) -> Term {
+    Term::Literal { lexical: value.to_string(), datatype: None }
+}
+
+fn typed(value: &str, datatype: &str) -> Term {
+    Term::Literal { lexical: value.to_string(), datatype: Some(datatype.to_string()) }
+}
+
+/// Percent encode everything in a name but letters, digits, `_`, `-` and inner `.`, so it can end an IRI
+/// and, in Turtle, be written after a prefix, eg. `AWS::SQS::Queue` becomes `AWS%3A%3ASQS%3A%3AQueue`
+pub fn local_name(name: &str) -> String {
+    let mut encoded = String::new();
+    let last = name.chars().count().saturating_sub(1);
+
+    for (position, c) in name.chars().enumerate() {
+        let allowed = c.is_ascii_alphanumeric() || c == '_'
+            || (c == '-' && position > 0)
+            || (c == '.' && position > 0 && position < last);
+        if allowed {
+            encoded.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+
+    encoded
+}
+
+/// The triples of a graph: the vocabulary it uses, then each resource, then each relationship
+pub fn triples(graph: &InfrastructureGraph) -> Vec<Triple> {
+    let mut data = vec![];
+    let mut classes = BTreeSet::new();
+    let mut datatype_properties = BTreeSet::new();
+    let mut object_properties = BTreeSet::new();
+
+    for node in graph.nodes() {
+        classes.insert(node.node_type.to_string());
+        node_triples(node, &mut data, &mut datatype_properties);
+    }
+    for edge in graph.edges() {
+        edge_triples(edge, &mut data, &mut object_properties);
+    }
+
+    let mut vocabulary = vec![];
+    let declare = |vocabulary: &mut Vec<Triple>, subject: Term, class: Term, label: &str| {
+        vocabulary.push(Triple { subject: subject.clone(), predicate: iri("rdf", "type"), object: class });
+        vocabulary.push(Triple { subject, predicate: iri("rdfs", "label"), object: string(label) });
+    };
+
+    declare(&mut vocabulary, iri("ig", "Resource"), iri("rdfs", "Class"), "Resource");
+    for name in &["address", "name", "blockType", "module", "plannedAction"] {
+        declare(&mut vocabulary, iri("ig", name), iri("owl", "DatatypeProperty"), name);
+    }
+    for kind in &[EdgeKind::Relationship, EdgeKind::Permission, EdgeKind::Reference] {
+        declare(&mut vocabulary, iri("ig", kind_name(*kind)), iri("owl", "ObjectProperty"), kind_name(*kind));
+    }
+    for class in &classes {
+        declare(&mut vocabulary, iri("type", class), iri("rdfs", "Class"), class);
+        vocabulary.push(Triple { subject: iri("type", class), predicate: iri("rdfs", "subClassOf"), object: iri("ig", "Resource") });
+    }
+    for property in &datatype_properties {
+        declare(&mut vocabulary, iri("attr", property), iri("owl", "DatatypeProperty"), property);
+    }
+    for property in &object_properties {
+        declare(&mut vocabulary, iri("rel", property), iri("owl", "ObjectProperty"), property);
+    }
+
+    vocabulary.extend(data);
+    vocabulary
+}
+
+fn node_triples(node: &Node, triples: &mut Vec<Triple>, properties: &mut BTreeSet<String>) {
+    let subject = iri("res", &node.address);
+    let mut add = |predicate: Term, object: Term| triples.push(Triple { subject: subject.clone(), predicate, object });
+
+    add(iri("rdf", "type"), iri("type", &node.node_type));
+    add(iri("ig", "address"), string(&node.address));
+    add(iri("ig", "blockType"), string(&node.block_type));
+    if let Some(name) = &node.name {
+        add(iri("ig", "name"), string(name));
+    }
+    if let Some(module) = &node.module {
+        add(iri("ig", "module"), string(module));
+    }
+    for action in &node.planned_actions {
+        add(iri("ig", "plannedAction"), string(action));
+    }
+
+    for attribute in &node.attributes {
+        attribute_triples(&subject, &attribute.key, &attribute.value, triples, properties);
+    }
+}
+
+/// an attribute's values as literals, arrays as a value each, nested blocks beneath the attribute's name
+fn attribute_triples(subject: &Term, name: &str, value: &AttributeType, triples: &mut Vec<Triple>, properties: &mut BTreeSet<String>) {
+    let object = match value {
+        AttributeType::Str(text) => string(text),
+        AttributeType::TemplatedString(template) => string(&template.to_string()),
+        AttributeType::Expression(expression) => string(&expression.to_string()),
+        AttributeType::Boolean(boolean) => typed(&boolean.to_string(), &format!("{}boolean", XSD)),
+        AttributeType::Num(number) => number_literal(*number),
+        AttributeType::Json(JsonValue::Str(text)) => string(text),
+        AttributeType::Json(json) => typed(&JsonVisitor{}.visit_json(json).to_string(), &format!("{}JSON", RDF)),
+        AttributeType::Array(values) => {
+            return values.iter().for_each(|value| attribute_triples(subject, name, value, triples, properties));
+        },
+        AttributeType::Block(attributes) => {
+            return attributes.iter().for_each(|attr| attribute_triples(subject, &format!("{}.{}", name, attr.key), &attr.value, triples, properties));
+        },
+        AttributeType::TFBlock(block) => {
+            return block.attributes().iter().for_each(|attr| attribute_triples(subject, &format!("{}.{}", name, attr.key), &attr.value, triples, properties));
+        },
+    };
+
+    properties.insert(name.to_string());
+    triples.push(Triple { subject: subject.clone(), predicate: iri("attr", name), object });
+}
+
+fn number_literal(number: f64) -> Term {
+    if number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+        typed(&(number as i64).to_string(), &format!("{}integer", XSD))
+    } else if number.is_finite() {
+        typed(&number.to_string(), &format!("{}decimal", XSD))
+    } else {
+        let lexical = if number.is_nan() { "NaN" } else if number > 0.0 { "INF" } else { "-INF" };
+        typed(lexical, &format!("{}double", XSD))
+    }
+}
+
+/// the triples of an edge, none when it names no resource at one end
+fn edge_triples(edge: &Edge, triples: &mut Vec<Triple>, properties: &mut BTreeSet<String>) {
+    if !drawn(edge) {
+        return;
+    }
+
+    let subject = iri("res", &edge.source);
+    let object = iri("res", &edge.target);
+
+    triples.push(Triple { subject: subject.clone(), predicate: iri("ig", kind_name(edge.kind)), object: object.clone() });
+    for name in label_names(&edge.label) {
+        triples.push(Triple { subject: subject.clone(), predicate: iri("rel", &name), object: object.clone() });
+        properties.insert(name);
+    }
+}
+
+fn escape(lexical: &str) -> String {
+    let mut escaped = String::new();
+    for c in lexical.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn ntriples_term(term: &Term) -> String {
+    match term {
+        Term::Iri(iri) => format!("<{}>", iri),
+        Term::Literal { lexical, datatype: None } => format!("\"{}\"", escape(lexical)),
+        Term::Literal { lexical, datatype: Some(datatype) } => format!("\"{}\"^^<{}>", escape(lexical), datatype),
+    }
+}
+
+pub fn render_ntriples(graph: &InfrastructureGraph) -> String {
+    triples(graph).iter()
+        .map(|triple| format!("{} {} {} .\n", ntriples_term(&triple.subject), ntriples_term(&triple.predicate), ntriples_term(&triple.object)))
+        .collect()
+}
+
+/// an IRI written with a prefix where one of ours covers it, `a` for rdf:type
+fn turtle_iri(iri: &str) -> String {
+    if iri == format!("{}type", RDF) {
+        return String::from("a");
+    }
+
+    for (prefix, _) in PREFIXES.iter() {
+        if let Some(local) = iri.strip_prefix(&namespace(prefix)) {
+            if !local.is_empty() && local_name(&percent_decode(local)) == local {
+                return format!("{}:{}", prefix, local);
+            }
+        }
+    }
+    format!("<{}>", iri)
+}
+
+/// undo local_name's encoding, so a local name can be checked to be one local_name would write
+fn percent_decode(local: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = local.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = std::str::from_utf8(tail.get(..2).unwrap_or(&[])).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            },
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            },
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn turtle_term(term: &Term) -> String {
+    match term {
+        Term::Iri(iri) => turtle_iri(iri),
+        Term::Literal { lexical, datatype: None } => format!("\"{}\"", escape(lexical)),
+        Term::Literal { lexical, datatype: Some(datatype) } => format!("\"{}\"^^{}", escape(lexical), turtle_iri(datatype)),
+    }
+}
+
+/// Turtle, with each subject's triples written together in the order the subjects first appear
+pub fn render_turtle(graph: &InfrastructureGraph) -> String {
+    let mut subjects: Vec<(Term, Vec<(Term, Term)>)> = vec![];
+    let mut positions: HashMap<Term, usize> = HashMap::new();
+
+    for triple in triples(graph) {
+        let position = *positions.entry(triple.subject.clone()).or_insert_with(|| {
+            subjects.push((triple.subject.clone(), vec![]));
+            subjects.len() - 1
+        });
+        subjects[position].1.push((triple.predicate, triple.object));
+    }
+
+    let mut turtle: String = PREFIXES.iter()
+        .map(|(prefix, _)| format!("@prefix {}: <{}> .\n", prefix, namespace(prefix)))
+        .collect();
+
+    for (subject, predicates) in subjects {
+        let lines: Vec<String> = predicates.iter()
+            .map(|(predicate, object)| format!("{} {}", turtle_term(predicate), turtle_term(object)))
+            .collect();
+        turtle.push_str(&format!("\n{}\n    {} .\n", turtle_term(&subject), lines.join(" ;\n    ")));
+    }
+
+    turtle
+}
+
+/// a triple in a named graph
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Quad {
+    pub triple: Triple,
+    pub graph: String,
+}
+
+/// the IRI of a named graph, eg. for the template `main.tf` or the environment `production`
+pub fn graph_iri(name: &str) -> String {
+    format!("{}graph/{}", BASE, local_name(name))
+}
+
+fn template(span: &Span) -> Option<String> {
+    span.file.clone()
+}
+
+/// The triples of a graph in named graphs: all in that of the environment given, otherwise each resource in that of the
+/// template it was read from, and each relationship in that of the template it was found in. Every named graph declares
+/// the vocabulary it uses, so one can be dropped without losing the declarations another needs.
+pub fn quads(graph: &InfrastructureGraph, environment: Option<&str>) -> Vec<Quad> {
+    let name_of = |found_in: Option<String>| environment.map(str::to_string).or(found_in).unwrap_or_else(|| String::from("default"));
+    let mut partitions: Vec<(String, InfrastructureGraph)> = vec![];
+
+    for node in graph.nodes() {
+        partition(&mut partitions, name_of(template(&node.span))).add_node(node.clone());
+    }
+    for edge in graph.edges() {
+        let found_in = template(&edge.provenance.span)
+            .or_else(|| graph.node(&edge.provenance.block).and_then(|node| template(&node.span)));
+        partition(&mut partitions, name_of(found_in)).add_edge(edge.clone());
+    }
+
+    partitions.iter()
+        .flat_map(|(name, partition)| triples(partition).into_iter().map(move |triple| Quad { triple, graph: graph_iri(name) }))
+        .collect()
+}
+
+/// the partition named, added after the others if there isn't one yet
+fn partition(partitions: &mut Vec<(String, InfrastructureGraph)>, name: String) -> &mut InfrastructureGraph {
+    let position = match partitions.iter().position(|(found, _)| *found == name) {
+        Some(position) => position,
+        None => {
+            partitions.push((name, InfrastructureGraph::new()));
+            partitions.len() - 1
+        },
+    };
+    &mut partitions[position].1
+}
+pub fn render_nquads(graph: &InfrastructureGraph, environment: Option<&str>) -> String {
+    quads(graph, environment).iter()
+        .map(|quad| format!("{} {} {} <{}> .\n", ntriples_term(&quad.triple.subject), ntriples_term(&quad.triple.predicate), ntriples_term(&quad.triple.object), quad.graph))
+        .collect()
+}
+
+/// Read the quads of an N-Quads document, such as one rendered by render_nquads. Every quad must be in a named graph,
+/// and blank nodes and language tagged literals, which aren't written here, aren't read.
+pub fn parse_nquads(document: &str) -> Result<Vec<Quad>, String> {
+    let mut quads = vec![];
+
+    for (number, line) in document.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut chars = line.chars().peekable();
+        let mut terms = vec![];
+        loop {
+            skip_whitespace(&mut chars);
+            match chars.peek() {
+                Some('.') => break,
+                Some(_) => terms.push(read_term(&mut chars).map_err(|message| format!("line {}: {}", number + 1, message))?),
+                None => return Err(format!("line {}: expected a '.' at the end of the quad", number + 1)),
+            }
+        }
+
+        match terms.as_slice() {
+            [subject, predicate, object, Term::Iri(graph)] => quads.push(Quad {
+                triple: Triple { subject: subject.clone(), predicate: predicate.clone(), object: object.clone() },
+                graph: graph.to_string(),
+            }),
+            _ => return Err(format!("line {}: expected a subject, predicate, object and named graph", number + 1)),
+        }
+    }
+
+    Ok(quads)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn read_iri(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    chars.next();
+    let mut iri = String::new();
+    loop {
+        match chars.next() {
+            Some('>') => return Ok(iri),
+            Some(c) => iri.push(c),
+            None => return Err(String::from("unterminated IRI")),
+        }
+    }
+}
+
+fn read_term(chars: &mut Peekable<Chars>) -> Result<Term, String> {
+    match chars.peek() {
+        Some('<') => Ok(Term::Iri(read_iri(chars)?)),
+        Some('"') => {
+            chars.next();
+            let mut lexical = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => lexical.push(read_escape(chars)?),
+                    Some(c) => lexical.push(c),
+                    None => return Err(String::from("unterminated literal")),
+                }
+            }
+            match chars.peek() {
+                Some('^') => {
+                    chars.next();
+                    if chars.next() != Some('^') || chars.peek() != Some(&'<') {
+                        return Err(String::from("expected a datatype IRI after '^^'"));
+                    }
+                    Ok(Term::Literal { lexical, datatype: Some(read_iri(chars)?) })
+                },
+                Some('@') => Err(String::from("language tagged literals aren't supported")),
+                _ => Ok(Term::Literal { lexical, datatype: None }),
+            }
+        },
+        Some('_') => Err(String::from("blank nodes aren't supported")),
+        Some(c) => Err(format!("unexpected '{}'", c)),
+        None => Err(String::from("expected a term")),
+    }
+}
+
+fn read_escape(chars: &mut Peekable<Chars>) -> Result<char, String> {
+    let hex = |chars: &mut Peekable<Chars>, length: usize| {
+        let digits: String = chars.take(length).collect();
+        u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32)
+            .ok_or_else(|| format!("invalid escape '{}'", digits))
+    };
+
+    match chars.next() {
+        Some('t') => Ok('\t'),
+        Some('b') => Ok('\u{8}'),
+        Some('n') => Ok('\n'),
+        Some('r') => Ok('\r'),
+        Some('f') => Ok('\u{c}'),
+        Some('"') => Ok('"'),
+        Some('\'') => Ok('\''),
+        Some('\\') => Ok('\\'),
+        Some('u') => hex(chars, 4),
+        Some('U') => hex(chars, 8),
+        other => Err(format!("invalid escape '\\{}'", other.map(String::from).unwrap_or_default())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::fixtures::{ edge, graph };
+
+    fn example() -> InfrastructureGraph {
+        graph(r#"{"resource": {
+            "aws_sqs_queue": {"queue": {"name": "orders", "delay_seconds": 30, "fifo_queue": true, "redrive_policy": "{\"maxReceiveCount\": 2}"}},
+            "aws_iam_policy": {"policy": {"name": "it's \"quoted\"", "tags": {"team": "payments"}}}
+        }}"#, &[], vec![
+            edge(EdgeKind::Permission, "aws_iam_policy.policy", "aws_sqs_queue.queue", "['sqs:SendMessage','sqs:DeleteMessage']", "aws_iam_policy.policy"),
+            edge(EdgeKind::Permission, "aws_iam_policy.policy", "", "['logs:CreateLogStream']", "aws_iam_policy.policy"),
+        ])
+    }
+
+    #[test]
+    fn names_are_encoded_for_iris() {
+        assert_eq!(local_name("aws_sqs_queue.queue"), "aws_sqs_queue.queue");
+        assert_eq!(local_name("AWS::SQS::Queue"), "AWS%3A%3ASQS%3A%3AQueue");
+        assert_eq!(local_name("module.queues[\"eu\"].queue."), "module.queues%5B%22eu%22%5D.queue%2E");
+        assert_eq!(local_name("-x"), "%2Dx");
+        assert_eq!(percent_decode("AWS%3A%3ASQS"), "AWS::SQS");
+    }
+
+    #[test]
+    fn ntriples_are_one_triple_a_line() {
+        let ntriples = render_ntriples(&example());
+        let res = format!("{}resource/", BASE);
+        let vocab = format!("{}vocab", BASE);
+
+        for line in ntriples.lines() {
+            assert!(line.starts_with('<') && line.ends_with(" ."), "{}", line);
+        }
+        assert!(ntriples.contains(&format!("<{}aws_sqs_queue.queue> <{}/attribute/delay_seconds> \"30\"^^<{}integer> .\n", res, vocab, XSD)));
+        assert!(ntriples.contains(&format!("<{}aws_sqs_queue.queue> <{}/attribute/fifo_queue> \"true\"^^<{}boolean> .\n", res, vocab, XSD)));
+        assert!(ntriples.contains(&format!("<{}aws_iam_policy.policy> <{}/attribute/name> \"it's \\\"quoted\\\"\" .\n", res, vocab)));
+        assert!(ntriples.contains(&format!("<{}aws_iam_policy.policy> <{}/attribute/tags.team> \"payments\" .\n", res, vocab)));
+        assert!(ntriples.contains(&format!("<{}aws_iam_policy.policy> <{}#permission> <{}aws_sqs_queue.queue> .\n", res, vocab, res)));
+        assert!(ntriples.contains(&format!("<{}aws_iam_policy.policy> <{}/relationship/sqs%3ASendMessage> <{}aws_sqs_queue.queue> .\n", res, vocab, res)));
+        assert!(!ntriples.contains("CreateLogStream"));
+        assert!(ntriples.contains(&format!("<{}/type/aws_sqs_queue> <{}subClassOf> <{}#Resource> .\n", vocab, RDFS, vocab)));
+    }
+
+    #[test]
+    fn turtle_groups_triples_by_subject() {
+        let turtle = render_turtle(&example());
+
+        assert!(turtle.starts_with(&format!("@prefix ig: <{}vocab#> .\n", BASE)));
+        assert!(turtle.contains("\nres:aws_sqs_queue.queue\n    a type:aws_sqs_queue ;\n    ig:address \"aws_sqs_queue.queue\" ;\n"));
+        assert!(turtle.contains("    attr:redrive_policy \"{\\\"maxReceiveCount\\\":2}\"^^rdf:JSON"));
+        assert!(turtle.contains("\nres:aws_iam_policy.policy\n"));
+        assert!(turtle.contains("    ig:permission res:aws_sqs_queue.queue ;\n    rel:sqs%3ASendMessage res:aws_sqs_queue.queue ;\n"));
+        assert!(turtle.contains("\nrel:sqs%3ADeleteMessage\n    a owl:ObjectProperty ;\n    rdfs:label \"sqs:DeleteMessage\" .\n"));
+    }
+}
@@ -0,0 +1,154 @@
+//! SPARQL updates which bring a triple store up to date with a graph, described in docs/graph_databases.md.
+//! The quads of the graph, see `rdf::quads`, are compared with those loaded before, as recorded by an N-Quads
+//! export: the quads no longer found are removed by a `DELETE DATA` and the new ones added by an `INSERT DATA`,
+//! so a template's named graph is changed in place rather than loaded again. Without a previous load each named graph
+//! is cleared before its quads are inserted, so loading again leaves nothing stale behind.
+
+use std::collections::HashSet;
+
+use crate::exporters::rdf::{ self, Quad };
+use crate::graph::InfrastructureGraph;
+
+/// the named graphs of the quads, in the order they're first found
+fn graphs<'a>(quads: &[&'a Quad]) -> Vec<&'a str> {
+    let mut graphs: Vec<&str> = vec![];
+    for quad in quads {
+        if !graphs.contains(&quad.graph.as_str()) {
+            graphs.push(&quad.graph);
+        }
+    }
+    graphs
+}
+
+fn data(operation: &str, quads: &[&Quad]) -> String {
+    let blocks: Vec<String> = graphs(quads).iter().map(|graph| {
+        let triples: String = quads.iter()
+            .filter(|quad| quad.graph == *graph)
+            .map(|quad| format!("    {} {} {} .\n", rdf::ntriples_term(&quad.triple.subject), rdf::ntriples_term(&quad.triple.predicate), rdf::ntriples_term(&quad.triple.object)))
+            .collect();
+        format!("  GRAPH <{}> {{\n{}  }}\n", graph, triples)
+    }).collect();
+
+    format!("{} {{\n{}}}", operation, blocks.concat())
+}
+
+/// the updates from the quads `previous` to `current`, a comment when there are none
+pub fn update(current: &[Quad], previous: &[Quad]) -> String {
+    let current_set: HashSet<&Quad> = current.iter().collect();
+    let previous_set: HashSet<&Quad> = previous.iter().collect();
+
+    let deleted: Vec<&Quad> = previous.iter().filter(|quad| !current_set.contains(quad)).collect();
+    let inserted: Vec<&Quad> = current.iter().filter(|quad| !previous_set.contains(quad)).collect();
+
+    let mut operations = vec![];
+    if !deleted.is_empty() {
+        operations.push(data("DELETE DATA", &deleted));
+    }
+    if !inserted.is_empty() {
+        operations.push(data("INSERT DATA", &inserted));
+    }
+
+    match operations.is_empty() {
+        true => String::from("# nothing has changed since the previous load\n"),
+        false => format!("{}\n", operations.join(" ;\n")),
+    }
+}
+
+/// the update loading the quads `current` into named graphs cleared of whatever was loaded before
+pub fn load(current: &[Quad]) -> String {
+    let quads: Vec<&Quad> = current.iter().collect();
+    let clears: String = graphs(&quads).iter().map(|graph| format!("CLEAR SILENT GRAPH <{}> ;\n", graph)).collect();
+    format!("{}{}\n", clears, data("INSERT DATA", &quads))
+}
+
+pub fn render(graph: &InfrastructureGraph, environment: Option<&str>, previous: Option<&[Quad]>) -> String {
+    let current = rdf::quads(graph, environment);
+    match previous {
+        Some(previous) => update(&current, previous),
+        None => load(&current),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::fixtures::{ edge, graph };
+    use crate::graph::EdgeKind;
+
+    fn example(delay_seconds: u32) -> InfrastructureGraph {
+        graph(&format!(r#"{{"resource": {{
+            "aws_sqs_queue": {{"queue": {{"delay_seconds": {}}}}},
+            "aws_lambda_function": {{"worker": {{"description": "reads \"orders\""}}}}
+        }}}}"#, delay_seconds), &[("aws_sqs_queue.queue", "queues.tf.json"), ("aws_lambda_function.worker", "workers.tf.json")], vec![
+            edge(EdgeKind::Relationship, "aws_sqs_queue.queue", "aws_lambda_function.worker", "", "aws_lambda_function.worker"),
+        ])
+    }
+
+    #[test]
+    fn quads_are_in_a_graph_per_template_or_environment() {
+        let queues = rdf::graph_iri("queues.tf.json");
+        let workers = rdf::graph_iri("workers.tf.json");
+        let quads = rdf::quads(&example(30), None);
+
+        let in_graph = |graph: &str, predicate: &str| quads.iter()
+            .any(|quad| quad.graph == graph && quad.triple.predicate == rdf::Term::Iri(format!("{}{}", rdf::BASE, predicate)));
+        assert!(in_graph(&queues, "vocab/attribute/delay_seconds"));
+        assert!(!in_graph(&workers, "vocab/attribute/delay_seconds"));
+        // the relationship was found in the worker's template
+        assert!(in_graph(&workers, "vocab#relationship"));
+        assert!(!in_graph(&queues, "vocab#relationship"));
+
+        let production = rdf::quads(&example(30), Some("production"));
+        assert!(production.iter().all(|quad| quad.graph == format!("{}graph/production", rdf::BASE)));
+    }
+
+    #[test]
+    fn nquads_read_back_as_written() {
+        let graph = example(30);
+        let written = rdf::render_nquads(&graph, None);
+        assert_eq!(rdf::parse_nquads(&written).unwrap(), rdf::quads(&graph, None));
+
+        assert!(rdf::parse_nquads("<a> <b> <c> .").unwrap_err().starts_with("line 1:"));
+        assert!(rdf::parse_nquads("<a> <b> \"c\"@en <g> .").is_err());
+        assert!(rdf::parse_nquads("<a> <b> \"c\" <g>").is_err());
+    }
+
+    #[test]
+    fn updates_change_only_what_changed() {
+        let loaded = rdf::quads(&example(30), None);
+
+        let first = render(&example(30), None, Some(&[]));
+        assert!(first.starts_with("INSERT DATA {\n  GRAPH <"));
+        assert!(!first.contains("DELETE DATA"));
+        assert!(first.contains("\"reads \\\"orders\\\"\""));
+
+        assert_eq!(render(&example(30), None, Some(&loaded)), "# nothing has changed since the previous load\n");
+
+        let changed = render(&example(60), None, Some(&loaded));
+        let delay = format!("<{}vocab/attribute/delay_seconds>", rdf::BASE);
+        assert_eq!(changed, format!(
+            "DELETE DATA {{\n  GRAPH <{graph}> {{\n    <{res}aws_sqs_queue.queue> {delay} \"30\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n  }}\n}} ;\n\
+             INSERT DATA {{\n  GRAPH <{graph}> {{\n    <{res}aws_sqs_queue.queue> {delay} \"60\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n  }}\n}}\n",
+            graph = rdf::graph_iri("queues.tf.json"), res = format!("{}resource/", rdf::BASE), delay = delay,
+        ));
+    }
+
+    #[test]
+    fn loads_without_a_previous_load_clear_their_graphs() {
+        let clears = format!("CLEAR SILENT GRAPH <{}> ;\nCLEAR SILENT GRAPH <{}> ;\nINSERT DATA {{\n",
+            rdf::graph_iri("queues.tf.json"), rdf::graph_iri("workers.tf.json"));
+        let first = render(&example(30), None, None);
+        let second = render(&example(60), None, None);
+
+        // the second load replaces what the first loaded, rather than adding to it
+        assert!(first.starts_with(&clears));
+        assert!(second.starts_with(&clears));
+        assert!(first.contains("\"30\"^^") && !first.contains("\"60\"^^"));
+        assert!(second.contains("\"60\"^^") && !second.contains("\"30\"^^"));
+        assert!(!second.contains("DELETE DATA"));
+        assert!(second.ends_with("}\n"));
+
+        let production = render(&example(60), Some("production"), None);
+        assert!(production.starts_with(&format!("CLEAR SILENT GRAPH <{}graph/production> ;\nINSERT DATA", rdf::BASE)));
+    }
+}
@@ -0,0 +1,504 @@
+extern crate nom;
+
+use nom::{
+  branch::alt,
+  bytes::complete::{is_not, tag, take, take_while},
+  character::complete::{anychar, char, digit1, multispace0, one_of},
+  combinator::{map, not, opt, recognize, value, verify},
+  error::{ErrorKind, make_error},
+  multi::{many0, separated_list0},
+  sequence::{delimited, pair, preceded, terminated, tuple},
+  IResult
+};
+use std::str;
+
+use crate::structs::template_string::{
+    BinaryOperator, BuiltInFunction, Expression, TemplatePart, TemplateString, Traversal, TraversalStep, UnaryOperator,
+};
+
+type Operand = fn(&str) -> IResult<&str, Expression>;
+
+fn identifier(i: &str) -> IResult<&str, &str> {
+    recognize(
+        pair(
+            verify(anychar, |c: &char| c.is_alphabetic() || *c == '_'),
+            take_while(|c: char| c.is_alphanumeric() || c == '_' || c == '-'),
+        )
+    )(i)
+}
+
+fn number(i: &str) -> IResult<&str, f64> {
+    let (rest, digits) = recognize(
+        tuple((
+            digit1,
+            opt(pair(char('.'), digit1)),
+            opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+        ))
+    )(i)?;
+
+    match digits.parse() {
+        Ok(number) => Ok((rest, number)),
+        Err(_) => Err(nom::Err::Error(make_error(i, ErrorKind::Float))),
+    }
+}
+
+/// the text of a literal as written, decoded by `unescape` once the template's parts are merged
+fn template_literal(i: &str) -> IResult<&str, &str> {
+    alt((
+        is_not("\"\\$"),
+        recognize(pair(char('\\'), take(1usize))),
+        tag("$${"),
+        terminated(tag("$"), not(char('{'))),
+    ))(i)
+}
+
+/// decode the backslash escapes of a quoted string: `\"`, `\\`, `\n`, `\r`, `\t`, `\uNNNN` and `\UNNNNNNNN`.
+/// Template escapes such as `$${` are left for templates to render, as is a backslash before anything else.
+pub fn unescape(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('"') => decoded.push('"'),
+            Some('\\') => decoded.push('\\'),
+            Some('n') => decoded.push('\n'),
+            Some('r') => decoded.push('\r'),
+            Some('t') => decoded.push('\t'),
+            Some(unicode @ 'u') | Some(unicode @ 'U') => {
+                let length = if unicode == 'u' { 4 } else { 8 };
+                let digits: String = chars.clone().skip(1).take(length).collect();
+                match u32::from_str_radix(&digits, 16).ok().filter(|_| digits.len() == length).and_then(char::from_u32) {
+                    Some(character) => {
+                        decoded.push(character);
+                        chars.nth(length);
+                    },
+                    None => decoded.push('\\'),
+                }
+                continue;
+            },
+            _ => {
+                decoded.push('\\');
+                continue;
+            },
+        }
+        chars.next();
+    }
+    decoded
+}
+
+fn interpolation(i: &str) -> IResult<&str, Expression> {
+    delimited(
+        pair(tag("${"), opt(char('~'))),
+        expression,
+        preceded(multispace0, pair(opt(char('~')), char('}'))),
+    )(i)
+}
+
+fn template_part(i: &str) -> IResult<&str, TemplatePart> {
+    alt((
+        map(interpolation, TemplatePart::Interpolation),
+        map(template_literal, |text| TemplatePart::Literal(String::from(text))),
+    ))(i)
+}
+
+/// a quoted string, split into its literal text and interpolations
+pub fn template(i: &str) -> IResult<&str, Vec<TemplatePart>> {
+    let (rest, parts) = delimited(char('\"'), many0(template_part), char('\"'))(i)?;
+
+    let merged = parts.into_iter().fold(Vec::new(), |mut merged: Vec<TemplatePart>, part| {
+        match (merged.last_mut(), part) {
+            (Some(TemplatePart::Literal(text)), TemplatePart::Literal(more)) => text.push_str(&more),
+            (_, part) => merged.push(part),
+        }
+        merged
+    });
+    let decoded = merged.into_iter().map(|part| match part {
+        TemplatePart::Literal(text) => TemplatePart::Literal(unescape(&text)),
+        part => part,
+    }).collect();
+
+    Ok((rest, decoded))
+}
+
+fn quoted(i: &str) -> IResult<&str, Expression> {
+    let (rest, parts) = template(i)?;
+
+    let expression = match parts.as_slice() {
+        [] => Expression::Str(String::new()),
+        [TemplatePart::Literal(text)] => Expression::Str(text.to_owned()),
+        _ => Expression::Template(parts),
+    };
+    Ok((rest, expression))
+}
+
+fn arguments(i: &str) -> IResult<&str, (Vec<Expression>, bool)> {
+    delimited(
+        char('('),
+        pair(
+            separated_list0(preceded(multispace0, char(',')), expression),
+            map(opt(preceded(multispace0, tag("..."))), |expand| expand.is_some()),
+        ),
+        preceded(multispace0, pair(opt(char(',')), preceded(multispace0, char(')')))),
+    )(i)
+}
+
+/// a function call, a keyword or the root of a traversal
+fn named(i: &str) -> IResult<&str, Expression> {
+    let (rest, name) = identifier(i)?;
+
+    if let Ok((rest, (args, expand_final))) = arguments(rest) {
+        return Ok((rest, Expression::FunctionCall(BuiltInFunction { name: String::from(name), args, expand_final })));
+    }
+
+    let expression = match name {
+        "true" => Expression::Boolean(true),
+        "false" => Expression::Boolean(false),
+        "null" => Expression::Null,
+        _ => Expression::Variable(Traversal::new(name, vec![])),
+    };
+    Ok((rest, expression))
+}
+
+fn tuple_items(i: &str) -> IResult<&str, Vec<Expression>> {
+    delimited(
+        char('['),
+        separated_list0(preceded(multispace0, char(',')), expression),
+        preceded(multispace0, pair(opt(char(',')), preceded(multispace0, char(']')))),
+    )(i)
+}
+
+/// object items may be separated by commas or newlines
+fn object_items(i: &str) -> IResult<&str, Vec<(Expression, Expression)>> {
+    delimited(
+        char('{'),
+        many0(
+            terminated(
+                pair(expression, preceded(preceded(multispace0, one_of("=:")), expression)),
+                opt(preceded(multispace0, char(','))),
+            )
+        ),
+        preceded(multispace0, char('}')),
+    )(i)
+}
+
+fn primary(i: &str) -> IResult<&str, Expression> {
+    preceded(
+        multispace0,
+        alt((
+            map(number, Expression::Num),
+            quoted,
+            named,
+            map(delimited(char('('), expression, preceded(multispace0, char(')'))), |inner| Expression::Parenthesized(Box::new(inner))),
+            map(tuple_items, Expression::Tuple),
+            map(object_items, Expression::Object),
+        )),
+    )(i)
+}
+
+fn traversal_step(i: &str) -> IResult<&str, TraversalStep> {
+    alt((
+        value(TraversalStep::AttributeSplat, tag(".*")),
+        value(TraversalStep::FullSplat, tag("[*]")),
+        // legacy index syntax, `aws_instance.web.0.id`
+        map(preceded(char('.'), digit1), |digits: &str| TraversalStep::Index(Expression::Num(digits.parse().unwrap_or_default()))),
+        map(preceded(char('.'), identifier), |name| TraversalStep::Attribute(String::from(name))),
+        map(delimited(char('['), expression, preceded(multispace0, char(']'))), TraversalStep::Index),
+    ))(i)
+}
+
+fn postfix(i: &str) -> IResult<&str, Expression> {
+    let (rest, (target, steps)) = pair(primary, many0(traversal_step))(i)?;
+
+    let expression = match target {
+        _ if steps.is_empty() => target,
+        Expression::Variable(mut traversal) => {
+            traversal.steps.extend(steps);
+            Expression::Variable(traversal)
+        },
+        target => Expression::Access { target: Box::new(target), steps },
+    };
+    Ok((rest, expression))
+}
+
+fn unary(i: &str) -> IResult<&str, Expression> {
+    let (rest, operator) = opt(preceded(
+        multispace0,
+        alt((
+            value(UnaryOperator::Not, terminated(char('!'), not(char('=')))),
+            value(UnaryOperator::Negate, char('-')),
+        )),
+    ))(i)?;
+
+    match operator {
+        None => postfix(i),
+        Some(operator) => {
+            let (rest, operand) = unary(rest)?;
+
+            let expression = match (operator, operand) {
+                (UnaryOperator::Negate, Expression::Num(number)) => Expression::Num(-number),
+                (operator, operand) => Expression::Unary { operator, operand: Box::new(operand) },
+            };
+            Ok((rest, expression))
+        },
+    }
+}
+
+/// parse a left associative chain of `operand (operator operand)*`.
+/// Longer operators must come before their prefixes, eg. `<=` before `<`.
+/// An operator with no operand after it ends the chain before the operator, leaving it for the caller.
+fn binary<'a>(i: &'a str, operators: &[(&str, BinaryOperator)], operand: Operand) -> IResult<&'a str, Expression> {
+    let (mut rest, mut left) = operand(i)?;
+
+    'chain: loop {
+        let (after_space, _) = multispace0(rest)?;
+        for (symbol, operator) in operators {
+            if let Ok((after_operator, _)) = tag::<_, _, (&str, ErrorKind)>(*symbol)(after_space) {
+                let (after_right, right) = match operand(after_operator) {
+                    Ok(parsed) => parsed,
+                    Err(nom::Err::Incomplete(needed)) => return Err(nom::Err::Incomplete(needed)),
+                    Err(_) => return Ok((rest, left)),
+                };
+                left = Expression::Binary { operator: *operator, left: Box::new(left), right: Box::new(right) };
+                rest = after_right;
+                continue 'chain;
+            }
+        }
+        return Ok((rest, left));
+    }
+}
+
+fn multiplicative(i: &str) -> IResult<&str, Expression> {
+    binary(i, &[("*", BinaryOperator::Multiply), ("/", BinaryOperator::Divide), ("%", BinaryOperator::Modulo)], unary)
+}
+
+fn additive(i: &str) -> IResult<&str, Expression> {
+    binary(i, &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)], multiplicative)
+}
+
+fn comparison(i: &str) -> IResult<&str, Expression> {
+    binary(i, &[
+        ("<=", BinaryOperator::LessOrEqual),
+        (">=", BinaryOperator::GreaterOrEqual),
+        ("<", BinaryOperator::Less),
+        (">", BinaryOperator::Greater),
+    ], additive)
+}
+
+fn equality(i: &str) -> IResult<&str, Expression> {
+    binary(i, &[("==", BinaryOperator::Equal), ("!=", BinaryOperator::NotEqual)], comparison)
+}
+
+fn and(i: &str) -> IResult<&str, Expression> {
+    binary(i, &[("&&", BinaryOperator::And)], equality)
+}
+
+fn or(i: &str) -> IResult<&str, Expression> {
+    binary(i, &[("||", BinaryOperator::Or)], and)
+}
+
+/// parse an HCL2 expression, the lowest precedence being the conditional `a ? b : c`
+pub fn expression(i: &str) -> IResult<&str, Expression> {
+    let (rest, condition) = or(i)?;
+
+    match preceded(multispace0, char::<_, (&str, ErrorKind)>('?'))(rest) {
+        Ok((rest, _)) => {
+            let (rest, true_result) = expression(rest)?;
+            let (rest, _) = preceded(multispace0, char(':'))(rest)?;
+            let (rest, false_result) = expression(rest)?;
+
+            Ok((rest, Expression::Conditional {
+                condition: Box::new(condition),
+                true_result: Box::new(true_result),
+                false_result: Box::new(false_result),
+            }))
+        },
+        Err(_) => Ok((rest, condition)),
+    }
+}
+
+/// parse a quoted string containing at least one interpolation.
+/// Strings without interpolations are left for the plain string parser.
+pub fn template_string(i: &str) -> IResult<&str, TemplateString> {
+    let (rest, mut parts) = template(i)?;
+
+    let template_string = match parts.as_slice() {
+        [TemplatePart::Interpolation(_)] => match parts.remove(0) {
+            TemplatePart::Interpolation(expression) => TemplateString::from_expression(expression),
+            TemplatePart::Literal(_) => unreachable!(),
+        },
+        _ if parts.iter().any(|part| matches!(part, TemplatePart::Interpolation(_))) => TemplateString::Template(parts),
+        _ => return Err(nom::Err::Error(make_error(i, ErrorKind::Tag))),
+    };
+    Ok((rest, template_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::template_string::Reference;
+
+    fn variable(root: &str, attributes: &[&str]) -> Expression {
+        Expression::Variable(Traversal::new(root, attributes.iter().map(|name| TraversalStep::Attribute(String::from(*name))).collect()))
+    }
+
+    #[test]
+    fn nested_function_calls() {
+        let data = r#""${md5(file("default-config/cpsc-vmware-config.json"))}""#;
+        let expected = TemplateString::BuiltInFunction(Box::new(BuiltInFunction {
+            name: String::from("md5"),
+            args: vec![Expression::FunctionCall(BuiltInFunction {
+                name: String::from("file"),
+                args: vec![Expression::Str(String::from("default-config/cpsc-vmware-config.json"))],
+                expand_final: false,
+            })],
+            expand_final: false,
+        }));
+
+        assert_eq!(template_string(data), Ok(("", expected)))
+    }
+
+    #[test]
+    fn operator_precedence() {
+        let (rest, result) = expression("var.a + 2 * var.b >= 10 && !var.enabled").unwrap();
+
+        let expected = Expression::Binary {
+            operator: BinaryOperator::And,
+            left: Box::new(Expression::Binary {
+                operator: BinaryOperator::GreaterOrEqual,
+                left: Box::new(Expression::Binary {
+                    operator: BinaryOperator::Add,
+                    left: Box::new(variable("var", &["a"])),
+                    right: Box::new(Expression::Binary {
+                        operator: BinaryOperator::Multiply,
+                        left: Box::new(Expression::Num(2.0)),
+                        right: Box::new(variable("var", &["b"])),
+                    }),
+                }),
+                right: Box::new(Expression::Num(10.0)),
+            }),
+            right: Box::new(Expression::Unary { operator: UnaryOperator::Not, operand: Box::new(variable("var", &["enabled"])) }),
+        };
+        assert_eq!((rest, result), ("", expected))
+    }
+
+    #[test]
+    fn operators_with_no_right_operand_backtrack() {
+        assert_eq!(expression("var.a -"), Ok((" -", variable("var", &["a"]))));
+        assert_eq!(expression("var.a * 2 *)"), Ok((" *)", Expression::Binary {
+            operator: BinaryOperator::Multiply,
+            left: Box::new(variable("var", &["a"])),
+            right: Box::new(Expression::Num(2.0)),
+        })));
+        assert_eq!(expression("1 + 2 >"), Ok((" >", Expression::Binary {
+            operator: BinaryOperator::Add,
+            left: Box::new(Expression::Num(1.0)),
+            right: Box::new(Expression::Num(2.0)),
+        })));
+    }
+
+    #[test]
+    fn conditional() {
+        let data = r#""${var.environment == "prod" ? 3 : 1}""#;
+        let expected = TemplateString::Expression(Box::new(Expression::Conditional {
+            condition: Box::new(Expression::Binary {
+                operator: BinaryOperator::Equal,
+                left: Box::new(variable("var", &["environment"])),
+                right: Box::new(Expression::Str(String::from("prod"))),
+            }),
+            true_result: Box::new(Expression::Num(3.0)),
+            false_result: Box::new(Expression::Num(1.0)),
+        }));
+
+        assert_eq!(template_string(data), Ok(("", expected)))
+    }
+
+    #[test]
+    fn index_and_splat() {
+        let (_, result) = expression("aws_subnet.private.*.id[0]").unwrap();
+        let expected = Expression::Variable(Traversal::new("aws_subnet", vec![
+            TraversalStep::Attribute(String::from("private")),
+            TraversalStep::AttributeSplat,
+            TraversalStep::Attribute(String::from("id")),
+            TraversalStep::Index(Expression::Num(0.0)),
+        ]));
+        assert_eq!(result, expected);
+
+        let (_, result) = expression("element(var.subnets, count.index)[*].cidr").unwrap();
+        assert_eq!(result.to_string(), "element(var.subnets, count.index)[*].cidr");
+    }
+
+    #[test]
+    fn template_with_several_interpolations() {
+        let data = r#""service/${aws_ecs_cluster.discovery_platform-s-cluster.name}/${aws_ecs_service.discovery_collector-service.name}""#;
+        let expected = TemplateString::Template(vec![
+            TemplatePart::Literal(String::from("service/")),
+            TemplatePart::Interpolation(variable("aws_ecs_cluster", &["discovery_platform-s-cluster", "name"])),
+            TemplatePart::Literal(String::from("/")),
+            TemplatePart::Interpolation(variable("aws_ecs_service", &["discovery_collector-service", "name"])),
+        ]);
+
+        assert_eq!(template_string(data), Ok(("", expected)))
+    }
+
+    #[test]
+    fn template_decodes_escapes() {
+        let data = r#""{\"arn\":\"${aws_sqs_queue.dlq.arn}\"} costs $5 $${literal}""#;
+        let (rest, result) = template_string(data).unwrap();
+
+        assert_eq!(rest, "");
+        // the template's own escapes are kept, so it still reads as the same template
+        assert_eq!(result.to_string(), r#"{"arn":"${aws_sqs_queue.dlq.arn}"} costs $5 $${literal}"#);
+        // and rendered back into HCL, its quotes are escaped again
+        assert_eq!(result.to_expression().to_string(), data);
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        assert_eq!(unescape(r#"a\"b"#), "a\"b");
+        assert_eq!(unescape(r#"c:\\dir\\"#), "c:\\dir\\");
+        assert_eq!(unescape(r#"x\ty\nz\u00e9\U0001F600"#), "x\ty\nz\u{e9}\u{1F600}");
+        assert_eq!(unescape(r#"\u12 \q"#), r#"\u12 \q"#);
+        assert_eq!(expression(r#""say \"hi\"""#), Ok(("", Expression::Str(String::from("say \"hi\"")))));
+    }
+
+    #[test]
+    fn strings_without_interpolations_are_not_templates() {
+        assert!(template_string(r#""arn:aws:sqs:us-east-1:123456789012:queue""#).is_err());
+        assert!(template_string(r#""$${not_interpolated}""#).is_err());
+    }
+
+    #[test]
+    fn collections_and_expansion() {
+        let (rest, result) = expression(r#"merge(local.tags, { Name = "queue", "Team": var.team }, [1, 2.5, -3]...)"#).unwrap();
+
+        assert_eq!(rest, "");
+        assert_eq!(result.to_string(), r#"merge(local.tags, {Name = "queue", "Team" = var.team}, [1, 2.5, -3]...)"#)
+    }
+
+    #[test]
+    fn traversal_references() {
+        let reference = |data: &str| match expression(data) {
+            Ok((_, Expression::Variable(traversal))) => traversal.reference(),
+            _ => None,
+        };
+
+        assert_eq!(reference("aws_iam_role.foo.arn"), Some(Reference::Resource {
+            resource_type: String::from("aws_iam_role"),
+            name: String::from("foo"),
+            steps: vec![TraversalStep::Attribute(String::from("arn"))],
+            module_path: vec![],
+        }));
+        assert_eq!(reference("module.network.subnet_ids[0]"), Some(Reference::Module {
+            name: String::from("network"),
+            output: Some(String::from("subnet_ids")),
+            steps: vec![TraversalStep::Index(Expression::Num(0.0))],
+        }));
+        assert_eq!(reference("data.aws_caller_identity.current.account_id").map(|r| r.address()), Some(String::from("data.aws_caller_identity.current")));
+        assert_eq!(reference("count.index"), None);
+        assert_eq!(reference("data.aws_caller_identity"), None);
+    }
+}
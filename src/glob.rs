@@ -0,0 +1,134 @@
+//! Shell style glob matching, for choosing which files to read.
+//! - `*` matches any run of characters within one path segment
+//! - `**` matches any run of characters, including `/`, so `**/` matches any number of directories
+//! - `?` matches any single character other than `/`
+//! - `[abc]`, `[a-z]` and `[!abc]` match one character from, or not from, a set
+//!
+//! A pattern without a `/` is matched against the last segment of the path only,
+//! so `*.tf` matches `main.tf` as well as `modules/queue/main.tf`.
+
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let path = path.trim_start_matches("./");
+
+    if pattern.contains('/') {
+        matches(&pattern.chars().collect::<Vec<char>>(), &path.chars().collect::<Vec<char>>())
+    } else {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        matches(&pattern.chars().collect::<Vec<char>>(), &file_name.chars().collect::<Vec<char>>())
+    }
+}
+
+/// match the whole of a value, such as an ARN, rather than a path's file name. `*` still stops at a `/`,
+/// so `arn:aws:s3:::logs/*` matches the objects at the top of the bucket and `arn:aws:s3:::logs/**` all of them.
+pub fn glob_match_text(pattern: &str, text: &str) -> bool {
+    matches(&pattern.chars().collect::<Vec<char>>(), &text.chars().collect::<Vec<char>>())
+}
+
+fn matches(pattern: &[char], text: &[char]) -> bool {
+    // whether the pattern from each position matches the text from each position, remembered so a pattern
+    // of many stars, eg. `**a**a**a**b`, takes polynomial rather than exponential time
+    let mut memo = vec![None; (pattern.len() + 1) * (text.len() + 1)];
+    matches_from(pattern, text, 0, 0, &mut memo)
+}
+
+fn matches_from(pattern: &[char], text: &[char], p: usize, t: usize, memo: &mut [Option<bool>]) -> bool {
+    let key = p * (text.len() + 1) + t;
+    if let Some(matched) = memo[key] {
+        return matched;
+    }
+
+    let rest = &text[t..];
+    let matched = match pattern.get(p) {
+        None => rest.is_empty(),
+        Some('*') if pattern.get(p + 1) == Some(&'*') && pattern.get(p + 2) == Some(&'/') => {
+            // `**/` matches whole directories, or none at all
+            (0..=rest.len())
+                .filter(|skip| *skip == 0 || rest[skip - 1] == '/')
+                .any(|skip| matches_from(pattern, text, p + 3, t + skip, memo))
+        },
+        Some('*') if pattern.get(p + 1) == Some(&'*') => (0..=rest.len()).any(|skip| matches_from(pattern, text, p + 2, t + skip, memo)),
+        Some('*') => {
+            let segment_len = rest.iter().position(|c| *c == '/').unwrap_or(rest.len());
+            (0..=segment_len).any(|skip| matches_from(pattern, text, p + 1, t + skip, memo))
+        },
+        Some('?') => !rest.is_empty() && rest[0] != '/' && matches_from(pattern, text, p + 1, t + 1, memo),
+        Some('[') => match (character_class(&pattern[p + 1..]), rest.first()) {
+            (Some((matched, class_len)), Some(c)) if *c != '/' => matched(*c) && matches_from(pattern, text, p + class_len + 1, t + 1, memo),
+            (None, Some('[')) => matches_from(pattern, text, p + 1, t + 1, memo),
+            _ => false,
+        },
+        Some(c) => rest.first() == Some(c) && matches_from(pattern, text, p + 1, t + 1, memo),
+    };
+
+    memo[key] = Some(matched);
+    matched
+}
+
+/// parse the class following a `[`, returning a matcher and the length of the class including its `]`
+fn character_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool, usize)> {
+    let negated = pattern.first() == Some(&'!');
+    let start = if negated { 1 } else { 0 };
+    // a `]` straight after the `[` is part of the set
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|c| *c == ']')?;
+
+    let set: Vec<char> = pattern[start..end].to_vec();
+    let matched = move |c: char| {
+        let mut found = false;
+        let mut i = 0;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                found |= set[i] <= c && c <= set[i + 2];
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+
+    Some((matched, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name_patterns() {
+        assert!(glob_match("*.tf", "main.tf"));
+        assert!(glob_match("*.tf", "./modules/queue/main.tf"));
+        assert!(!glob_match("*.tf", "main.tf.json"));
+        assert!(glob_match("discovery?.tf", "discovery1.tf"));
+        assert!(glob_match("[!.]*.tfvars", "prod.tfvars"));
+        assert!(!glob_match("[!.]*.tfvars", ".hidden.tfvars"));
+        assert!(glob_match("v[0-9].tf", "v7.tf"));
+    }
+
+    #[test]
+    fn path_patterns() {
+        assert!(glob_match("modules/*/main.tf", "modules/queue/main.tf"));
+        assert!(!glob_match("modules/*/main.tf", "modules/queue/nested/main.tf"));
+        assert!(glob_match("modules/**/main.tf", "modules/queue/nested/main.tf"));
+        assert!(glob_match("**/main.tf", "main.tf"));
+        assert!(!glob_match("**/main.tf", "modules/domain.tf"));
+        assert!(glob_match("**/test/**", "modules/test/fixtures/main.tf"));
+        assert!(!glob_match("**/test/**", "modules/testing/main.tf"));
+    }
+
+    #[test]
+    fn text_patterns() {
+        assert!(glob_match_text("arn:aws:s3:::logs/*", "arn:aws:s3:::logs/today"));
+        assert!(!glob_match_text("arn:aws:s3:::logs/*", "arn:aws:s3:::logs/2020/today"));
+        assert!(glob_match_text("arn:aws:s3:::logs/**", "arn:aws:s3:::logs/2020/today"));
+        assert!(!glob_match_text("*.tf", "modules/main.tf"));
+    }
+
+    #[test]
+    fn many_stars_match_quickly() {
+        let pattern = format!("{}b", "**a".repeat(10));
+        assert!(!glob_match_text(&pattern, &"a".repeat(200)));
+        assert!(glob_match_text(&pattern, &format!("{}b", "a".repeat(200))));
+        assert!(!glob_match_text(&"*a".repeat(10), &format!("{}/a", "a".repeat(200))));
+    }
+}
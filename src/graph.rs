@@ -0,0 +1,323 @@
+//! InfrastructureGraph
+//! - the resources read, and the relationships found between them, as a graph which every output is rendered from
+//! - nodes are keyed by the address of the block they were built from, eg. `aws_sqs_queue.queue`, and keep the order they were added in
+//! - edges are typed, labelled, and say which block and attribute they were found in
+//! - edges may point at addresses with no node, such as a bucket named by an ARN but not declared in any template
+//! - adjacency indexes give the edges leaving and arriving at an address, they are rebuilt when a graph is deserialised
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::structs::attributes::Attribute;
+use crate::structs::span::Span;
+use crate::structs::terraform_block::{ TerraformBlock, module_prefix };
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Node {
+    pub address: String,
+    /// the kind of block, eg. `resource`, `data` or `variable`
+    pub block_type: String,
+    /// the type shown for the node: the resource or data source type, or for other blocks their block type
+    pub node_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// the module the block was expanded from, eg. `module.queues`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub planned_actions: Vec<String>,
+    pub attributes: Vec<Attribute>,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
+}
+
+impl Node {
+    pub fn from_block(block: &TerraformBlock) -> Node {
+        let (node_type, name, module, planned_actions) = match block {
+            TerraformBlock::NoIdentifiers(resource) => (resource.block_type.to_string(), None, None, vec![]),
+            TerraformBlock::WithOneIdentifier(resource) => (resource.block_type.to_string(), Some(resource.first_identifier.to_string()), None, vec![]),
+            TerraformBlock::WithTwoIdentifiers(resource) => {
+                let module = match resource.module_path.is_empty() {
+                    true => None,
+                    false => Some(module_prefix(&resource.module_path).trim_end_matches('.').to_string()),
+                };
+                (resource.first_identifier.to_string(), Some(resource.second_identifier.to_string()), module, resource.planned_actions.clone())
+            },
+        };
+
+        Node {
+            address: block.address(),
+            block_type: block.block_type().to_string(),
+            node_type,
+            name,
+            module,
+            planned_actions,
+            attributes: block.attributes().clone(),
+            span: block.span().clone(),
+        }
+    }
+
+    /// the id the block's policy results are keyed by, as given by `TerraformBlock::get_id`, eg. `aws_sqs_queue_queue`
+    pub fn id(&self) -> String {
+        let module = self.module.as_ref().map(|module| format!("{}.", module)).unwrap_or_default();
+        match &self.name {
+            Some(name) => format!("{}{}_{}", module, self.node_type, name),
+            None => self.node_type.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    /// a pair of resources connected by a relationship spec, eg. the queue and function of an event source mapping
+    Relationship,
+    /// a resource an IAM policy statement grants access to, labelled with the actions granted
+    Permission,
+    /// a resource referred to by another, by a CloudFormation `Ref` or as recorded in a plan or state
+    Reference,
+}
+
+/// where an edge was found
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Provenance {
+    /// the address of the block the edge was found in
+    pub block: String,
+    /// the top level attribute of that block, when the edge came from one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<String>,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
+}
+
+impl Provenance {
+    pub fn new(block: &str, attribute: Option<&Attribute>) -> Provenance {
+        Provenance {
+            block: block.to_string(),
+            attribute: attribute.map(|attr| attr.key.to_string()),
+            span: attribute.map(|attr| attr.span.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    pub label: String,
+    pub kind: EdgeKind,
+    pub provenance: Provenance,
+}
+
+/// the object properties a relationship's label names: each action of a permission, eg. `['sqs:SendMessage','sqs:DeleteMessage']`,
+/// or the label itself
+pub fn label_names(label: &str) -> Vec<String> {
+    let label = label.trim();
+    if label.starts_with('[') && label.ends_with(']') {
+        label[1..label.len() - 1].split(',')
+            .map(|name| name.trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    } else if label.is_empty() {
+        vec![]
+    } else {
+        vec![label.to_string()]
+    }
+}
+
+pub fn kind_name(kind: EdgeKind) -> &'static str {
+    match kind {
+        EdgeKind::Relationship => "relationship",
+        EdgeKind::Permission => "permission",
+        EdgeKind::Reference => "reference",
+    }
+}
+
+/// the serialised form of a graph, its indexes are rebuilt from it
+#[derive(Deserialize)]
+struct GraphParts {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(from = "GraphParts")]
+pub struct InfrastructureGraph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    #[serde(skip)]
+    node_index: HashMap<String, usize>,
+    #[serde(skip)]
+    outgoing: HashMap<String, Vec<usize>>,
+    #[serde(skip)]
+    incoming: HashMap<String, Vec<usize>>,
+}
+
+impl From<GraphParts> for InfrastructureGraph {
+    fn from(parts: GraphParts) -> InfrastructureGraph {
+        let mut graph = InfrastructureGraph::new();
+        parts.nodes.into_iter().for_each(|node| graph.add_node(node));
+        parts.edges.into_iter().for_each(|edge| graph.add_edge(edge));
+        graph
+    }
+}
+
+impl InfrastructureGraph {
+    pub fn new() -> InfrastructureGraph {
+        InfrastructureGraph::default()
+    }
+
+    /// a graph of the blocks, without any edges
+    pub fn from_blocks(blocks: &[TerraformBlock]) -> InfrastructureGraph {
+        let mut graph = InfrastructureGraph::new();
+        blocks.iter().for_each(|block| graph.add_node(Node::from_block(block)));
+        graph
+    }
+
+    /// add a node, replacing any with the same address but keeping its place
+    pub fn add_node(&mut self, node: Node) {
+        match self.node_index.get(&node.address) {
+            Some(&position) => self.nodes[position] = node,
+            None => {
+                self.node_index.insert(node.address.to_string(), self.nodes.len());
+                self.nodes.push(node);
+            },
+        }
+    }
+
+    /// add an edge, unless the same one has already been found
+    pub fn add_edge(&mut self, edge: Edge) {
+        let duplicate = self.outgoing(&edge.source).iter()
+            .any(|found| found.target == edge.target && found.label == edge.label && found.kind == edge.kind);
+        if duplicate {
+            return;
+        }
+
+        let position = self.edges.len();
+        self.outgoing.entry(edge.source.to_string()).or_default().push(position);
+        self.incoming.entry(edge.target.to_string()).or_default().push(position);
+        self.edges.push(edge);
+    }
+
+    pub fn node(&self, address: &str) -> Option<&Node> {
+        self.node_index.get(address).map(|&position| &self.nodes[position])
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// the edges whose source is `address`, in the order they were found
+    pub fn outgoing(&self, address: &str) -> Vec<&Edge> {
+        self.indexed(&self.outgoing, address)
+    }
+
+    /// the edges whose target is `address`, in the order they were found
+    pub fn incoming(&self, address: &str) -> Vec<&Edge> {
+        self.indexed(&self.incoming, address)
+    }
+
+    fn indexed(&self, index: &HashMap<String, Vec<usize>>, address: &str) -> Vec<&Edge> {
+        index.get(address).into_iter().flatten().map(|&position| &self.edges[position]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::attributes::AttributeType;
+    use crate::structs::terraform_block::TerraformBlockWithTwoIdentifiers;
+
+    fn resource(resource_type: &str, name: &str) -> TerraformBlock {
+        TerraformBlock::WithTwoIdentifiers(TerraformBlockWithTwoIdentifiers {
+            block_type: String::from("resource"),
+            first_identifier: resource_type.to_string(),
+            second_identifier: name.to_string(),
+            attributes: vec![
+                Attribute { key: String::from("name"), value: AttributeType::Str(name.to_string()), span: Span::default() },
+            ],
+            span: Span::default(),
+            module_path: vec![],
+            planned_actions: vec![],
+            references: vec![],
+        })
+    }
+
+    fn edge(source: &str, target: &str, kind: EdgeKind) -> Edge {
+        Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            label: String::from(""),
+            kind,
+            provenance: Provenance::new(source, None),
+        }
+    }
+
+    fn example() -> InfrastructureGraph {
+        let mut graph = InfrastructureGraph::from_blocks(&[
+            resource("aws_sqs_queue", "queue"),
+            resource("aws_lambda_function", "worker"),
+            resource("aws_lambda_event_source_mapping", "mapping"),
+        ]);
+        graph.add_edge(edge("aws_sqs_queue.queue", "aws_lambda_function.worker", EdgeKind::Relationship));
+        graph.add_edge(edge("aws_lambda_event_source_mapping.mapping", "aws_sqs_queue.queue", EdgeKind::Reference));
+        graph.add_edge(edge("aws_lambda_event_source_mapping.mapping", "aws_lambda_function.worker", EdgeKind::Reference));
+        graph
+    }
+
+    #[test]
+    fn nodes_are_keyed_by_address() {
+        let mut graph = example();
+        let node = graph.node("aws_lambda_function.worker").unwrap();
+        assert_eq!(node.node_type, "aws_lambda_function");
+        assert_eq!(node.name, Some(String::from("worker")));
+        assert_eq!(node.id(), resource("aws_lambda_function", "worker").get_id());
+
+        // a node added again replaces the first in place
+        let mut replacement = Node::from_block(&resource("aws_sqs_queue", "queue"));
+        replacement.planned_actions = vec![String::from("create")];
+        graph.add_node(replacement);
+        let addresses: Vec<&str> = graph.nodes().iter().map(|node| node.address.as_str()).collect();
+        assert_eq!(addresses, vec!["aws_sqs_queue.queue", "aws_lambda_function.worker", "aws_lambda_event_source_mapping.mapping"]);
+        assert_eq!(graph.nodes()[0].planned_actions, vec!["create"]);
+        assert_eq!(graph.node("aws_s3_bucket.missing"), None);
+    }
+
+    #[test]
+    fn edges_are_indexed_both_ways() {
+        let mut graph = example();
+        graph.add_edge(edge("aws_sqs_queue.queue", "aws_lambda_function.worker", EdgeKind::Relationship));
+        assert_eq!(graph.edges().len(), 3);
+
+        let into_worker: Vec<&str> = graph.incoming("aws_lambda_function.worker").iter().map(|edge| edge.source.as_str()).collect();
+        assert_eq!(into_worker, vec!["aws_sqs_queue.queue", "aws_lambda_event_source_mapping.mapping"]);
+
+        let from_mapping: Vec<EdgeKind> = graph.outgoing("aws_lambda_event_source_mapping.mapping").iter().map(|edge| edge.kind).collect();
+        assert_eq!(from_mapping, vec![EdgeKind::Reference, EdgeKind::Reference]);
+        assert!(graph.outgoing("aws_lambda_function.worker").is_empty());
+    }
+
+    #[test]
+    fn serialisation_round_trips() {
+        let graph = example();
+        let json = serde_json::to_string(&graph).unwrap();
+        assert!(json.contains(r#""kind":"relationship""#));
+        assert!(!json.contains("outgoing"));
+
+        let read: InfrastructureGraph = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, graph);
+        assert_eq!(read.incoming("aws_sqs_queue.queue").len(), 1);
+    }
+
+    #[test]
+    fn edges_are_named_by_kind_and_label() {
+        assert_eq!(kind_name(EdgeKind::Permission), "permission");
+        assert_eq!(label_names("['sqs:SendMessage','sqs:DeleteMessage']"), vec!["sqs:SendMessage", "sqs:DeleteMessage"]);
+        assert_eq!(label_names("subscribes"), vec!["subscribes"]);
+        assert_eq!(label_names(""), Vec::<String>::new());
+    }
+}
@@ -1,8 +1,18 @@
 // https://stackoverflow.com/questions/57756927/rust-modules-confusion-when-there-is-main-rs-and-lib-rs
 pub mod cloud_template_parser;
 pub mod terraform;
+pub mod terraform_json;
+pub mod cloudformation;
+pub mod terraform_plan;
 pub mod json;
+pub mod expression;
 pub mod visitors;
+pub mod graph;
+pub mod exporters;
 pub mod relationship_finders;
+pub mod reporters;
 pub mod structs;
-pub mod policy_evaluator;
\ No newline at end of file
+pub mod policy_evaluator;
+pub mod resolver;
+pub mod module_expander;
+pub mod glob;
\ No newline at end of file
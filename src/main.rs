@@ -2,20 +2,27 @@
 use structopt::StructOpt;
 use std::collections::HashMap;
 extern crate serde_yaml;
-use serde::{Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::{ Map, Value };
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{ Path, PathBuf };
 
-use std::time::{Instant};
+use std::time::{ Duration, Instant };
 use exitfailure::ExitFailure;
 
-use rust_nom_json::structs::policies::Policies;
+use rust_nom_json::structs::policies::{ Policies, Severity };
 use rust_nom_json::*;
 use rust_nom_json::visitors::resource_visitor;
+use rust_nom_json::resolver::Resolver;
+use rust_nom_json::module_expander::ModuleExpander;
 
 use rust_nom_json::visitors::relationship_visitor::{Relationship};
+use rust_nom_json::visitors::json_visitor::JsonVisitor;
+use rust_nom_json::graph::InfrastructureGraph;
+use rust_nom_json::structs::terraform_block::TerraformBlock;
+use rust_nom_json::relationship_finders::tf_block_query::tf_block_query::{ TFQueryResult, jmespath_query };
 
 mod terraform;
 
@@ -26,53 +33,357 @@ mod terraform;
 // [√] use that trait to mock responses, enabling TDD for applications with side effects  
 // [√] make sure that errors are transformed into some kind of standard Error
 
-/// Search for a pattern in a file and display the lines that contain it.
+/// Parse cloud templates into a graph of their resources and the relationships between them,
+/// evaluate policies against it and write it out for other tools.
 #[derive(StructOpt)]
-struct Cli {
-    /// The path to the file to read
-    #[structopt(parse(from_os_str))]
-    path: std::path::PathBuf,
+#[structopt(name = "rust_nom_json")]
+enum Cli {
+    /// Write the resources read from the templates as JSON, defaults to stdout
+    Parse {
+        #[structopt(flatten)]
+        inputs: Inputs,
+        #[structopt(flatten)]
+        output: Output,
+    },
+    /// Write graph.json, the resources, their relationships and the policies they fail, for the front end
+    Graph {
+        #[structopt(flatten)]
+        inputs: Inputs,
+        #[structopt(flatten)]
+        specs: Specs,
+        #[structopt(flatten)]
+        output: Output,
+    },
+    /// Evaluate the policies against the resources and report those which fail. Exits 2 if any policy at or above
+    /// the severity failed and 3 if a template couldn't be read or parsed, as the policies may then have missed resources
+    Check {
+        /// the least severe failure which fails the check: low, medium, high or critical
+        #[structopt(long = "severity", default_value = "low")]
+        severity: Severity,
+        /// text, sarif for code scanning, junit for CI dashboards or markdown for pull requests
+        #[structopt(long = "format", default_value = "text")]
+        format: reporters::Format,
+        /// what links to templates in markdown start with, eg. https://github.com/org/repo/blob/main/
+        #[structopt(long = "link-base")]
+        link_base: Option<String>,
+        #[structopt(flatten)]
+        inputs: Inputs,
+        #[structopt(flatten)]
+        specs: Specs,
+        #[structopt(flatten)]
+        output: Output,
+    },
+    /// Write the value an expression selects from each resource, eg. `versioning.enabled`, as JSON keyed by address
+    Query {
+        /// a path through the resource's attributes, as used by policy filters
+        expression: String,
+        /// only query resources of this type, eg. aws_s3_bucket
+        #[structopt(long = "type")]
+        resource_type: Option<String>,
+        #[structopt(flatten)]
+        inputs: Inputs,
+        #[structopt(flatten)]
+        output: Output,
+    },
+    /// Write the graph in another format, for a triple store, Neo4j, a diagram or graph analysis tools
+    Export {
+        /// json, turtle, ntriples, nquads, sparql, cypher, neo4j-csv, dot, mermaid, graphml or gexf
+        #[structopt(long = "format", default_value = "json")]
+        format: exporters::Format,
+        /// put every triple of nquads or sparql output in the named graph of this environment, rather than one per template
+        #[structopt(long = "environment")]
+        environment: Option<String>,
+        /// the nquads output of the previous load, for sparql output to update the triple store from, without it each named graph is cleared and loaded again
+        #[structopt(long = "previous", parse(from_os_str))]
+        previous: Option<PathBuf>,
+        /// what to group the resources of a dot or mermaid diagram by: type, or network for their VPC and subnet
+        #[structopt(long = "cluster-by", default_value = "type")]
+        cluster_by: exporters::diagram::ClusterBy,
+        #[structopt(flatten)]
+        inputs: Inputs,
+        #[structopt(flatten)]
+        specs: Specs,
+        #[structopt(flatten)]
+        output: Output,
+    },
 }
 
-fn main() -> Result<(), ExitFailure> {
-    let start = Instant::now();
-    let args = Cli::from_args();
+/// the templates to read and the variable values to read them with
+#[derive(StructOpt)]
+struct Inputs {
+    /// The template files, or directories of them, to read. A file may also hold the output of `terraform show -json`
+    #[structopt(parse(from_os_str), required = true)]
+    paths: Vec<PathBuf>,
+    /// only read files in the directories given which match one of these globs, defaults to `*.tf`, `*.tfvars` and their `.json` forms
+    #[structopt(long = "include")]
+    include: Vec<String>,
+    /// skip files which match one of these globs
+    #[structopt(long = "exclude")]
+    exclude: Vec<String>,
+    /// .tfvars files to read variable values from, later files take precedence
+    #[structopt(long = "var-file", parse(from_os_str))]
+    var_files: Vec<PathBuf>,
+    /// a variable value, `name=value`, which takes precedence over any .tfvars file
+    #[structopt(long = "var")]
+    vars: Vec<String>,
+}
+
+/// where the relationship specs and policies come from
+#[derive(StructOpt)]
+struct Specs {
+    /// relationship spec files, read in turn with later specs for a resource type replacing earlier ones,
+    /// defaults to the specs built in from example_files/aws_relationships.yaml
+    #[structopt(long = "relationships", parse(from_os_str))]
+    relationships: Vec<PathBuf>,
+    /// policy files, whose policies are all evaluated, defaults to those built in from example_files/policies.yaml
+    #[structopt(long = "policies", parse(from_os_str))]
+    policies: Vec<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct Output {
+    /// the file to write to, or `-` for stdout. For neo4j-csv the directory to write its files to
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// print nothing but the diagnostics found reading the templates
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// also print how long reading the templates and writing the output took
+    #[structopt(short = "v", long = "verbose", conflicts_with = "quiet")]
+    verbose: bool,
+}
+
+/// the exit codes of `check`, besides 1 for errors such as a policy file which can't be read
+const EXIT_VIOLATIONS: i32 = 2;
+const EXIT_PARSE_ERRORS: i32 = 3;
+
+const DEFAULT_RELATIONSHIPS: &str = include_str!("../example_files/aws_relationships.yaml");
+const DEFAULT_POLICIES: &str = include_str!("../example_files/policies.yaml");
+
+fn read_yaml<T: DeserializeOwned>(path: &Path) -> Result<T, failure::Error> {
+    let file = File::open(path).map_err(|why| failure::format_err!("couldn't open {}: {}", path.display(), why))?;
+    serde_yaml::from_reader(file).map_err(|why| failure::format_err!("couldn't read {}: {}", path.display(), why))
+}
+
+impl Specs {
+    fn relationships(&self) -> Result<HashMap<String, Relationship>, failure::Error> {
+        if self.relationships.is_empty() {
+            return Ok(serde_yaml::from_str(DEFAULT_RELATIONSHIPS)?);
+        }
+        let mut specs = HashMap::new();
+        for path in &self.relationships {
+            let file_specs: HashMap<String, Relationship> = read_yaml(path)?;
+            specs.extend(file_specs);
+        }
+        Ok(specs)
+    }
+
+    /// the policies, checked as they're loaded so a mistake in one is an error rather than a policy which never fails
+    fn policies(&self) -> Result<Policies, failure::Error> {
+        if self.policies.is_empty() {
+            let policies: Policies = serde_yaml::from_str(DEFAULT_POLICIES)?;
+            policies.validate().map_err(|error| failure::format_err!("couldn't read the built in policies: {}", error))?;
+            return Ok(policies);
+        }
+        let mut policies = Policies { policies: vec![] };
+        for path in &self.policies {
+            let file_policies: Policies = read_yaml(path)?;
+            file_policies.validate().map_err(|error| failure::format_err!("couldn't read {}: {}", path.display(), error))?;
+            policies.policies.extend(file_policies.policies);
+        }
+        Ok(policies)
+    }
+}
 
-    let relationship_file = std::fs::File::open("./example_files/aws_relationships.yaml")?;
-    let aws_relationship_specs: HashMap<String, Relationship> = serde_yaml::from_reader(relationship_file)?;
-    // println!("Relationships YAML string: {:?}", aws_relationship_specs);
+impl Output {
+    fn status(&self, message: &str) {
+        if !self.quiet {
+            eprintln!("{}", message);
+        }
+    }
+
+    fn timing(&self, message: &str, duration: Duration) {
+        if self.verbose {
+            eprintln!("{} in: {:?}", message, duration);
+        }
+    }
+
+    /// write to the file asked for, or the default: a file name, or stdout when there's none
+    fn write(&self, rendered: exporters::Rendered, default: Option<&str>) -> Result<(), failure::Error> {
+        let path = self.output.clone().or_else(|| default.map(PathBuf::from));
+        let path = path.filter(|path| path.as_os_str() != "-");
+        match (rendered, path) {
+            (exporters::Rendered::File(contents), None) => std::io::stdout().write_all(contents.as_bytes())?,
+            (exporters::Rendered::File(contents), Some(path)) => {
+                write_file(&path, &contents)?;
+                self.status(&format!("successfully wrote to {}", path.display()));
+            },
+            (exporters::Rendered::Directory(_), None) => failure::bail!("this format writes several files, --output must name a directory for them"),
+            (exporters::Rendered::Directory(files), Some(path)) => {
+                std::fs::create_dir_all(&path)?;
+                for (name, contents) in files {
+                    write_file(&path.join(name), &contents)?;
+                }
+                self.status(&format!("successfully wrote to {}", path.display()));
+            },
+        }
+        Ok(())
+    }
+}
 
-    let policies_file = std::fs::File::open("./example_files/policies.yaml")?;
-    let policy_specs: Policies = serde_yaml::from_reader(policies_file)?;
-    // println!("Policies YAML string: {:?}", policy_specs);
+fn write_file(path: &Path, contents: &str) -> Result<(), failure::Error> {
+    let mut file = File::create(path).map_err(|why| failure::format_err!("couldn't create {}: {}", path.display(), why))?;
+    file.write_all(contents.as_bytes()).map_err(|why| failure::format_err!("couldn't write to {}: {}", path.display(), why))?;
+    Ok(())
+}
 
+/// read the templates, resolve their variables and expand their modules, printing any diagnostics on the way.
+/// Returns the resources and how many of the diagnostics were parse errors.
+fn read_resources(inputs: &Inputs) -> Result<(Vec<TerraformBlock>, usize), failure::Error> {
     let parser = cloud_template_parser::CloudTemplateParser::new();
-    let parsed_resources = parser.handle(args.path);
+    let resource_tree = parser.handle_paths(&inputs.paths, &inputs.include, &inputs.exclude)?;
+
+    for diagnostic in &resource_tree.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    let mut parse_errors = resource_tree.diagnostics.len();
+
+    let mut resolver = Resolver::new();
+    resolver.set_variables(resource_tree.variables);
+    for var_file in &inputs.var_files {
+        let (assignments, diagnostics) = parser.handle_tfvars(var_file.clone())?;
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+        parse_errors += diagnostics.len();
+        resolver.set_variables(assignments);
+    }
+    for var in &inputs.vars {
+        if let Err(diagnostic) = resolver.set_variable(var) {
+            eprintln!("{}", diagnostic);
+            parse_errors += 1;
+        }
+    }
+
+    let expansion = ModuleExpander::new(&parser).expand(resource_tree.resources, resolver);
+    for diagnostic in &expansion.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    parse_errors += expansion.diagnostics.len();
+    for reference in &expansion.unresolved {
+        eprintln!("{}", reference);
+    }
+
+    Ok((expansion.resources, parse_errors))
+}
+
+fn to_json(value: &Value) -> String {
+    let mut json = serde_json::to_string_pretty(value).unwrap();
+    json.push('\n');
+    json
+}
+
+fn parse(inputs: Inputs, output: Output) -> Result<(), failure::Error> {
+    let start = Instant::now();
+    let (resources, _) = read_resources(&inputs)?;
+    output.timing("Terraform parsed", start.elapsed());
+
+    let graph = InfrastructureGraph::from_blocks(&resources);
+    let visitor = JsonVisitor{};
+    let nodes: Vec<Value> = graph.nodes().iter().map(|node| visitor.visit_node(node)).collect();
+    output.write(exporters::Rendered::File(to_json(&Value::Array(nodes))), None)?;
+    output.timing("Resources written", start.elapsed());
+    Ok(())
+}
 
-    let json = resource_visitor::dispatch(&parsed_resources, aws_relationship_specs, policy_specs);
-    // // iterate over array, use match statement to get initial visitor right
-    // // then allow Visitor pattern to do the rest
-    let elapsed_before_printing = start.elapsed();
+fn export(format: exporters::Format, options: exporters::ExportOptions, inputs: Inputs, specs: Specs, output: Output) -> Result<(), failure::Error> {
+    let start = Instant::now();
+    let relationships = specs.relationships()?;
+    let policies = specs.policies()?;
+    let (resources, _) = read_resources(&inputs)?;
+    output.timing("Terraform parsed", start.elapsed());
 
-    let path = Path::new("graph.json");
-    let display = path.display();
+    let rendered = resource_visitor::dispatch(&resources, relationships, policies, format, &options);
+    output.write(rendered, Some(format.default_output()))?;
+    output.timing("Graph written", start.elapsed());
+    Ok(())
+}
 
-    let mut file = match File::create(&path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why),
-        Ok(file) => file,
+/// the code to exit with: 0 if the check passed, otherwise EXIT_VIOLATIONS or EXIT_PARSE_ERRORS
+fn check(severity: Severity, format: reporters::Format, link_base: Option<String>, inputs: Inputs, specs: Specs, output: Output) -> Result<i32, failure::Error> {
+    let start = Instant::now();
+    let relationships = specs.relationships()?;
+    let policies = specs.policies()?;
+    let (resources, parse_errors) = match read_resources(&inputs) {
+        Ok(read) => read,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            return Ok(EXIT_PARSE_ERRORS);
+        },
     };
+    output.timing("Terraform parsed", start.elapsed());
 
-    // Write json to file
-    match file.write_all(json.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        Ok(_) => println!("successfully wrote to {}", display),
+    let graph = resource_visitor::build_graph(&resources, relationships);
+    let policy_results = policy_evaluator::evaluate_graph(policies.clone(), &resources, &graph);
+    let report = reporters::Report::new(&policies, &resources, &policy_results);
+    let options = reporters::ReportOptions { threshold: severity, link_base };
+    output.write(exporters::Rendered::File(reporters::render(format, &report, &options)), None)?;
+    output.timing("Policies evaluated", start.elapsed());
+
+    if parse_errors > 0 {
+        output.status(&format!("{} parse errors, some resources may not have been checked", parse_errors));
+        Ok(EXIT_PARSE_ERRORS)
+    } else if report.at_or_above(severity) > 0 {
+        Ok(EXIT_VIOLATIONS)
+    } else {
+        Ok(0)
     }
-    // println!("json: {:?}", json);
-    // println!("ast: {:?}", parsed_resources);
+}
+
+fn query(expression: &str, resource_type: Option<String>, inputs: Inputs, output: Output) -> Result<(), failure::Error> {
+    let start = Instant::now();
+    let (resources, _) = read_resources(&inputs)?;
+    output.timing("Terraform parsed", start.elapsed());
+
+    let visitor = JsonVisitor{};
+    let mut values = Map::new();
+    for resource in &resources {
+        if let TerraformBlock::WithTwoIdentifiers(block) = resource {
+            if resource_type.as_ref().is_some_and(|resource_type| resource_type != &block.first_identifier) {
+                continue;
+            }
+            if let TFQueryResult::Scalar(value) = jmespath_query(block, expression) {
+                values.insert(resource.address(), visitor.visit_value(&value));
+            }
+        }
+    }
+
+    output.write(exporters::Rendered::File(to_json(&Value::Object(values))), None)?;
+    output.timing("Query answered", start.elapsed());
+    Ok(())
+}
 
-    let duration = start.elapsed();
-    println!("Terraform parsed in: {:?}", elapsed_before_printing);
-    println!("Terraform printed to stdout in: {:?}", duration);
+fn main() -> Result<(), ExitFailure> {
+    match Cli::from_args() {
+        Cli::Parse { inputs, output } => parse(inputs, output)?,
+        Cli::Graph { inputs, specs, output } => export(exporters::Format::Json, exporters::ExportOptions::default(), inputs, specs, output)?,
+        Cli::Check { severity, format, link_base, inputs, specs, output } => {
+            let code = check(severity, format, link_base, inputs, specs, output)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        },
+        Cli::Query { expression, resource_type, inputs, output } => query(&expression, resource_type, inputs, output)?,
+        Cli::Export { format, environment, previous, cluster_by, inputs, specs, output } => {
+            let previous = match &previous {
+                Some(path) => Some(exporters::rdf::parse_nquads(&std::fs::read_to_string(path)?)
+                    .map_err(|message| failure::format_err!("couldn't read {}: {}", path.display(), message))?),
+                None => None,
+            };
+            let options = exporters::ExportOptions { environment, previous, cluster_by };
+            export(format, options, inputs, specs, output)?
+        },
+    }
     Ok(())
 }
 
@@ -82,6 +393,92 @@ mod tests {
     use async_trait::async_trait;
     use anyhow::Result;
     use serde_json::Value;
+
+    /// a directory of its own for a test to write to
+    fn scratch(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rust_nom_json_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn exit_code(args: &[&str]) -> i32 {
+        match Cli::from_iter_safe(args).unwrap() {
+            Cli::Check { severity, format, link_base, inputs, specs, output } => check(severity, format, link_base, inputs, specs, output).unwrap(),
+            _ => panic!("expected a check"),
+        }
+    }
+
+    #[test]
+    fn subcommands_are_parsed() {
+        match Cli::from_iter_safe(&["rust_nom_json", "check", "main.tf", "--severity", "high", "--format", "sarif", "-q"]).unwrap() {
+            Cli::Check { severity, format, inputs, output, .. } => {
+                assert_eq!(severity, Severity::High);
+                assert_eq!(format, reporters::Format::Sarif);
+                assert_eq!(inputs.paths, vec![PathBuf::from("main.tf")]);
+                assert!(output.quiet);
+            },
+            _ => panic!("expected a check"),
+        }
+        match Cli::from_iter_safe(&["rust_nom_json", "export", "main.tf", "--format", "sparql", "--previous", "loaded.nq", "--environment", "production"]).unwrap() {
+            Cli::Export { format, environment, previous, .. } => {
+                assert_eq!(format, exporters::Format::Sparql);
+                assert_eq!(environment, Some(String::from("production")));
+                assert_eq!(previous, Some(PathBuf::from("loaded.nq")));
+            },
+            _ => panic!("expected an export"),
+        }
+        match Cli::from_iter_safe(&["rust_nom_json", "query", "versioning.enabled", "modules", "--type", "aws_s3_bucket", "--var", "env=prod"]).unwrap() {
+            Cli::Query { expression, resource_type, inputs, .. } => {
+                assert_eq!(expression, "versioning.enabled");
+                assert_eq!(resource_type, Some(String::from("aws_s3_bucket")));
+                assert_eq!(inputs.paths, vec![PathBuf::from("modules")]);
+                assert_eq!(inputs.vars, vec![String::from("env=prod")]);
+            },
+            _ => panic!("expected a query"),
+        }
+
+        assert!(Cli::from_iter_safe(&["rust_nom_json", "graph"]).is_err());
+        assert!(Cli::from_iter_safe(&["rust_nom_json", "export", "main.tf", "--format", "svg"]).is_err());
+        assert!(Cli::from_iter_safe(&["rust_nom_json", "parse", "main.tf", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn check_exits_with_what_it_found() {
+        let directory = scratch("check");
+        let report = directory.join("report.txt");
+        let report = report.to_str().unwrap();
+        let broken = directory.join("broken.tf");
+        std::fs::write(&broken, "resource \"aws_sqs_queue\" \"queue\" {\n  name = \n}\n").unwrap();
+
+        assert_eq!(exit_code(&["rust_nom_json", "check", "example_files/discovery.tf", "-q", "-o", report]), EXIT_VIOLATIONS);
+        assert!(std::fs::read_to_string(report).unwrap().contains("failures"));
+        assert_eq!(exit_code(&["rust_nom_json", "check", "example_files/discovery.tf", "--severity", "critical", "-q", "-o", report]), 0);
+        assert_eq!(exit_code(&["rust_nom_json", "check", broken.to_str().unwrap(), "-q", "-o", report]), EXIT_PARSE_ERRORS);
+        assert_eq!(exit_code(&["rust_nom_json", "check", "example_files/missing.tf", "-q", "-o", report]), EXIT_PARSE_ERRORS);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_are_errors() {
+        let missing = std::env::temp_dir().join(format!("rust_nom_json_missing_{}", std::process::id())).join("graph.json");
+        let error = write_file(&missing, "{}").unwrap_err().to_string();
+        assert!(error.starts_with(&format!("couldn't create {}", missing.display())), "{}", error);
+    }
+
+    #[test]
+    fn policies_are_validated_as_they_are_loaded() {
+        let built_in = Specs { relationships: vec![], policies: vec![] };
+        assert!(!built_in.policies().unwrap().policies.is_empty());
+
+        let path = std::env::temp_dir().join(format!("rnj-policies-{}.yaml", std::process::id()));
+        std::fs::write(&path, "policies:\n  - name: broken\n    resource: aws_sqs_queue\n    filters:\n      - { key: name, op: regex, value: '(' }\n").unwrap();
+        let from_file = Specs { relationships: vec![], policies: vec![path.clone()] };
+        let error = from_file.policies().unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.starts_with(&format!("couldn't read {}", path.display())), "{}", error);
+    }
+
     // use std::io::BufReader;
     // use std::fs::File;
 
@@ -0,0 +1,425 @@
+//! ModuleExpander
+//! - loads the modules called with a local path, eg. `source = "./modules/queue"`, and the modules they call
+//! - binds the arguments of each `module` block to the child module's variables and resolves the child
+//! - namespaces the child's resources and data sources, so `aws_sqs_queue.q` within `module "x"` becomes
+//!   `module.x.aws_sqs_queue.q`, along with every reference made to them inside the module
+//! - substitutes the child's `output` values for the `module.x.<output>` references made by its caller
+//!
+//! Modules fetched from a registry or version control are not expanded, references to them are left in place.
+//! The `variable`, `locals` and `output` blocks of a child module are consumed by the expansion,
+//! only its resources and data sources join the graph.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::cloud_template_parser::{ CloudTemplateParser, ResourceTree };
+use crate::expression::template;
+use crate::resolver::Resolver;
+use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::json::JsonValue;
+use crate::structs::parse_error::ParseError;
+use crate::structs::template_string::{ Expression, Reference, TemplatePart, TemplateString, TraversalStep };
+use crate::structs::terraform_block::{ TerraformBlock, module_prefix };
+use crate::structs::unresolved_reference::UnresolvedReference;
+
+/// the arguments of a `module` block which terraform handles itself, rather than passing to the module
+const META_ARGUMENTS: [&str; 6] = ["source", "version", "count", "for_each", "providers", "depends_on"];
+
+#[derive(Debug, Default)]
+pub struct Expansion {
+    pub resources: Vec<TerraformBlock>,
+    pub diagnostics: Vec<ParseError>,
+    pub unresolved: Vec<UnresolvedReference>,
+}
+
+pub struct ModuleExpander<'a> {
+    parser: &'a CloudTemplateParser,
+}
+
+impl<'a> ModuleExpander<'a> {
+    pub fn new(parser: &'a CloudTemplateParser) -> ModuleExpander<'a> {
+        ModuleExpander { parser }
+    }
+
+    /// resolve the root module with `resolver`, which should already hold any tfvars and `-var` values,
+    /// expanding the local modules it calls. Blocks read from the directories of those modules, as happens
+    /// when a whole directory tree is given, belong to the modules rather than the root and are dropped.
+    pub fn expand(&self, resources: Vec<TerraformBlock>, mut resolver: Resolver) -> Expansion {
+        let mut expansion = Expansion::default();
+
+        let module_directories: Vec<PathBuf> = resources.iter()
+            .filter_map(|resource| module_directory(resource)?.canonicalize().ok())
+            .collect();
+        let mut directories: HashMap<String, bool> = HashMap::new();
+        let root: Vec<TerraformBlock> = resources.into_iter()
+            .filter(|resource| {
+                let file = match &resource.span().file {
+                    Some(file) => file,
+                    None => return true,
+                };
+                let in_module = *directories.entry(file.to_string()).or_insert_with(|| {
+                    let directory = Path::new(file).parent().and_then(|directory| canonical(directory).ok());
+                    directory.is_some_and(|directory| module_directories.iter().any(|module| directory.starts_with(module)))
+                });
+                !in_module
+            })
+            .collect();
+
+        resolver.collect(&root);
+        let (resources, _) = self.expand_module(root, resolver, &[], &mut vec![], &mut expansion);
+        expansion.resources = resources;
+        expansion
+    }
+
+    /// resolve one module's blocks, expanding the local modules it calls first so that their outputs
+    /// can be substituted. Returns the resolved blocks and the module's outputs.
+    fn expand_module(
+        &self,
+        resources: Vec<TerraformBlock>,
+        mut resolver: Resolver,
+        module_path: &[String],
+        ancestors: &mut Vec<PathBuf>,
+        expansion: &mut Expansion,
+    ) -> (Vec<TerraformBlock>, HashMap<String, AttributeType>) {
+        let (calls, others): (Vec<TerraformBlock>, Vec<TerraformBlock>) = resources.into_iter()
+            .partition(|resource| module_directory(resource).is_some());
+
+        let mut kept = vec![];
+        let mut expanded = vec![];
+        for call in calls {
+            // arguments may refer to the outputs of the modules expanded before this one
+            let (mut resolved, unresolved) = resolver.resolve(vec![call]);
+            expansion.unresolved.extend(namespaced(unresolved, module_path));
+            let call = resolved.remove(0);
+
+            let name = match &call {
+                TerraformBlock::WithOneIdentifier(block) => block.first_identifier.to_string(),
+                _ => unreachable!(),
+            };
+            let mut path = module_path.to_vec();
+            path.push(name.to_string());
+
+            match self.load(&call, ancestors) {
+                Ok((directory, tree)) => {
+                    expansion.diagnostics.extend(tree.diagnostics);
+
+                    let mut child = tree.resources;
+                    for resource in child.iter_mut() {
+                        namespace_block(resource, &path);
+                    }
+
+                    let mut child_resolver = Resolver::new();
+                    child_resolver.collect(&child);
+                    child_resolver.set_variables(call.attributes().iter()
+                        .filter(|attr| !META_ARGUMENTS.contains(&attr.key.as_str()))
+                        .cloned()
+                        .collect());
+
+                    ancestors.push(directory);
+                    let (child, outputs) = self.expand_module(child, child_resolver, &path, ancestors, expansion);
+                    ancestors.pop();
+
+                    resolver.set_module_outputs(&name, outputs);
+                    expanded.extend(child.into_iter().filter(|resource| matches!(resource, TerraformBlock::WithTwoIdentifiers(_))));
+                },
+                Err(reason) => expansion.unresolved.push(UnresolvedReference {
+                    reference: format!("{}module.{}", module_prefix(module_path), name),
+                    block: format!("{}{}", module_prefix(module_path), call.address()),
+                    attribute: String::from("source"),
+                    reason,
+                    span: call.span().clone(),
+                }),
+            }
+
+            // only the root module's calls are kept, as nodes for the modules
+            if module_path.is_empty() {
+                kept.push(call);
+            }
+        }
+
+        let (mut resources, unresolved) = resolver.resolve(others);
+        expansion.unresolved.extend(namespaced(unresolved, module_path));
+
+        let outputs = resources.iter()
+            .filter_map(|resource| match resource {
+                TerraformBlock::WithOneIdentifier(block) if block.block_type == "output" => {
+                    let value = block.attributes.iter().find(|attr| attr.key == "value")?;
+                    Some((block.first_identifier.to_string(), value.value.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+
+        resources.extend(kept);
+        resources.extend(expanded);
+        (resources, outputs)
+    }
+
+    /// parse the templates of the module a `module` block calls, returning the module's directory with them
+    fn load(&self, call: &TerraformBlock, ancestors: &[PathBuf]) -> Result<(PathBuf, ResourceTree), String> {
+        let directory = module_directory(call).unwrap_or_default();
+        let canonical = canonical(&directory)
+            .map_err(|error| format!("its source {} cannot be read: {}", directory.display(), error))?;
+
+        if ancestors.contains(&canonical) {
+            return Err(String::from("the module calls itself"));
+        }
+
+        let tree = self.parser.handle_module(&directory)
+            .map_err(|error| format!("its source {} cannot be read: {}", directory.display(), error))?;
+        Ok((canonical, tree))
+    }
+}
+
+/// the directory of a module called with a local path, relative to the file the `module` block is in
+fn module_directory(resource: &TerraformBlock) -> Option<PathBuf> {
+    let block = match resource {
+        TerraformBlock::WithOneIdentifier(block) if block.block_type == "module" => block,
+        _ => return None,
+    };
+    let source = match block.attributes.iter().find(|attr| attr.key == "source") {
+        Some(Attribute { value: AttributeType::Str(source), .. }) if source.starts_with("./") || source.starts_with("../") => source,
+        _ => return None,
+    };
+
+    let base = block.span.file.as_ref().and_then(|file| Path::new(file).parent()).unwrap_or_else(|| Path::new(""));
+    Some(base.join(source))
+}
+
+/// `Path::canonicalize`, treating an empty path as the current directory
+fn canonical(directory: &Path) -> std::io::Result<PathBuf> {
+    if directory.as_os_str().is_empty() {
+        Path::new(".").canonicalize()
+    } else {
+        directory.canonicalize()
+    }
+}
+
+/// the blocks of a module's variables, locals and outputs aren't namespaced, so their problems are given the module's prefix
+fn namespaced(unresolved: Vec<UnresolvedReference>, module_path: &[String]) -> Vec<UnresolvedReference> {
+    let prefix = module_prefix(module_path);
+    unresolved.into_iter().map(|mut problem| {
+        if !problem.block.starts_with(&prefix) {
+            problem.block = format!("{}{}", prefix, problem.block);
+        }
+        problem
+    }).collect()
+}
+
+/// place a child module's block, and the references it makes to the child's other resources, within `module_path`
+fn namespace_block(resource: &mut TerraformBlock, module_path: &[String]) {
+    if let TerraformBlock::WithTwoIdentifiers(block) = resource {
+        block.module_path = module_path.to_vec();
+    }
+    for attribute in resource.attributes_mut().iter_mut() {
+        namespace_value(&mut attribute.value, module_path);
+    }
+}
+
+fn namespace_value(value: &mut AttributeType, module_path: &[String]) {
+    match value {
+        AttributeType::TemplatedString(template_string) => {
+            let mut expression = template_string.to_expression();
+            if namespace_expression(&mut expression, module_path) {
+                *template_string = TemplateString::from_expression(expression);
+            }
+        },
+        AttributeType::Expression(expression) => {
+            namespace_expression(expression, module_path);
+        },
+        AttributeType::Array(values) => values.iter_mut().for_each(|value| namespace_value(value, module_path)),
+        AttributeType::Block(attributes) => attributes.iter_mut().for_each(|attr| namespace_value(&mut attr.value, module_path)),
+        AttributeType::TFBlock(block) => block.attributes_mut().iter_mut().for_each(|attr| namespace_value(&mut attr.value, module_path)),
+        AttributeType::Json(json) => namespace_json(json, module_path),
+        AttributeType::Str(_) | AttributeType::Num(_) | AttributeType::Boolean(_) => (),
+    }
+}
+
+/// interpolations within JSON documents, eg. the `Resource` of a policy statement
+fn namespace_json(value: &mut JsonValue, module_path: &[String]) {
+    match value {
+        JsonValue::Str(text) if text.contains("${") => {
+            if let Ok(("", mut parts)) = template(&format!("\"{}\"", text)) {
+                let mut changed = false;
+                for part in parts.iter_mut() {
+                    if let TemplatePart::Interpolation(expression) = part {
+                        changed |= namespace_expression(expression, module_path);
+                    }
+                }
+                if changed {
+                    *text = TemplateString::Template(parts).to_string();
+                }
+            }
+        },
+        JsonValue::Array(values) => values.iter_mut().for_each(|value| namespace_json(value, module_path)),
+        JsonValue::Object(items) => items.iter_mut().for_each(|(_, value)| namespace_json(value, module_path)),
+        _ => (),
+    }
+}
+
+/// returns whether any resource or data source reference was namespaced
+fn namespace_expression(expression: &mut Expression, module_path: &[String]) -> bool {
+    let steps = |steps: &mut Vec<TraversalStep>| steps.iter_mut().fold(false, |changed, step| match step {
+        TraversalStep::Index(index) => namespace_expression(index, module_path) | changed,
+        _ => changed,
+    });
+
+    match expression {
+        Expression::Variable(traversal) => {
+            let changed = steps(&mut traversal.steps);
+            match traversal.reference() {
+                Some(Reference::Resource { .. }) | Some(Reference::Data { .. }) if traversal.module_path.is_empty() => {
+                    traversal.module_path = module_path.to_vec();
+                    true
+                },
+                _ => changed,
+            }
+        },
+        Expression::FunctionCall(function) => function.args.iter_mut().fold(false, |changed, arg| namespace_expression(arg, module_path) | changed),
+        Expression::Access { target, steps: access_steps } => namespace_expression(target, module_path) | steps(access_steps),
+        Expression::Tuple(items) => items.iter_mut().fold(false, |changed, item| namespace_expression(item, module_path) | changed),
+        Expression::Object(items) => items.iter_mut().fold(false, |changed, (_, value)| namespace_expression(value, module_path) | changed),
+        Expression::Unary { operand, .. } => namespace_expression(operand, module_path),
+        Expression::Binary { left, right, .. } => namespace_expression(left, module_path) | namespace_expression(right, module_path),
+        Expression::Conditional { condition, true_result, false_result } => {
+            namespace_expression(condition, module_path)
+                | namespace_expression(true_result, module_path)
+                | namespace_expression(false_result, module_path)
+        },
+        Expression::Parenthesized(inner) => namespace_expression(inner, module_path),
+        Expression::Template(parts) => parts.iter_mut().fold(false, |changed, part| match part {
+            TemplatePart::Interpolation(expression) => namespace_expression(expression, module_path) | changed,
+            TemplatePart::Literal(_) => changed,
+        }),
+        Expression::Str(_) | Expression::Num(_) | Expression::Boolean(_) | Expression::Null => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// write a root module calling `./modules/queue`, which calls `../topic`, into a fresh directory
+    fn example_modules(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rust_nom_json_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("modules/queue")).unwrap();
+        fs::create_dir_all(directory.join("modules/topic")).unwrap();
+
+        fs::write(directory.join("main.tf"), r#"
+module "orders" {
+    source      = "./modules/queue"
+    queue_name  = "orders-${var.environment}"
+}
+
+variable "environment" {
+    default = "prod"
+}
+
+resource "aws_lambda_event_source_mapping" "mapping" {
+    event_source_arn = module.orders.queue_arn
+    function_name    = "aws_lambda_function.consumer"
+}
+
+module "registry" {
+    source = "terraform-aws-modules/sqs/aws"
+}
+"#).unwrap();
+        fs::write(directory.join("modules/queue/main.tf"), r#"
+variable "queue_name" {}
+
+module "alerts" {
+    source = "../topic"
+    name   = "${var.queue_name}-alerts"
+}
+
+resource "aws_sqs_queue" "queue" {
+    name   = var.queue_name
+    policy = module.alerts.topic_arn
+}
+
+output "queue_arn" {
+    value = aws_sqs_queue.queue.arn
+}
+"#).unwrap();
+        fs::write(directory.join("modules/topic/main.tf"), r#"
+variable "name" {}
+
+resource "aws_sns_topic" "topic" {
+    name = var.name
+}
+
+output "topic_arn" {
+    value = aws_sns_topic.topic.arn
+}
+"#).unwrap();
+
+        directory
+    }
+
+    fn expand(directory: &Path) -> Expansion {
+        let parser = CloudTemplateParser::new();
+        let tree = parser.handle_paths(&[directory.to_path_buf()], &[], &[]).unwrap();
+        assert_eq!(tree.diagnostics, vec![]);
+
+        ModuleExpander::new(&parser).expand(tree.resources, Resolver::new())
+    }
+
+    fn value<'a>(resources: &'a [TerraformBlock], address: &str, key: &str) -> &'a AttributeType {
+        let resource = resources.iter().find(|resource| resource.address() == address).unwrap();
+        &resource.attributes().iter().find(|attr| attr.key == key).unwrap().value
+    }
+
+    #[test]
+    fn child_resources_are_namespaced() {
+        let directory = example_modules("namespaced");
+        let expansion = expand(&directory);
+
+        let addresses: Vec<String> = expansion.resources.iter().map(|resource| resource.address()).collect();
+        assert_eq!(addresses, vec![
+            "variable.environment",
+            "aws_lambda_event_source_mapping.mapping",
+            "module.registry",
+            "module.orders",
+            "module.orders.aws_sqs_queue.queue",
+            "module.orders.module.alerts.aws_sns_topic.topic",
+        ]);
+        assert_eq!(expansion.unresolved, vec![]);
+        assert_eq!(
+            value(&expansion.resources, "module.orders.module.alerts.aws_sns_topic.topic", "name"),
+            &AttributeType::Str(String::from("orders-prod-alerts"))
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn outputs_are_wired_to_their_callers() {
+        let directory = example_modules("outputs");
+        let expansion = expand(&directory);
+
+        let reference = |address: &str, key: &str| match value(&expansion.resources, address, key) {
+            AttributeType::Expression(Expression::Variable(traversal)) => traversal.reference().map(|reference| reference.address()),
+            _ => None,
+        };
+        assert_eq!(reference("aws_lambda_event_source_mapping.mapping", "event_source_arn"), Some(String::from("module.orders.aws_sqs_queue.queue")));
+        assert_eq!(reference("module.orders.aws_sqs_queue.queue", "policy"), Some(String::from("module.orders.module.alerts.aws_sns_topic.topic")));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn missing_modules_are_reported() {
+        let directory = example_modules("missing");
+        fs::remove_dir_all(directory.join("modules/topic")).unwrap();
+        let expansion = expand(&directory);
+
+        assert_eq!(expansion.unresolved.len(), 1);
+        assert_eq!(expansion.unresolved[0].reference, "module.orders.module.alerts");
+        assert_eq!(expansion.unresolved[0].block, "module.orders.module.alerts");
+        assert!(expansion.unresolved[0].reason.starts_with("its source"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -4,24 +4,67 @@ use crate::structs::terraform_block::{
     TerraformBlock,
 };
 use crate::structs::attributes::AttributeType;
-use AttributeType::{ Block, Str, Num };
-use crate::structs::policies::{ Policies, Policy, Filter };
-use crate::relationship_finders::tf_block_query::tf_block_query::{ jmespath_query, TFQueryResult };
+use crate::structs::span::Span;
+use AttributeType::{ Array, Block };
+use crate::structs::policies::{ Direction, Policies, Policy, Filter, FilterTree, Op, Quantifier };
+use crate::graph::{ Edge, InfrastructureGraph, kind_name, label_names };
+use crate::glob::glob_match_text;
+use crate::visitors::json_visitor::JsonVisitor;
+use crate::visitors::visitor::Visitor;
+use crate::relationship_finders::tf_block_query::tf_block_query::{ jmespath_list_query, jmespath_query, TFQueryResult };
 
 use TFQueryResult::{ List, Scalar };
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use itertools::Itertools;
+use serde_json::{ json, Value };
 
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct FilterResult {
     filter: Filter, // TODO: this should be a Vector of Filters
     result: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    span: Option<Span>,
+    /// where the filter sits in the policy's groups, eg. `filters[0].or[1]` for the second filter of an `or` group
+    /// which is the policy's first filter. Filters outside any group have none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    branch: String,
+    /// whether the filter decided the policy's outcome: those which failed when the policy failed,
+    /// unless an `or` or a `not` turned them around
+    #[serde(default)]
+    decided: bool,
 }
 
 impl FilterResult {
     pub fn new(filter: Filter, result: bool) -> FilterResult {
-        FilterResult { filter, result }
+        FilterResult { filter, result, span: None, branch: String::new(), decided: false }
+    }
+
+    /// the span of the attribute the filter was evaluated against
+    pub fn with_span(mut self, span: Option<Span>) -> FilterResult {
+        self.span = span;
+        self
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn result(&self) -> bool {
+        self.result
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+
+    pub fn decided(&self) -> bool {
+        self.decided
     }
 }
 
@@ -36,11 +79,31 @@ pub struct PolicyResult {
     filters: Vec<FilterResult>,
     policy_id: String,
     policy_result: bool,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    span: Span,
 }
 
 impl PolicyResult {
     pub fn new(filters: Vec<FilterResult>, policy_id: String, policy_result: bool) -> PolicyResult {
-        PolicyResult { filters, policy_id, policy_result }
+        PolicyResult { filters, policy_id, policy_result, span: Span::default() }
+    }
+
+    /// the span of the resource the policy was evaluated against
+    pub fn with_span(mut self, span: Span) -> PolicyResult {
+        self.span = span;
+        self
+    }
+
+    pub fn policy_id(&self) -> &str {
+        &self.policy_id
+    }
+
+    pub fn filters(&self) -> &[FilterResult] {
+        &self.filters
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
     }
 }
 
@@ -60,51 +123,342 @@ fn extract_policy_targets(policies: &Policies) -> Vec<String> {
     result.into_iter().unique().collect_vec()
 }
 
-fn evaluate_filter(filter: &Filter, attribute_type: AttributeType) -> FilterResult {
-    let result = match &attribute_type {
-        Block(attributes) => {
-            if &attributes.len() > &0 {
-                match &attributes[0].value {
-                    Str(val) => val == &filter.value,
-                    Num(val) => val == &filter.value.parse::<f64>().unwrap(),
-                    // TODO: accommodate all variants
-                    _ => false,
-                }
-            } else {
-                println!("found bad attribute: {:?}", &attribute_type);
-                false
-            }
+/// the value a filter's key found, as JSON. Keys reaching into JSON find a Block holding the value under
+/// the last key of each object matched: one match is the value itself, several an array of them.
+fn found_value(filter: &Filter, attribute_type: &AttributeType) -> Option<Value> {
+    let visitor = JsonVisitor{};
+    match attribute_type {
+        Block(matches) if filter.key.contains('.') => match matches.len() {
+            0 => None,
+            1 => Some(visitor.visit_value(&matches[0].value)),
+            _ => Some(Value::Array(matches.iter().map(|attr| visitor.visit_value(&attr.value)).collect())),
         },
-        Str(val) => val == &filter.value,
-        Num(val) => val == &filter.value.parse::<f64>().unwrap(),
-        // TODO: accommodate all variants
+        value => Some(visitor.visit_value(value)),
+    }
+}
+
+/// a number, or a string holding one
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+/// a filter's value as text, strings without their quotes
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// equality read with the type of the value found: a string filter value equals the number, boolean,
+/// null, list or object it spells
+fn equals(found: &Value, expected: &Value) -> bool {
+    match (found, expected) {
+        (Value::String(found), Value::String(expected)) => found == expected,
+        (Value::Number(_), _) | (_, Value::Number(_)) => match (number(found), number(expected)) {
+            (Some(found), Some(expected)) => found == expected,
+            _ => false,
+        },
+        (Value::Bool(found), Value::String(expected)) => expected == &found.to_string(),
+        (Value::Null, Value::String(expected)) => expected == "null",
+        (Value::Array(_), Value::String(expected)) | (Value::Object(_), Value::String(expected)) => {
+            serde_json::from_str::<Value>(expected).is_ok_and(|expected| equals(found, &expected))
+        },
+        (Value::Array(found), Value::Array(expected)) => {
+            found.len() == expected.len() && found.iter().zip(expected).all(|(found, expected)| equals(found, expected))
+        },
+        (Value::Object(found), Value::Object(expected)) => {
+            found.len() == expected.len() && found.iter().all(|(key, found)| expected.get(key).is_some_and(|expected| equals(found, expected)))
+        },
+        (found, expected) => found == expected,
+    }
+}
+
+/// numbers are ordered as numbers, strings which aren't numbers alphabetically
+fn compare(found: &Value, expected: &Value) -> Option<Ordering> {
+    match (number(found), number(expected), found, expected) {
+        (Some(found), Some(expected), _, _) => found.partial_cmp(&expected),
+        (_, _, Value::String(found), Value::String(expected)) => Some(found.as_str().cmp(expected.as_str())),
+        _ => None,
+    }
+}
+
+fn length(value: &Value) -> Option<usize> {
+    match value {
+        Value::String(text) => Some(text.chars().count()),
+        Value::Array(values) => Some(values.len()),
+        Value::Object(values) => Some(values.len()),
+        _ => None,
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    value.is_null() || length(value) == Some(0)
+}
+
+fn contains(found: &Value, expected: &Value) -> bool {
+    match found {
+        Value::String(found) => found.contains(&text(expected)),
+        Value::Array(found) => found.iter().any(|element| equals(element, expected)),
+        Value::Object(found) => found.contains_key(&text(expected)),
         _ => false,
+    }
+}
+
+/// whether a string found passes a test against the filter's value, anything else fails
+fn string_test<F: Fn(&str, &str) -> bool>(found: &Value, expected: &Value, test: F) -> bool {
+    match found {
+        Value::String(found) => test(found, &text(expected)),
+        _ => false,
+    }
+}
+
+/// whether the value found, if any, passes the filter. Nothing found fails every op but `absent`.
+/// `regex` and `filter` need what's compiled from the filter, so are applied by `passes`.
+fn apply_op(op: Op, found: Option<&Value>, expected: &Value) -> bool {
+    let found = match (op, found) {
+        (Op::Exists, found) => return found.is_some(),
+        (Op::Absent, found) => return found.is_none(),
+        (_, None) => return false,
+        (_, Some(found)) => found,
+    };
+    let members = || match expected {
+        Value::Array(members) => members.clone(),
+        expected => vec![expected.clone()],
+    };
+    let length_is = |wanted: &[Ordering]| match (length(found), number(expected)) {
+        (Some(length), Some(expected)) => (length as f64).partial_cmp(&expected).is_some_and(|order| wanted.contains(&order)),
+        _ => false,
+    };
+
+    match op {
+        Op::Eq => equals(found, expected),
+        Op::Ne => !equals(found, expected),
+        Op::Gt => compare(found, expected) == Some(Ordering::Greater),
+        Op::Ge => matches!(compare(found, expected), Some(Ordering::Greater) | Some(Ordering::Equal)),
+        Op::Lt => compare(found, expected) == Some(Ordering::Less),
+        Op::Le => matches!(compare(found, expected), Some(Ordering::Less) | Some(Ordering::Equal)),
+        Op::In => members().iter().any(|member| equals(found, member)),
+        Op::NotIn => !members().iter().any(|member| equals(found, member)),
+        Op::Contains => contains(found, expected),
+        Op::StartsWith => string_test(found, expected, |found, prefix| found.starts_with(prefix)),
+        Op::EndsWith => string_test(found, expected, |found, suffix| found.ends_with(suffix)),
+        Op::Glob => string_test(found, expected, |found, pattern| glob_match_text(pattern, found)),
+        Op::Empty => is_empty(found),
+        Op::LenEq => length_is(&[Ordering::Equal]),
+        Op::LenNe => length_is(&[Ordering::Less, Ordering::Greater]),
+        Op::LenGt => length_is(&[Ordering::Greater]),
+        Op::LenGe => length_is(&[Ordering::Greater, Ordering::Equal]),
+        Op::LenLt => length_is(&[Ordering::Less]),
+        Op::LenLe => length_is(&[Ordering::Less, Ordering::Equal]),
+        Op::Exists | Op::Absent | Op::Filter | Op::Regex => unreachable!(),
+    }
+}
+
+/// the value a key finds in a list element, following the keys of its objects
+fn element_value<'v>(key: &str, element: &'v Value) -> Option<&'v Value> {
+    key.split('.').try_fold(element, |value, part| value.get(part.trim_end_matches("[]")))
+}
+
+/// whether a list element passes a tree of an `op: filter`'s filters, their keys starting from the element
+fn element_passes(tree: &FilterTree, element: &Value) -> bool {
+    match tree {
+        FilterTree::Filter(filter) => passes(filter, element_value(&filter.key, element)),
+        FilterTree::And(trees) => trees.iter().all(|tree| element_passes(tree, element)),
+        FilterTree::Or(trees) => trees.iter().any(|tree| element_passes(tree, element)),
+        FilterTree::Not(tree) => !element_passes(tree, element),
+    }
+}
+
+/// whether enough of the elements found pass an `op: filter`'s filters. A key which finds no list
+/// has no elements, so it passes `all` and `none` and fails `any`.
+fn filter_elements(filter: &Filter, found: Option<&Value>) -> bool {
+    let trees = filter.sub_filters().unwrap_or(&[]);
+    let elements = match found {
+        Some(Value::Array(elements)) => elements.iter().collect(),
+        Some(element) => vec![element],
+        None => vec![],
     };
+    let passed = elements.iter().filter(|element| trees.iter().all(|tree| element_passes(tree, element))).count();
+
+    match filter.quantifier.as_ref().unwrap_or(&Quantifier::Any) {
+        Quantifier::Any => passed > 0,
+        Quantifier::All => passed == elements.len(),
+        Quantifier::None => passed == 0,
+        Quantifier::Count(count) => apply_op(count.op, Some(&Value::from(passed)), &count.value),
+    }
+}
+
+/// whether the value found, if any, passes the filter. A pattern which doesn't compile is rejected when the
+/// policies are validated, so never reaches here from a policy file.
+fn passes(filter: &Filter, found: Option<&Value>) -> bool {
+    match filter.op {
+        Op::Filter => filter_elements(filter, found),
+        Op::Regex => match (filter.regex(), found) {
+            (Ok(regex), Some(found)) => string_test(found, &filter.value, |found, _| regex.is_match(found)),
+            _ => false,
+        },
+        op => apply_op(op, found, &filter.value),
+    }
+}
+
+fn evaluate_filter(filter: &Filter, found: Option<AttributeType>) -> FilterResult {
+    let found = found.and_then(|attribute_type| found_value(filter, &attribute_type));
+    let result = passes(filter, found.as_ref());
 
     FilterResult::new(filter.clone(), result)
 }
 
-fn evaluate_policy(policy: &Policy, resource: &TerraformBlock) -> PolicyResult {
-    let filters: Vec<FilterResult> = policy.filters.iter().map(|filter| {
-        let query_result = match resource {
-            TerraformBlock::WithTwoIdentifiers(tf_block) => jmespath_query(tf_block, &filter.key.as_str()),
-            _ => TFQueryResult::None,
-        };
+/// the other end of an edge followed from `address` in a direction, and the direction it was followed in
+fn follow<'e>(edge: &'e Edge, address: &str) -> (&'e str, &'static str) {
+    if edge.source == address {
+        (&edge.target, "outgoing")
+    } else {
+        (&edge.source, "incoming")
+    }
+}
 
-        match query_result {
-            List(attribute_types) => FilterResult::new(filter.clone(), false),
-            Scalar(attribute_type) => evaluate_filter(filter, attribute_type),
-            TFQueryResult::None => FilterResult::new(filter.clone(), false),
+/// the resources reached from `address` by paths of up to `depth` edges in a direction, for filters on the graph
+/// to test. Each path is an object of the `address`, `type`, `module` and attributes (`resource`) of the resource
+/// reached, whether it's in the `same_module` as the resource the policy is evaluated against, how many edges away
+/// it is (`depth`), the `direction`, `kind` and `label` of the last edge and the addresses along the `path` before it.
+/// Paths don't go through a resource twice.
+fn connections(graph: &InfrastructureGraph, address: &str, direction: Direction, depth: usize) -> Vec<Value> {
+    let visitor = JsonVisitor{};
+    let module = graph.node(address).and_then(|node| node.module.as_ref());
+    let mut connections = vec![];
+    let mut paths: Vec<Vec<&str>> = vec![vec![address]];
+
+    while let Some(path) = paths.pop() {
+        let from = path[path.len() - 1];
+        let edges = match direction {
+            Direction::Outgoing => graph.outgoing(from),
+            Direction::Incoming => graph.incoming(from),
+            Direction::Either => graph.outgoing(from).into_iter().chain(graph.incoming(from)).collect(),
+        };
+        for edge in edges {
+            let (to, followed) = follow(edge, from);
+            if path.contains(&to) {
+                continue;
+            }
+            let node = graph.node(to);
+            connections.push(json!({
+                "address": to,
+                "type": node.map(|node| node.node_type.to_string()),
+                "module": node.and_then(|node| node.module.clone()),
+                "same_module": node.is_some_and(|node| node.module.as_ref() == module),
+                "depth": path.len(),
+                "direction": followed,
+                "kind": kind_name(edge.kind),
+                "label": label_names(&edge.label),
+                "path": path[1..],
+                "resource": node.map(|node| visitor.visit_block(&node.attributes)),
+            }));
+            if path.len() < depth {
+                let mut longer = path.clone();
+                longer.push(to);
+                paths.push(longer);
+            }
         }
-    }).collect();
+    }
+    connections
+}
+
+/// the span of the top level attribute a filter key starts from
+fn filter_span(filter: &Filter, resource: &TerraformBlock) -> Option<Span> {
+    let root_key = filter.key.split('.').next().unwrap_or("").trim_end_matches("[]");
+
+    resource.attributes().iter()
+        .find(|attr| attr.key == root_key)
+        .map(|attr| attr.span.clone())
+        .filter(|span| !span.is_empty())
+}
+
+fn evaluate_leaf(filter: &Filter, resource: &TerraformBlock, graph: &InfrastructureGraph) -> FilterResult {
+    if let Some(direction) = filter.direction() {
+        let found = Value::Array(connections(graph, &resource.address(), direction, filter.depth.unwrap_or(1)));
+        return FilterResult::new(filter.clone(), passes(filter, Some(&found)));
+    }
 
-    let combined_result = filters.iter().fold(true, |acc, x| acc && x.result);
+    let query_result = match (resource, filter.op) {
+        (TerraformBlock::WithTwoIdentifiers(tf_block), Op::Filter) => match jmespath_list_query(tf_block, filter.key.as_str()) {
+            Some(elements) => List(elements),
+            None => TFQueryResult::None,
+        },
+        (TerraformBlock::WithTwoIdentifiers(tf_block), _) => jmespath_query(tf_block, filter.key.as_str()),
+        _ => TFQueryResult::None,
+    };
+
+    let filter_result = match query_result {
+        List(attribute_types) => evaluate_filter(filter, Some(Array(attribute_types))),
+        Scalar(attribute_type) => evaluate_filter(filter, Some(attribute_type)),
+        TFQueryResult::None => evaluate_filter(filter, None),
+    };
 
-    PolicyResult::new(filters, policy.name.to_string(), combined_result)
+    filter_result.with_span(filter_span(filter, resource))
+}
+
+/// evaluate a tree of filters, adding the result of every filter in it to `results`. Returns the tree's result
+/// and which of `results` decided it: of a group's filters, those whose result was the group's.
+fn evaluate_tree(tree: &FilterTree, branch: &str, resource: &TerraformBlock, graph: &InfrastructureGraph, results: &mut Vec<FilterResult>) -> (bool, Vec<usize>) {
+    let group = |trees: &[FilterTree], name: &str, results: &mut Vec<FilterResult>| -> Vec<(bool, Vec<usize>)> {
+        trees.iter().enumerate()
+            .map(|(position, tree)| evaluate_tree(tree, &format!("{}.{}[{}]", branch, name, position), resource, graph, results))
+            .collect()
+    };
+    let deciding = |outcomes: Vec<(bool, Vec<usize>)>, result: bool| -> Vec<usize> {
+        outcomes.into_iter().filter(|(outcome, _)| *outcome == result).flat_map(|(_, deciding)| deciding).collect()
+    };
+
+    match tree {
+        FilterTree::Filter(filter) => {
+            let mut filter_result = evaluate_leaf(filter, resource, graph);
+            // filters outside any group are only placed by their order
+            if branch.contains('.') {
+                filter_result.branch = branch.to_string();
+            }
+            results.push(filter_result);
+            (results[results.len() - 1].result, vec![results.len() - 1])
+        },
+        FilterTree::And(trees) => {
+            let outcomes = group(trees, "and", results);
+            let result = outcomes.iter().all(|(outcome, _)| *outcome);
+            (result, deciding(outcomes, result))
+        },
+        FilterTree::Or(trees) => {
+            let outcomes = group(trees, "or", results);
+            let result = outcomes.iter().any(|(outcome, _)| *outcome);
+            (result, deciding(outcomes, result))
+        },
+        FilterTree::Not(tree) => {
+            let (result, deciding) = evaluate_tree(tree, &format!("{}.not", branch), resource, graph, results);
+            (!result, deciding)
+        },
+    }
+}
+
+fn evaluate_policy(policy: &Policy, resource: &TerraformBlock, graph: &InfrastructureGraph) -> PolicyResult {
+    let mut filters: Vec<FilterResult> = vec![];
+    let outcomes: Vec<(bool, Vec<usize>)> = policy.filters.iter().enumerate()
+        .map(|(position, tree)| evaluate_tree(tree, &format!("filters[{}]", position), resource, graph, &mut filters))
+        .collect();
+
+    let combined_result = outcomes.iter().all(|(outcome, _)| *outcome);
+    for (_, deciding) in outcomes.into_iter().filter(|(outcome, _)| *outcome == combined_result) {
+        for position in deciding {
+            filters[position].decided = true;
+        }
+    }
+
+    PolicyResult::new(filters, policy.name.to_string(), combined_result).with_span(resource.span().clone())
 }
 
 // TODO: Return a HashMap<Policy, Vec<PolicyResult>>
-fn query_resources<'a>(cache: HashMap<&str, Vec<&TerraformBlock>>, policies: Policies) -> HashMap<String, Vec<PolicyResult>> {
+fn query_resources<'a>(cache: HashMap<&str, Vec<&TerraformBlock>>, policies: Policies, graph: &InfrastructureGraph) -> HashMap<String, Vec<PolicyResult>> {
     let mut results_map: HashMap<String, Vec<PolicyResult>> = HashMap::new();
 
     for policy in policies.policies {
@@ -112,7 +466,7 @@ fn query_resources<'a>(cache: HashMap<&str, Vec<&TerraformBlock>>, policies: Pol
 
         if let Some(resources) = cache_entry {
             for &resource in resources {
-                let policy_results = evaluate_policy(&policy, &resource);
+                let policy_results = evaluate_policy(&policy, &resource, graph);
 
                 if policy_results.policy_result == false {
                     let existing_policy_results = results_map.get(&resource.get_id());
@@ -137,7 +491,13 @@ fn query_resources<'a>(cache: HashMap<&str, Vec<&TerraformBlock>>, policies: Pol
     results_map
 }
 
+/// evaluate the policies against the resources alone: filters on the graph find no edges
 pub fn evaluate(policies: Policies, resources: &Vec<TerraformBlock>) -> HashMap<String, Vec<PolicyResult>> {
+    evaluate_graph(policies, resources, &InfrastructureGraph::from_blocks(resources))
+}
+
+/// evaluate the policies against the resources and the relationships found between them
+pub fn evaluate_graph(policies: Policies, resources: &Vec<TerraformBlock>, graph: &InfrastructureGraph) -> HashMap<String, Vec<PolicyResult>> {
     let mut cache: HashMap<&str, Vec<&TerraformBlock>> = HashMap::new();
 
     let resource_targets = extract_policy_targets(&policies);
@@ -154,7 +514,7 @@ pub fn evaluate(policies: Policies, resources: &Vec<TerraformBlock>) -> HashMap<
         cache.insert(target_resource, filtered_resources.clone());
     }
 
-    let policy_results = query_resources(cache, policies);
+    let policy_results = query_resources(cache, policies, graph);
     
     policy_results
 }
@@ -171,22 +531,24 @@ mod tests {
         AttributeType,
     };
     use crate::structs::json::JsonValue;
-    use crate::structs::policies::{ Policies, Policy, Filter };
+    use crate::structs::policies::{ Count, Policies, Policy, Filter, Op };
+    use crate::terraform::root;
+    use serde_json::json;
 
     fn setup_policies() -> Policies {
         Policies {
             policies: vec![
                 Policy::new(
                     "example-do-policy-name-check", "balbabal", "aws_iam_role_policy", 
-                    vec![Filter::new("policy.maxReceiveCount", "eq", "2.0")]
+                    vec![Filter::new("policy.maxReceiveCount", Op::Eq, "2.0")]
                 ),
                 Policy::new(
                     "example-do-ec2-id-check", "description...", "aws_ec2_instance", 
-                    vec![Filter::new("id", "eq", "id-that-we-are-looking-for2")]
+                    vec![Filter::new("id", Op::Eq, "id-that-we-are-looking-for2")]
                 ),
                 Policy::new(
                     "policy-id-check", "balbabal", "aws_iam_role_policy", 
-                    vec![Filter::new("visibility_timeout_seconds", "eq", "3.1")]
+                    vec![Filter::new("visibility_timeout_seconds", Op::Eq, "3.1")]
                 ),
             ]
         }
@@ -202,15 +564,21 @@ mod tests {
                     attributes: vec![
                         Attribute {
                             key: String::from("visibility_timeout_seconds"), 
-                            value: AttributeType::Num(30.0)
+                            value: AttributeType::Num(30.0),
+                            span: Span::default()
                         },
                         Attribute {
                             key: String::from("policy"),
                             value: AttributeType::Json(JsonValue::Object(vec![
                                 (String::from("deadLetterTargetArn"), JsonValue::Str(String::from("${aws_sqs_queue.discovery_collector-deadletter-queue.arn}"))),
-                                (String::from("maxReceiveCount"), JsonValue::Num(2.0))]))
+                                (String::from("maxReceiveCount"), JsonValue::Num(2.0))])),
+                            span: Span::default()
                         },
-                    ]
+                    ],
+                    span: Span::default(),
+                    module_path: vec![],
+                    planned_actions: vec![],
+                    references: vec![],
                 }
             ),
             TerraformBlock::WithTwoIdentifiers(
@@ -221,9 +589,14 @@ mod tests {
                     attributes: vec![
                         Attribute {
                             key: String::from("visibility_timeout_seconds"), 
-                            value: AttributeType::Num(30.0)
+                            value: AttributeType::Num(30.0),
+                            span: Span::default()
                         },
-                    ]
+                    ],
+                    span: Span::default(),
+                    module_path: vec![],
+                    planned_actions: vec![],
+                    references: vec![],
                 }
             ),
             TerraformBlock::WithTwoIdentifiers(
@@ -234,9 +607,14 @@ mod tests {
                     attributes: vec![
                         Attribute {
                             key: String::from("id"),
-                            value: AttributeType::Str(String::from("id-that-we-are-looking-for"))
+                            value: AttributeType::Str(String::from("id-that-we-are-looking-for")),
+                            span: Span::default()
                         }
-                    ]
+                    ],
+                    span: Span::default(),
+                    module_path: vec![],
+                    planned_actions: vec![],
+                    references: vec![],
                 }
             ),
         ]
@@ -246,13 +624,13 @@ mod tests {
     fn evaluate_filter_test() {
         let policies = setup_policies();
 
-        let filter = Filter::new("policy.maxReceiveCount", "eq", "2.0");
+        let filter = Filter::new("policy.maxReceiveCount", Op::Eq, "2.0");
         let filter_result = FilterResult::new(filter, true);
 
         let attribute_input = AttributeType::Block(
-            vec![Attribute{ key: String::from("policy.maxReceiveCount"), value: AttributeType::Num(2.0) }]
+            vec![Attribute{ key: String::from("policy.maxReceiveCount"), value: AttributeType::Num(2.0), span: Span::default() }]
         );
-        let result = evaluate_filter(&policies.policies[0].filters[0], attribute_input);
+        let result = evaluate_filter(policies.policies[0].filters[0].filters()[0], Some(attribute_input));
         assert_eq!(result, filter_result)
     }
 
@@ -261,18 +639,39 @@ mod tests {
         let resources = setup_resources();
         let policies = setup_policies();
 
-        let result = evaluate_policy(&policies.policies[0], &resources[0]);
-        let filter = Filter::new("policy.maxReceiveCount", "eq", "2.0"); 
-        let filter_result = FilterResult::new(filter, true);
+        let result = evaluate_policy(&policies.policies[0], &resources[0], &InfrastructureGraph::new());
+        let filter = Filter::new("policy.maxReceiveCount", Op::Eq, "2.0"); 
+        // the only filter decided the policy passed
+        let filter_result = FilterResult { decided: true, ..FilterResult::new(filter, true) };
         let expected = PolicyResult{
             filters: vec![filter_result],
             policy_id: policies.policies[0].name.to_string(),
             policy_result: true,
+            span: Span::default(),
         };
 
         assert_eq!(result, expected)
     }
 
+    #[test]
+    fn policy_result_points_at_source_lines() {
+        let data = r#"
+resource "aws_iam_role_policy" "located-policy" {
+    name = "located-policy"
+    visibility_timeout_seconds = 30
+}
+"#;
+        let (resources, _) = root(data);
+        let policies = setup_policies();
+
+        let result = evaluate_policy(&policies.policies[2], &resources[0], &InfrastructureGraph::new());
+        let filter_span = result.filters[0].span.clone().unwrap();
+
+        assert_eq!((result.span.start.line, result.span.end.line), (2, 5));
+        assert_eq!((filter_span.start.line, filter_span.start.column), (4, 5));
+        assert_eq!(filter_span.end.column, 36)
+    }
+
     #[test]
     fn unique_policy_resources_test() {
         let policies = setup_policies();
@@ -289,9 +688,9 @@ mod tests {
         let result = evaluate(policies, &resources);
         let mut expected_map: HashMap<String, Vec<PolicyResult>> = HashMap::new();
         
-        let f1 = Filter::new("id", "eq", "id-that-we-are-looking-for2");
-        let f2 = Filter::new("visibility_timeout_seconds", "eq", "3.1");
-        let f3 = Filter::new("policy.maxReceiveCount", "eq", "2.0");
+        let f1 = Filter::new("id", Op::Eq, "id-that-we-are-looking-for2");
+        let f2 = Filter::new("visibility_timeout_seconds", Op::Eq, "3.1");
+        let f3 = Filter::new("policy.maxReceiveCount", Op::Eq, "2.0");
         let filter_result1 = FilterResult::new(f1, false);
         let filter_result2= FilterResult::new(f2, false);
         let filter_result3 = FilterResult::new(f3, false);
@@ -309,4 +708,308 @@ mod tests {
         assert_eq!(result.contains_key(&resources[1].get_id()), true);
         assert_eq!(result.contains_key(&resources[2].get_id()), true);
     }
+
+    #[test]
+    fn filter_ops_are_type_aware() {
+        let found = |value: Value| Some(value);
+        let ops = |op: Op, value: Option<Value>, expected: Value| apply_op(op, value.as_ref(), &expected);
+
+        assert!(ops(Op::Eq, found(json!(30)), json!("30")));
+        assert!(ops(Op::Eq, found(json!(30)), json!(30.0)));
+        assert!(!ops(Op::Eq, found(json!(30)), json!("thirty")));
+        assert!(ops(Op::Eq, found(json!(true)), json!("true")));
+        assert!(ops(Op::Eq, found(json!(["a", "b"])), json!("[\"a\",\"b\"]")));
+        assert!(ops(Op::Ne, found(json!("Deny")), json!("Allow")));
+        assert!(ops(Op::Gt, found(json!(900)), json!("300")));
+        assert!(ops(Op::Le, found(json!("30")), json!(30)));
+        assert!(!ops(Op::Lt, found(json!(true)), json!(1)));
+        assert!(ops(Op::In, found(json!("us-east-1")), json!(["eu-west-1", "us-east-1"])));
+        assert!(ops(Op::NotIn, found(json!(128)), json!([256, 512])));
+        assert!(ops(Op::Contains, found(json!(["s3:GetObject", "s3:PutObject"])), json!("s3:PutObject")));
+        assert!(ops(Op::Contains, found(json!({ "Environment": "prod" })), json!("Environment")));
+        assert!(ops(Op::Contains, found(json!("arn:aws:sqs:*")), json!("sqs")));
+        assert!(ops(Op::StartsWith, found(json!("arn:aws:kms:key")), json!("arn:aws:kms")));
+        assert!(ops(Op::EndsWith, found(json!("index.handler")), json!(".handler")));
+        assert!(ops(Op::Glob, found(json!("arn:aws:s3:::logs/2020/today")), json!("arn:aws:s3:::logs/**")));
+        assert!(!ops(Op::Glob, found(json!(7)), json!("*")));
+        assert!(ops(Op::Exists, found(json!(null)), Value::Null));
+        assert!(ops(Op::Absent, None, Value::Null));
+        assert!(!ops(Op::Eq, None, json!("anything")));
+        assert!(!ops(Op::Ne, None, json!("anything")));
+        assert!(ops(Op::Empty, found(json!([])), Value::Null));
+        assert!(ops(Op::Empty, found(json!("")), Value::Null));
+        assert!(!ops(Op::Empty, found(json!({ "a": 1 })), Value::Null));
+        assert!(ops(Op::LenEq, found(json!(["a", "b"])), json!(2)));
+        assert!(ops(Op::LenGe, found(json!("abc")), json!("3")));
+        assert!(ops(Op::LenLt, found(json!({})), json!(1)));
+    }
+
+    #[test]
+    fn ops_apply_to_json_and_block_values() {
+        let data = r#"{"resource": {"aws_sqs_queue": {"queue": {
+            "name": "orders",
+            "visibility_timeout_seconds": 900,
+            "tags": {"Environment": "prod"},
+            "policy": "{\"Version\": \"2012-10-17\", \"Statement\": [{\"Effect\": \"Allow\"}, {\"Effect\": \"Deny\"}]}"
+        }}}}"#;
+        let (resources, _) = crate::terraform_json::root(data);
+        let policy = |op: Op, key: &str, value: Value| {
+            let filter = Filter::with_value(key, op, value);
+            evaluate_policy(&Policy::new("policy", "", "aws_sqs_queue", vec![filter]), &resources[0], &InfrastructureGraph::new()).policy_result
+        };
+
+        assert!(policy(Op::Ge, "visibility_timeout_seconds", json!(300)));
+        assert!(policy(Op::Contains, "tags", json!("Environment")));
+        assert!(policy(Op::Absent, "kms_master_key_id", Value::Null));
+        assert!(policy(Op::Exists, "policy.Statement", Value::Null));
+        assert!(policy(Op::LenEq, "policy.Statement", json!(2)));
+        assert!(policy(Op::Eq, "policy.Version", json!("2012-10-17")));
+        assert!(!policy(Op::Eq, "policy.Version", json!("2008-10-17")));
+        assert!(policy(Op::Regex, "name", json!("^ord")));
+    }
+
+    #[test]
+    fn unknown_ops_and_unsuitable_values_fail_to_load() {
+        let policies = |filter: &str| serde_yaml::from_str::<Policies>(&format!(
+            "policies:\n - name: check\n   description: ''\n   resource: aws_sqs_queue\n   filters:\n     - {}\n", filter));
+
+        let unknown = policies("{ key: name, op: matches, value: orders }").unwrap_err();
+        assert!(unknown.to_string().contains("unknown variant `matches`"));
+
+        let loaded = policies("{ key: name, op: equals, value: orders }").unwrap();
+        assert_eq!(loaded.policies[0].filters[0].filters()[0].op, Op::Eq);
+        assert!(loaded.validate().is_ok());
+        assert!(policies("{ key: tags, op: exists }").unwrap().validate().is_ok());
+        assert_eq!(policies("{ key: name, op: in, value: orders }").unwrap().validate(),
+            Err(String::from("policy check, filter on name: `in` needs a list of values")));
+        assert!(policies("{ key: name, op: regex, value: '(' }").unwrap().validate().unwrap_err().starts_with("policy check, filter on name: regex parse error"));
+        assert!(policies("{ key: name, op: len-gt, value: long }").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn patterns_are_compiled_when_validated() {
+        let policies: Policies = serde_yaml::from_str("policies:\n - name: check\n   description: ''\n   resource: aws_iam_policy\n   filters:\n\
+            \x20    - { key: name, op: regex, value: '^ord' }\n\
+            \x20    - { key: 'statement[]', op: filter, value: [{ key: sid, op: regex, value: '^Allow' }] }\n").unwrap();
+        let name = policies.policies[0].filters[0].filters()[0];
+        let statement = policies.policies[0].filters[1].filters()[0];
+        assert!(name.pattern.get().is_none());
+        assert!(statement.elements.get().is_none());
+
+        policies.validate().unwrap();
+        assert_eq!(name.pattern.get().map(|regex| regex.as_str()), Some("^ord"));
+        let sid = statement.elements.get().unwrap()[0].filters()[0];
+        assert_eq!(sid.pattern.get().map(|regex| regex.as_str()), Some("^Allow"));
+        // evaluating uses what was compiled, rather than compiling it again
+        assert!(std::ptr::eq(name.regex().unwrap(), name.pattern.get().unwrap()));
+    }
+
+    #[test]
+    fn filter_trees_report_the_branch_which_decided() {
+        let data = r#"{"resource": {"aws_s3_bucket": {
+            "logs": {"bucket_prefix": "dev-logs"},
+            "orders": {"bucket_prefix": "orders", "acl": "public-read"}
+        }}}"#;
+        let (resources, _) = crate::terraform_json::root(data);
+        let yaml = "
+policies:
+ - name: encrypted-or-dev
+   description: buckets are encrypted unless they're in dev, and never public
+   resource: aws_s3_bucket
+   filters:
+     - or:
+         - key: server_side_encryption_configuration
+           op: exists
+         - key: bucket_prefix
+           op: starts-with
+           value: dev-
+     - not:
+         key: acl
+         op: starts-with
+         value: public
+";
+        let policies: Policies = serde_yaml::from_str(yaml).unwrap();
+        let policy = &policies.policies[0];
+
+        let logs = evaluate_policy(policy, &resources[0], &InfrastructureGraph::new());
+        assert!(logs.policy_result);
+        let decided: Vec<(&str, bool)> = logs.filters.iter().map(|filter| (filter.branch(), filter.decided())).collect();
+        assert_eq!(decided, vec![("filters[0].or[0]", false), ("filters[0].or[1]", true), ("filters[1].not", true)]);
+
+        let orders = evaluate_policy(policy, &resources[1], &InfrastructureGraph::new());
+        assert!(!orders.policy_result);
+        let decided: Vec<(&str, bool, bool)> = orders.filters.iter().map(|filter| (filter.branch(), filter.result(), filter.decided())).collect();
+        assert_eq!(decided, vec![("filters[0].or[0]", false, true), ("filters[0].or[1]", false, true), ("filters[1].not", true, true)]);
+
+        // a tree reads back as it was written
+        let written = serde_yaml::to_string(&policies).unwrap();
+        assert_eq!(serde_yaml::from_str::<Policies>(&written).unwrap(), policies);
+    }
+
+    #[test]
+    fn malformed_filter_trees_fail_to_load() {
+        let policies = |filter: &str| serde_yaml::from_str::<Policies>(&format!(
+            "policies:\n - name: check\n   description: ''\n   resource: aws_sqs_queue\n   filters:\n     - {}\n", filter));
+
+        assert!(policies("{ and: [{ key: name, op: exists }], key: name, op: exists }").is_err());
+        assert!(policies("{ or: [{ key: name }] }").is_err());
+        assert!(policies("{ not: { key: name, op: matches } }").unwrap_err().to_string().contains("unknown variant `matches`"));
+        assert_eq!(policies("{ not: { or: [{ key: name, op: exists }] } }").unwrap().policies[0].filters[0],
+            FilterTree::Not(Box::new(FilterTree::Or(vec![FilterTree::Filter(Filter::with_value("name", Op::Exists, Value::Null))]))));
+    }
+
+    #[test]
+    fn filter_op_tests_each_element_of_a_list() {
+        let data = r#"{"resource": {"aws_iam_role_policy": {"policy": {
+            "policy": "{\"Statement\": [{\"Effect\": \"Allow\", \"Action\": [\"dynamodb:GetItem\", \"dynamodb:UpdateItem\"], \"Resource\": \"*\"}, {\"Effect\": \"Allow\", \"Action\": \"sns:Publish\", \"Resource\": \"arn:aws:sns:us-east-1:123456789012:topic\"}]}"
+        }}, "aws_security_group": {"group": {
+            "ingress": [{"from_port": 22, "cidr_blocks": ["0.0.0.0/0"]}, {"from_port": 443, "cidr_blocks": ["10.0.0.0/8"]}]
+        }}}}"#;
+        let (resources, _) = crate::terraform_json::root(data);
+        let yaml = "
+key: 'policy.Statement[]'
+op: filter
+value:
+  - key: Effect
+    op: eq
+    value: Allow
+  - key: Action
+    op: contains
+    value: 'dynamodb:UpdateItem'
+  - key: Resource
+    op: eq
+    value: '*'
+";
+        let wildcard_updates: FilterTree = serde_yaml::from_str(yaml).unwrap();
+        let filters = |quantifier: Quantifier| match &wildcard_updates {
+            FilterTree::Filter(filter) => Filter { quantifier: Some(quantifier), ..filter.clone() },
+            _ => unreachable!(),
+        };
+        let policy = |resource: usize, filter: Filter| {
+            evaluate_policy(&Policy::new("policy", "", "aws_iam_role_policy", vec![filter]), &resources[resource], &InfrastructureGraph::new()).policy_result
+        };
+        let count = |op: Op, value: usize| Quantifier::Count(Count { op, value: json!(value) });
+
+        // the sub-filters must all pass on one statement, not each on any statement
+        assert!(policy(0, filters(Quantifier::Any)));
+        assert!(!policy(0, filters(Quantifier::All)));
+        assert!(!policy(0, filters(Quantifier::None)));
+        assert!(policy(0, filters(count(Op::Eq, 1))));
+        assert!(!policy(0, filters(count(Op::Ge, 2))));
+        let split = Filter::elements("policy.Statement[]", Quantifier::Any, vec![
+            FilterTree::Filter(Filter::new("Action", Op::Contains, "sns:Publish")),
+            FilterTree::Filter(Filter::new("Resource", Op::Eq, "*")),
+        ]);
+        assert!(!policy(0, split));
+
+        // a key which finds no list has no elements
+        let missing = |quantifier: Quantifier| Filter { key: String::from("inline_policy[]"), ..filters(quantifier) };
+        assert!(!policy(0, missing(Quantifier::Any)));
+        assert!(policy(0, missing(Quantifier::None)));
+
+        let open_ssh = Filter::elements("ingress[]", Quantifier::None, vec![
+            FilterTree::Filter(Filter::new("from_port", Op::Eq, "22")),
+            FilterTree::Filter(Filter::new("cidr_blocks", Op::Contains, "0.0.0.0/0")),
+        ]);
+        assert!(!policy(1, open_ssh.clone()));
+        assert!(policy(1, Filter { quantifier: Some(count(Op::Eq, 1)), ..open_ssh }));
+    }
+
+    #[test]
+    fn malformed_filter_ops_fail_to_load() {
+        let policies = |filter: &str| serde_yaml::from_str::<Policies>(&format!(
+            "policies:\n - name: check\n   description: ''\n   resource: aws_iam_policy\n   filters:\n     - {}\n", filter))
+            .map_err(|error| error.to_string())
+            .and_then(|policies| policies.validate());
+
+        assert!(policies("{ key: 'policy.Statement[]', op: filter, quantifier: all, value: [{ key: Effect, op: eq, value: Allow }] }").is_ok());
+        assert!(policies("{ key: 'policy.Statement[]', op: filter, quantifier: { count: { op: ge, value: 2 } }, value: [{ key: Effect, op: exists }] }").is_ok());
+        assert!(policies("{ key: 'policy.Statement', op: filter, value: [{ key: Effect, op: exists }] }").unwrap_err().contains("needs a key ending in []"));
+        assert!(policies("{ key: 'policy.Statement[]', op: filter, value: Allow }").unwrap_err().contains("needs a list of filters"));
+        assert!(policies("{ key: 'policy.Statement[]', op: filter, value: [{ key: Action, op: in, value: x }] }").unwrap_err()
+            .contains("filter on policy.Statement[]: filter on Action: `in` needs a list of values"));
+        assert!(policies("{ key: 'policy.Statement[]', op: filter, quantifier: { count: { op: contains, value: 2 } }, value: [] }").is_err());
+        assert!(policies("{ key: name, op: eq, value: x, quantifier: all }").unwrap_err().contains("only `filter` takes a quantifier"));
+        assert!(policies("{ key: 'policy.Statement[]', op: filter, quantifier: some, value: [] }").is_err());
+    }
+
+    #[test]
+    fn graph_filters_follow_edges() {
+        use crate::graph::{ EdgeKind, Provenance };
+
+        let data = r#"{"resource": {
+            "aws_sqs_queue": {"orders": {}, "unread": {}},
+            "aws_lambda_function": {"worker": {"role": "aws_iam_role.worker"}},
+            "aws_iam_role": {"worker": {}},
+            "aws_dynamodb_table": {"orders": {}}
+        }}"#;
+        let (resources, _) = crate::terraform_json::root(data);
+        let mut graph = InfrastructureGraph::from_blocks(&resources);
+        let mut edge = |source: &str, target: &str, label: &str, kind: EdgeKind| graph.add_edge(Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            label: label.to_string(),
+            kind,
+            provenance: Provenance::new(source, None),
+        });
+        edge("aws_sqs_queue.orders", "aws_lambda_function.worker", "", EdgeKind::Relationship);
+        edge("aws_lambda_function.worker", "aws_iam_role.worker", "", EdgeKind::Relationship);
+        edge("aws_iam_role.worker", "aws_dynamodb_table.orders", "['dynamodb:GetItem','dynamodb:UpdateItem']", EdgeKind::Permission);
+        let mut table = graph.node("aws_dynamodb_table.orders").unwrap().clone();
+        table.module = Some(String::from("module.tables"));
+        graph.add_node(table);
+
+        let resource = |address: &str| resources.iter().find(|resource| resource.address() == address).unwrap();
+        let passes = |address: &str, filter: Filter| {
+            evaluate_policy(&Policy::new("policy", "", "any", vec![filter]), resource(address), &graph).policy_result
+        };
+        let is = |key: &str, value: &str| FilterTree::Filter(Filter::new(key, Op::Eq, value));
+
+        // every queue has a consumer
+        let consumed = Filter::elements("outgoing[]", Quantifier::Any, vec![is("type", "aws_lambda_function")]);
+        assert!(passes("aws_sqs_queue.orders", consumed.clone()));
+        assert!(!passes("aws_sqs_queue.unread", consumed));
+
+        // functions triggered by a queue
+        let triggered = Filter::elements("incoming[]", Quantifier::Any, vec![is("type", "aws_sqs_queue"), is("direction", "incoming")]);
+        assert!(passes("aws_lambda_function.worker", triggered));
+
+        // paths reach further than their first edge, and describe the edge they end with
+        let updates = Filter::elements("outgoing[]", Quantifier::Any, vec![
+            is("type", "aws_dynamodb_table"),
+            is("kind", "permission"),
+            FilterTree::Filter(Filter::new("label", Op::Contains, "dynamodb:UpdateItem")),
+            FilterTree::Filter(Filter::new("path", Op::Contains, "aws_iam_role.worker")),
+            is("depth", "2"),
+        ]);
+        assert!(!passes("aws_lambda_function.worker", updates.clone()));
+        assert!(passes("aws_lambda_function.worker", Filter { depth: Some(2), ..updates }));
+        let upstream = Filter::elements("connected[]", Quantifier::Count(Count { op: Op::Eq, value: json!(3) }), vec![]);
+        assert!(passes("aws_lambda_function.worker", Filter { depth: Some(2), ..upstream }));
+
+        // roles only reach tables in their own module
+        let outside = Filter::elements("outgoing[]", Quantifier::None, vec![is("type", "aws_dynamodb_table"), is("same_module", "false")]);
+        assert!(!passes("aws_iam_role.worker", outside));
+
+        // any op applies to the list of connections
+        assert!(passes("aws_sqs_queue.unread", Filter { value: json!(0), ..Filter::new("connected[]", Op::LenEq, "") }));
+
+        let policies = Policies { policies: vec![Policy::new("consumed", "", "aws_sqs_queue", vec![
+            Filter::elements("outgoing[]", Quantifier::Any, vec![is("type", "aws_lambda_function")]),
+        ])] };
+        let failed: Vec<String> = evaluate_graph(policies.clone(), &resources, &graph).into_keys().collect();
+        assert_eq!(failed, vec![String::from("aws_sqs_queue_unread")]);
+        assert_eq!(evaluate(policies, &resources).len(), 2);
+    }
+
+    #[test]
+    fn graph_filter_depths_fail_to_load() {
+        let filter = |yaml: &str| serde_yaml::from_str::<FilterTree>(yaml).unwrap().filters()[0].validate();
+
+        assert!(filter("{ key: 'outgoing[]', op: filter, depth: 3, value: [{ key: type, op: eq, value: aws_sqs_queue }] }").is_ok());
+        assert!(filter("{ key: 'incoming[]', op: len-ge, value: 1 }").is_ok());
+        assert_eq!(filter("{ key: 'outgoing[]', op: len-ge, value: 1, depth: 0 }"), Err(String::from("a depth is from 1 to 8")));
+        assert_eq!(filter("{ key: 'policy.Statement[]', op: len-ge, value: 1, depth: 2 }"),
+            Err(String::from("only `outgoing[]`, `incoming[]` and `connected[]` take a depth")));
+    }
 }
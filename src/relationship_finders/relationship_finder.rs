@@ -1,7 +1,6 @@
-
 use crate::visitors::relationship_visitor::{Relationship};
+use crate::graph::{ EdgeKind, Provenance };
 
 pub trait RelationshipFinder {
-    fn add_relationship(&self, relationship: Relationship);
-    fn output_relationships(&self) -> String;
+    fn add_relationship(&self, relationship: Relationship, kind: EdgeKind, provenance: Provenance);
 }
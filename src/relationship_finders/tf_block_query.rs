@@ -70,6 +70,42 @@ pub mod tf_block_query {
             None => TFQueryResult::None,
         }
     }
+
+    /// the elements of the list an expression ending in `[]` finds, eg. each statement of `policy.Statement[]`
+    /// or each of a resource's `ingress[]` blocks. None when the expression doesn't end in a list or finds nothing.
+    pub fn jmespath_list_query(tf_block: &TerraformBlockWithTwoIdentifiers, jmespath_expression: &str) -> Option<Vec<AttributeType>> {
+        let expression = parse_jmespath(jmespath_expression);
+
+        match expression.path_parts.as_slice() {
+            // a nested block written several times is an attribute for each time
+            [PathPart::List(key)] => {
+                let found: Vec<&Attribute> = tf_block.attributes.iter().filter(|attr| &attr.key == key).collect();
+                match found.as_slice() {
+                    [] => None,
+                    [attr] => Some(elements(attr.value.clone())),
+                    attrs => Some(attrs.iter().map(|attr| attr.value.clone()).collect()),
+                }
+            },
+            [.., PathPart::List(_)] => tf_block.query(expression).map(|found| match found {
+                // json finds a Block of the values under the last key of each object it matched
+                AttributeType::Block(matches) => matches.into_iter().flat_map(|attr| elements(attr.value)).collect(),
+                AttributeType::Array(found) => found.into_iter().flat_map(|found| match found {
+                    AttributeType::Block(matches) => matches.into_iter().flat_map(|attr| elements(attr.value)).collect(),
+                    found => vec![found],
+                }).collect(),
+                found => elements(found),
+            }),
+            _ => None,
+        }
+    }
+
+    /// a list's elements, or anything else as a list of itself
+    fn elements(value: AttributeType) -> Vec<AttributeType> {
+        match value {
+            AttributeType::Array(values) => values,
+            value => vec![value],
+        }
+    }
 }
 
 
@@ -77,6 +113,7 @@ pub mod tf_block_query {
 mod tests {
     use super::*;
     use super::tf_block_query::*;
+    use crate::structs::span::Span;
 
     fn example_resource() -> TerraformBlockWithTwoIdentifiers {
         TerraformBlockWithTwoIdentifiers {
@@ -85,7 +122,8 @@ mod tests {
             second_identifier: String::from("discovery_scheduler_role_policy"),
             attributes: vec![
                 Attribute {
-                    key: String::from("depends_on"), value: Array(vec![AttributeType::Str(String::from("aws_iam_role.discovery_scheduler_role"))])
+                    key: String::from("depends_on"), value: Array(vec![AttributeType::Str(String::from("aws_iam_role.discovery_scheduler_role"))]),
+                    span: Span::default()
                 },
                 Attribute {
                     key: String::from("policy"),
@@ -134,15 +172,22 @@ mod tests {
                             ])
                         ])),
                         (String::from("Version"), JsonValue::Str(String::from("2012-10-17")))
-                    ]))
+                    ])),
+                    span: Span::default()
                 },
                 Attribute {
-                    key: String::from("role"), value: TemplatedString(TemplateString::Variable(String::from("aws_iam_role.discovery_scheduler_role.id")))
+                    key: String::from("role"), value: TemplatedString(TemplateString::Variable(String::from("aws_iam_role.discovery_scheduler_role.id"))),
+                    span: Span::default()
                 },
                 Attribute {
-                    key: String::from("name"), value: AttributeType::Str(String::from("discovery_scheduler_role_policy"))
+                    key: String::from("name"), value: AttributeType::Str(String::from("discovery_scheduler_role_policy")),
+                    span: Span::default()
                 }
-            ]
+            ],
+            span: Span::default(),
+            module_path: vec![],
+            planned_actions: vec![],
+            references: vec![],
         }
     }
 
@@ -174,23 +219,28 @@ mod tests {
             Array(vec![
                 Block(vec![
                     Attribute { key: String::from("Resource"), value: Array(vec![
-                        Str(String::from("arn:aws:logs:*:*:log-group:/aws/lambda/*discovery_scheduler*"))]) 
+                        Str(String::from("arn:aws:logs:*:*:log-group:/aws/lambda/*discovery_scheduler*"))]),
+                        span: Span::default() 
                     }, 
                     Attribute { key: String::from("Resource"), value: Array(vec![
                         Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_collector-config/*")), 
                         Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_collector-config")), 
                         Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_tenant-config/*")), 
-                        Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_tenant-config"))])
+                        Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_tenant-config"))]),
+                        span: Span::default()
                     }, 
                     Attribute { key: String::from("Resource"), value: Array(vec![
                         Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_tenant-config/*")), 
-                        Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_tenant-config"))])
+                        Str(String::from("arn:aws:dynamodb:us-east-1:309983114184:table/discovery_tenant-config"))]),
+                        span: Span::default()
                     }, 
                     Attribute { key: String::from("Resource"), value: Array(vec![
-                        Str(String::from("arn:aws:sns:us-east-1:309983114184:discovery_scheduled-discovery-topic"))])
+                        Str(String::from("arn:aws:sns:us-east-1:309983114184:discovery_scheduled-discovery-topic"))]),
+                        span: Span::default()
                     }, 
                     Attribute { key: String::from("Resource"), value: Array(vec![
-                        Str(String::from("arn:aws:events:us-east-1:309983114184:rule/discovery_scheduler-rule"))])
+                        Str(String::from("arn:aws:events:us-east-1:309983114184:rule/discovery_scheduler-rule"))]),
+                        span: Span::default()
                     }
                 ])
             ])
@@ -209,4 +259,24 @@ mod tests {
         );
         assert_eq!(result, expected)
     }
+
+    #[test]
+    fn query_tf_block_for_list_elements() {
+        let resource = example_resource();
+
+        let statements = tf_block_query::jmespath_list_query(&resource, "policy.Statement[]").unwrap();
+        assert_eq!(statements.len(), 5);
+        assert_eq!(statements[3], Block(vec![
+            Attribute { key: String::from("Action"), value: Array(vec![Str(String::from("sns:Publish"))]), span: Span::default() },
+            Attribute { key: String::from("Effect"), value: Str(String::from("Allow")), span: Span::default() },
+            Attribute { key: String::from("Resource"), value: Array(vec![
+                Str(String::from("arn:aws:sns:us-east-1:309983114184:discovery_scheduled-discovery-topic"))
+            ]), span: Span::default() },
+        ]));
+
+        let depends_on = tf_block_query::jmespath_list_query(&resource, "depends_on[]");
+        assert_eq!(depends_on, Some(vec![Str(String::from("aws_iam_role.discovery_scheduler_role"))]));
+        assert_eq!(tf_block_query::jmespath_list_query(&resource, "policy.Statement"), None);
+        assert_eq!(tf_block_query::jmespath_list_query(&resource, "tags[]"), None);
+    }
 }
@@ -0,0 +1,66 @@
+//! JUnit XML, for CI dashboards. Each policy is a test suite, and each resource it was evaluated against a test case
+//! which fails when the resource fails the policy, with a failure listing the filters which weren't met.
+
+use crate::exporters::xml_escape;
+use crate::reporters::{ Report, describe };
+
+pub fn render(report: &Report) -> String {
+    let policies = &report.policies.policies;
+    let tests: usize = policies.iter().map(|policy| report.checked(policy)).sum();
+
+    let mut junit = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    junit.push_str(&format!("<testsuites name=\"infrastructure-graph\" tests=\"{}\" failures=\"{}\">\n", tests, report.failures.len()));
+    for policy in policies {
+        let failures = report.failures_of(policy);
+        junit.push_str(&format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+            xml_escape(&policy.name), report.checked(policy), failures.len()));
+
+        for resource in report.checked_resources(policy) {
+            let address = resource.address();
+            let span = resource.span();
+            let location = match (&span.file, span.start.line) {
+                (Some(file), 0) => format!(" file=\"{}\"", xml_escape(file)),
+                (Some(file), line) => format!(" file=\"{}\" line=\"{}\"", xml_escape(file), line),
+                (None, _) => String::new(),
+            };
+            junit.push_str(&format!("    <testcase classname=\"{}\" name=\"{}\"{}", xml_escape(&policy.name), xml_escape(&address), location));
+
+            match failures.iter().find(|failure| failure.resource.address() == address) {
+                Some(failure) => {
+                    let filters: Vec<String> = failure.failed_filters().iter().map(|filter| describe(filter)).collect();
+                    junit.push_str(&format!(">\n      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>\n",
+                        xml_escape(&failure.message()), policy.severity, xml_escape(&filters.join("\n"))));
+                },
+                None => junit.push_str("/>\n"),
+            }
+        }
+        junit.push_str("  </testsuite>\n");
+    }
+    junit.push_str("</testsuites>\n");
+
+    junit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::evaluate;
+    use crate::reporters::tests::{ policies, resources };
+
+    #[test]
+    fn junit_has_a_test_case_for_each_policy_and_resource() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let report = Report::new(&policies, &resources, &results);
+
+        let junit = render(&report);
+        assert!(junit.contains("<testsuites name=\"infrastructure-graph\" tests=\"3\" failures=\"2\">\n"));
+        assert!(junit.contains("  <testsuite name=\"sqs-short-timeout\" tests=\"2\" failures=\"1\">\n"));
+        assert!(junit.contains("    <testcase classname=\"sqs-short-timeout\" name=\"aws_sqs_queue.fast\" file=\"main.tf.json\" line=\"2\"/>\n"));
+        assert!(junit.contains("    <testcase classname=\"sqs-short-timeout\" name=\"aws_sqs_queue.slow\" file=\"main.tf.json\" line=\"2\">\n\
+            \x20     <failure message=\"aws_sqs_queue.slow fails sqs-short-timeout: visibility_timeout_seconds eq 30\" type=\"high\">\
+            visibility_timeout_seconds eq 30</failure>\n    </testcase>\n"));
+        assert!(junit.ends_with("  </testsuite>\n</testsuites>\n"));
+    }
+}
@@ -0,0 +1,86 @@
+//! Markdown, for a bot to post on pull requests: the failures grouped by policy, the most severe first,
+//! each linking to the line of the template it was found at.
+
+use crate::reporters::{ Report, describe, location };
+use crate::structs::span::Span;
+
+/// text which can sit in a table cell
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// text in a code span, which can't hold its delimiter
+fn code(text: &str) -> String {
+    format!("`{}`", cell(text).replace('`', "'"))
+}
+
+fn link(span: &Span, link_base: Option<&str>) -> String {
+    match (location(span), &span.file) {
+        (Some(location), Some(file)) => format!("[{}]({}{}#L{})", cell(&location), link_base.unwrap_or(""), file.replace('\\', "/").replace(' ', "%20"), span.start.line),
+        _ => String::new(),
+    }
+}
+
+pub fn render(report: &Report, link_base: Option<&str>) -> String {
+    let mut markdown = String::from("## Policy violations\n\n");
+    let failing_policies = report.policies.policies.iter().filter(|policy| report.failed(policy) > 0).count();
+    markdown.push_str(&format!("{} failures of {} policies in {} resources.\n", report.failures.len(), failing_policies, report.resources.len()));
+
+    // the failures are ordered by severity, so the policies are too
+    let mut policies: Vec<&str> = vec![];
+    for failure in &report.failures {
+        if !policies.contains(&failure.policy.name.as_str()) {
+            policies.push(&failure.policy.name);
+        }
+    }
+
+    for name in policies {
+        let policy = report.policies.policies.iter().find(|policy| policy.name == name).unwrap();
+        markdown.push_str(&format!("\n### {} ({})\n", policy.name, policy.severity));
+        if !policy.description.is_empty() {
+            markdown.push_str(&format!("{}\n", policy.description));
+        }
+        markdown.push_str("\n| Resource | Location | Failed filters |\n| --- | --- | --- |\n");
+        for failure in report.failures_of(policy) {
+            let filters: Vec<String> = failure.failed_filters().iter().map(|filter| code(&describe(filter))).collect();
+            markdown.push_str(&format!("| {} | {} | {} |\n", code(&failure.resource.address()), link(failure.span(), link_base), filters.join("<br>")));
+        }
+    }
+
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::evaluate;
+    use crate::reporters::tests::{ policies, resources };
+
+    #[test]
+    fn markdown_groups_failures_by_policy() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let report = Report::new(&policies, &resources, &results);
+
+        assert_eq!(render(&report, Some("https://github.com/org/infra/blob/main/")), "\
+## Policy violations
+
+2 failures of 2 policies in 3 resources.
+
+### sqs-short-timeout (high)
+queues should time out quickly
+
+| Resource | Location | Failed filters |
+| --- | --- | --- |
+| `aws_sqs_queue.slow` | [main.tf.json:2](https://github.com/org/infra/blob/main/main.tf.json#L2) | `visibility_timeout_seconds eq 30` |
+
+### lambda-small (low)
+functions should be small
+
+| Resource | Location | Failed filters |
+| --- | --- | --- |
+| `aws_lambda_function.worker` | [main.tf.json:3](https://github.com/org/infra/blob/main/main.tf.json#L3) | `memory_size eq 128` |
+");
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{ HashMap, HashSet };
+
+use std::str::FromStr;
+
+use crate::policy_evaluator::{ FilterResult, PolicyResult };
+use crate::structs::policies::{ Policies, Policy, Severity };
+use crate::structs::span::Span;
+use crate::structs::terraform_block::TerraformBlock;
+
+pub mod junit;
+pub mod markdown;
+pub mod sarif;
+pub mod text;
+
+/// the formats `check` can report in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Text,
+    /// SARIF 2.1.0, for code scanning views
+    Sarif,
+    /// JUnit XML, a test case for each resource a policy was evaluated against
+    JUnit,
+    /// for posting on pull requests
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "text" => Ok(Format::Text),
+            "sarif" => Ok(Format::Sarif),
+            "junit" => Ok(Format::JUnit),
+            "markdown" | "md" => Ok(Format::Markdown),
+            _ => Err(format!("unknown format '{}', expected one of text, sarif, junit or markdown", name)),
+        }
+    }
+}
+
+/// what the reporters need besides the report
+#[derive(Debug, Default)]
+pub struct ReportOptions {
+    /// the least severe failure which fails the check
+    pub threshold: Severity,
+    /// what links to templates in markdown start with, eg. `https://github.com/org/repo/blob/main/`
+    pub link_base: Option<String>,
+}
+
+pub fn render(format: Format, report: &Report, options: &ReportOptions) -> String {
+    match format {
+        Format::Text => text::render(report, options.threshold),
+        Format::Sarif => sarif::render(report),
+        Format::JUnit => junit::render(report),
+        Format::Markdown => markdown::render(report, options.link_base.as_deref()),
+    }
+}
+
+/// a policy a resource failed
+#[derive(Debug)]
+pub struct Failure<'a> {
+    pub policy: &'a Policy,
+    pub resource: &'a TerraformBlock,
+    pub result: &'a PolicyResult,
+}
+
+impl<'a> Failure<'a> {
+    /// the filters which decided the resource failed: those it didn't meet, or in a `not`, those it did
+    pub fn failed_filters(&self) -> Vec<&'a FilterResult> {
+        self.result.filters().iter()
+            .filter(|filter| filter.decided())
+            .collect()
+    }
+
+    /// where the failure is: the attribute of the first failed filter found in the template, otherwise the resource
+    pub fn span(&self) -> &'a Span {
+        self.failed_filters().into_iter()
+            .find_map(|filter| filter.span())
+            .unwrap_or_else(|| self.resource.span())
+    }
+
+    /// eg. `aws_sqs_queue.slow fails sqs-short-timeout: visibility_timeout_seconds eq 30`
+    pub fn message(&self) -> String {
+        let filters: Vec<String> = self.failed_filters().iter().map(|filter| describe(filter)).collect();
+        format!("{} fails {}: {}", self.resource.address(), self.policy.name, filters.join(", "))
+    }
+}
+
+/// a filter as it's written in a policy, eg. `visibility_timeout_seconds eq 30`, after the group it's in if any,
+/// eg. `filters[1].not: acl starts-with public`
+pub fn describe(filter_result: &FilterResult) -> String {
+    let filter = filter_result.filter();
+    let described = format!("{} {} {}", filter.key, filter.op, filter.value_text()).trim_end().to_string();
+    match filter_result.branch() {
+        "" => described,
+        branch => format!("{}: {}", branch, described),
+    }
+}
+
+/// the outcome of evaluating policies against resources, which each reporter writes in its own format
+#[derive(Debug)]
+pub struct Report<'a> {
+    pub policies: &'a Policies,
+    pub resources: &'a [TerraformBlock],
+    /// the most severe first, then by policy and resource address
+    pub failures: Vec<Failure<'a>>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(policies: &'a Policies, resources: &'a [TerraformBlock], policy_results: &'a HashMap<String, Vec<PolicyResult>>) -> Report<'a> {
+        let mut failures = vec![];
+        let mut seen = HashSet::new();
+        for resource in resources {
+            // the results are keyed by id, which blocks of the same type and name share
+            if !seen.insert(resource.get_id()) {
+                continue;
+            }
+            for result in policy_results.get(&resource.get_id()).into_iter().flatten() {
+                if let Some(policy) = policies.policies.iter().find(|policy| policy.name == result.policy_id()) {
+                    failures.push(Failure { policy, resource, result });
+                }
+            }
+        }
+        failures.sort_by(|a, b| b.policy.severity.cmp(&a.policy.severity)
+            .then_with(|| a.policy.name.cmp(&b.policy.name))
+            .then_with(|| a.resource.address().cmp(&b.resource.address())));
+
+        Report { policies, resources, failures }
+    }
+
+    /// the resources a policy was evaluated against, those of the type it names
+    pub fn checked_resources(&self, policy: &Policy) -> Vec<&'a TerraformBlock> {
+        self.resources.iter().filter(|resource| resource_type(resource) == Some(&policy.resource)).collect()
+    }
+
+    pub fn checked(&self, policy: &Policy) -> usize {
+        self.checked_resources(policy).len()
+    }
+
+    /// the failures of a policy, in order of resource address
+    pub fn failures_of(&self, policy: &Policy) -> Vec<&Failure<'a>> {
+        self.failures.iter().filter(|failure| failure.policy.name == policy.name).collect()
+    }
+
+    pub fn failed(&self, policy: &Policy) -> usize {
+        self.failures_of(policy).len()
+    }
+
+    /// the failures of policies at or above a severity, those which fail a check
+    pub fn at_or_above(&self, threshold: Severity) -> usize {
+        self.failures.iter().filter(|failure| failure.policy.severity >= threshold).count()
+    }
+}
+
+/// the type policies name to select a block by, eg. `aws_sqs_queue`
+fn resource_type(resource: &TerraformBlock) -> Option<&String> {
+    match resource {
+        TerraformBlock::NoIdentifiers(_) => None,
+        TerraformBlock::WithOneIdentifier(block) => Some(&block.first_identifier),
+        TerraformBlock::WithTwoIdentifiers(block) => Some(&block.first_identifier),
+    }
+}
+
+/// a line of a template, eg. `main.tf:12`
+pub fn location(span: &Span) -> Option<String> {
+    span.file.as_ref().map(|file| format!("{}:{}", file, span.start.line))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::policy_evaluator::evaluate;
+    use crate::structs::policies::{ Filter, FilterTree, Op };
+
+    pub(crate) fn policies() -> Policies {
+        Policies {
+            policies: vec![
+                Policy::new("sqs-short-timeout", "queues should time out quickly", "aws_sqs_queue",
+                    vec![Filter::new("visibility_timeout_seconds", Op::Eq, "30")]).with_severity(Severity::High),
+                Policy::new("lambda-small", "functions should be small", "aws_lambda_function",
+                    vec![Filter::new("memory_size", Op::Eq, "128")]).with_severity(Severity::Low),
+            ]
+        }
+    }
+
+    pub(crate) fn resources() -> Vec<TerraformBlock> {
+        let data = r#"{"resource": {
+            "aws_sqs_queue": {"fast": {"visibility_timeout_seconds": 30}, "slow": {"visibility_timeout_seconds": 900}},
+            "aws_lambda_function": {"worker": {"memory_size": 1024}}
+        }}"#;
+        let (mut resources, _) = crate::terraform_json::root(data);
+        for resource in resources.iter_mut() {
+            resource.for_each_span_mut(&mut |span| span.file = Some(String::from("main.tf.json")));
+        }
+        resources
+    }
+
+    #[test]
+    fn report_orders_failures_by_severity() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let report = Report::new(&policies, &resources, &results);
+
+        let failures: Vec<(String, &str)> = report.failures.iter()
+            .map(|failure| (failure.resource.address(), failure.policy.name.as_str()))
+            .collect();
+        assert_eq!(failures, vec![
+            (String::from("aws_sqs_queue.slow"), "sqs-short-timeout"),
+            (String::from("aws_lambda_function.worker"), "lambda-small"),
+        ]);
+        assert_eq!(report.checked(&policies.policies[0]), 2);
+        assert_eq!(report.failed(&policies.policies[0]), 1);
+        assert_eq!(report.at_or_above(Severity::Low), 2);
+        assert_eq!(report.at_or_above(Severity::High), 1);
+        assert_eq!(report.at_or_above(Severity::Critical), 0);
+    }
+
+    #[test]
+    fn failures_describe_the_filters_which_decided() {
+        let policy = Policy::new("sqs-not-short", "queues shouldn't time out quickly", "aws_sqs_queue", vec![])
+            .with_filters(vec![FilterTree::Not(Box::new(FilterTree::Or(vec![
+                FilterTree::Filter(Filter::new("visibility_timeout_seconds", Op::Lt, "60")),
+                FilterTree::Filter(Filter::new("visibility_timeout_seconds", Op::Gt, "3600")),
+            ])))]);
+        let policies = Policies { policies: vec![policy] };
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let report = Report::new(&policies, &resources, &results);
+
+        let messages: Vec<String> = report.failures.iter().map(|failure| failure.message()).collect();
+        assert_eq!(messages, vec![
+            String::from("aws_sqs_queue.fast fails sqs-not-short: filters[0].not.or[0]: visibility_timeout_seconds lt 60"),
+        ]);
+    }
+}
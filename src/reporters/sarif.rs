@@ -0,0 +1,279 @@
+//! SARIF 2.1.0, the Static Analysis Results Interchange Format GitHub and GitLab code scanning read.
+//! Each policy is a rule, and each policy a resource fails a result located at the attribute of the filter which failed.
+
+use serde::Serialize;
+
+use crate::reporters::{ Failure, Report };
+use crate::structs::policies::{ Policy, Severity };
+use crate::structs::span::Span;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const INFORMATION_URI: &str = "https://github.com/jamesw201/infrastructure-graph";
+
+#[derive(Serialize)]
+struct Log<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run<'a>>,
+}
+
+#[derive(Serialize)]
+struct Run<'a> {
+    tool: Tool<'a>,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize)]
+struct Tool<'a> {
+    driver: Driver<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver<'a> {
+    name: &'static str,
+    information_uri: &'static str,
+    version: &'static str,
+    rules: Vec<Rule<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule<'a> {
+    id: &'a str,
+    short_description: Message,
+    default_configuration: Configuration,
+    properties: RuleProperties,
+}
+
+#[derive(Serialize)]
+struct Configuration {
+    level: &'static str,
+}
+
+#[derive(Serialize)]
+struct RuleProperties {
+    /// the CVSS-like score GitHub ranks security alerts by
+    #[serde(rename = "security-severity")]
+    security_severity: &'static str,
+    tags: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: String,
+    rule_index: usize,
+    level: &'static str,
+    message: Message,
+    locations: Vec<Location>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    physical_location: Option<PhysicalLocation>,
+    logical_locations: Vec<LogicalLocation>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<Region>,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogicalLocation {
+    fully_qualified_name: String,
+    kind: &'static str,
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Low => "note",
+        Severity::Medium => "warning",
+        Severity::High | Severity::Critical => "error",
+    }
+}
+
+fn security_severity(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Low => "3.0",
+        Severity::Medium => "5.0",
+        Severity::High => "7.5",
+        Severity::Critical => "9.5",
+    }
+}
+
+fn rule(policy: &Policy) -> Rule<'_> {
+    Rule {
+        id: &policy.name,
+        short_description: Message { text: policy.description.to_string() },
+        default_configuration: Configuration { level: level(policy.severity) },
+        properties: RuleProperties { security_severity: security_severity(policy.severity), tags: vec!["security", "infrastructure"] },
+    }
+}
+
+/// the file of a span, with `/` separators as SARIF's URIs have, and its region when its lines are known
+fn physical_location(span: &Span) -> Option<PhysicalLocation> {
+    let file = span.file.as_ref()?;
+    let region = if span.start.line > 0 {
+        Some(Region { start_line: span.start.line, start_column: span.start.column, end_line: span.end.line, end_column: span.end.column })
+    } else {
+        None
+    };
+    Some(PhysicalLocation { artifact_location: ArtifactLocation { uri: file.replace('\\', "/") }, region })
+}
+
+fn result(failure: &Failure, rule_index: usize) -> SarifResult {
+    SarifResult {
+        rule_id: failure.policy.name.to_string(),
+        rule_index,
+        level: level(failure.policy.severity),
+        message: Message { text: failure.message() },
+        locations: vec![Location {
+            physical_location: physical_location(failure.span()),
+            logical_locations: vec![LogicalLocation { fully_qualified_name: failure.resource.address(), kind: "resource" }],
+        }],
+    }
+}
+
+pub fn render(report: &Report) -> String {
+    let policies = &report.policies.policies;
+    let results = report.failures.iter()
+        .map(|failure| result(failure, policies.iter().position(|policy| policy.name == failure.policy.name).unwrap_or(0)))
+        .collect();
+
+    let log = Log {
+        schema: SCHEMA,
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: "infrastructure-graph",
+                    information_uri: INFORMATION_URI,
+                    version: env!("CARGO_PKG_VERSION"),
+                    rules: policies.iter().map(rule).collect(),
+                },
+            },
+            results,
+        }],
+    };
+
+    let mut sarif = serde_json::to_string_pretty(&log).unwrap();
+    sarif.push('\n');
+    sarif
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use crate::policy_evaluator::evaluate;
+    use crate::reporters::tests::{ policies, resources };
+    use crate::structs::span::Position;
+
+    #[test]
+    fn sarif_reports_policies_as_rules_and_failures_as_results() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let report = Report::new(&policies, &resources, &results);
+
+        let sarif: Value = serde_json::from_str(&render(&report)).unwrap();
+        assert_eq!(sarif["version"], "2.1.0");
+
+        let driver = &sarif["runs"][0]["tool"]["driver"];
+        assert_eq!(driver["rules"][0], serde_json::json!({
+            "id": "sqs-short-timeout",
+            "shortDescription": { "text": "queues should time out quickly" },
+            "defaultConfiguration": { "level": "error" },
+            "properties": { "security-severity": "7.5", "tags": ["security", "infrastructure"] },
+        }));
+        assert_eq!(driver["rules"][1]["defaultConfiguration"]["level"], "note");
+
+        let results = &sarif["runs"][0]["results"];
+        assert_eq!(results[0]["ruleId"], "sqs-short-timeout");
+        assert_eq!(results[0]["ruleIndex"], 0);
+        assert_eq!(results[0]["message"]["text"], "aws_sqs_queue.slow fails sqs-short-timeout: visibility_timeout_seconds eq 30");
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "main.tf.json");
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["region"]["startLine"], 2);
+        assert_eq!(results[0]["locations"][0]["logicalLocations"][0]["fullyQualifiedName"], "aws_sqs_queue.slow");
+        assert_eq!(results[1]["ruleIndex"], 1);
+    }
+
+    #[test]
+    fn rules_are_identified_by_policy_and_ranked_by_severity() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let sarif: Value = serde_json::from_str(&render(&Report::new(&policies, &resources, &results))).unwrap();
+
+        let rules = &sarif["runs"][0]["tool"]["driver"]["rules"];
+        let ids: Vec<&Value> = rules.as_array().unwrap().iter().map(|rule| &rule["id"]).collect();
+        assert_eq!(ids, vec!["sqs-short-timeout", "lambda-small"]);
+        // each result's rule is the one at its index
+        for result in sarif["runs"][0]["results"].as_array().unwrap() {
+            assert_eq!(rules[result["ruleIndex"].as_u64().unwrap() as usize]["id"], result["ruleId"]);
+            assert_eq!(rules[result["ruleIndex"].as_u64().unwrap() as usize]["defaultConfiguration"]["level"], result["level"]);
+        }
+
+        let ranked: Vec<(&str, &str)> = [Severity::Low, Severity::Medium, Severity::High, Severity::Critical].iter()
+            .map(|severity| (level(*severity), security_severity(*severity)))
+            .collect();
+        assert_eq!(ranked, vec![("note", "3.0"), ("warning", "5.0"), ("error", "7.5"), ("error", "9.5")]);
+    }
+
+    #[test]
+    fn results_are_located_at_the_attribute_which_failed() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let sarif: Value = serde_json::from_str(&render(&Report::new(&policies, &resources, &results))).unwrap();
+
+        let slow = resources.iter().find(|resource| resource.address() == "aws_sqs_queue.slow").unwrap();
+        let span = &slow.attributes()[0].span;
+        assert_eq!(sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"], serde_json::json!({
+            "artifactLocation": { "uri": "main.tf.json" },
+            "region": { "startLine": span.start.line, "startColumn": span.start.column, "endLine": span.end.line, "endColumn": span.end.column },
+        }));
+
+        let span = |file: Option<&str>, line: usize| Span {
+            file: file.map(String::from),
+            start: Position { offset: 40, line, column: 5 },
+            end: Position { offset: 66, line: line + 1, column: 2 },
+        };
+        let located = serde_json::to_value(physical_location(&span(Some(r"modules\queues\main.tf"), 3))).unwrap();
+        assert_eq!(located, serde_json::json!({
+            "artifactLocation": { "uri": "modules/queues/main.tf" },
+            "region": { "startLine": 3, "startColumn": 5, "endLine": 4, "endColumn": 2 },
+        }));
+        // a file without lines, such as a plan, is located by its file alone, and a resource without a file by its address
+        assert_eq!(serde_json::to_value(physical_location(&span(Some("plan.json"), 0))).unwrap(), serde_json::json!({ "artifactLocation": { "uri": "plan.json" } }));
+        assert!(physical_location(&span(None, 3)).is_none());
+    }
+}
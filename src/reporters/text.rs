@@ -0,0 +1,88 @@
+//! The report `check` prints for people: a table of the failures, a table of how each policy fared and a verdict.
+
+use crate::reporters::{ Report, location };
+use crate::structs::policies::Severity;
+
+/// left aligned columns, two spaces apart, with no padding after the last
+fn table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.chars().count()).max().unwrap_or(0))
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row.iter().enumerate()
+                .map(|(column, cell)| if column + 1 == row.len() { cell.to_string() } else { format!("{:width$}", cell, width = widths[column]) })
+                .collect();
+            cells.join("  ") + "\n"
+        })
+        .collect()
+}
+
+pub fn render(report: &Report, threshold: Severity) -> String {
+    let mut text = String::new();
+
+    if !report.failures.is_empty() {
+        let mut failures = vec![vec![String::from("SEVERITY"), String::from("POLICY"), String::from("RESOURCE"), String::from("LOCATION")]];
+        for failure in &report.failures {
+            failures.push(vec![
+                failure.policy.severity.to_string(),
+                failure.policy.name.to_string(),
+                failure.resource.address(),
+                location(failure.span()).unwrap_or_default(),
+            ]);
+        }
+        text.push_str(&table(&failures));
+        text.push('\n');
+    }
+
+    let mut policies: Vec<_> = report.policies.policies.iter().collect();
+    policies.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.name.cmp(&b.name)));
+    let mut summary = vec![vec![String::from("POLICY"), String::from("SEVERITY"), String::from("CHECKED"), String::from("FAILED")]];
+    for policy in policies {
+        summary.push(vec![
+            policy.name.to_string(),
+            policy.severity.to_string(),
+            report.checked(policy).to_string(),
+            report.failed(policy).to_string(),
+        ]);
+    }
+    text.push_str(&table(&summary));
+
+    let failing = report.at_or_above(threshold);
+    text.push_str(&format!("\n{} resources checked against {} policies: {} failures, {} at or above {}, {}\n",
+        report.resources.len(), report.policies.policies.len(), report.failures.len(), failing, threshold,
+        if failing == 0 { "passed" } else { "failed" }));
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::evaluate;
+    use crate::reporters::tests::{ policies, resources };
+
+    #[test]
+    fn text_report_tabulates_failures_and_policies() {
+        let policies = policies();
+        let resources = resources();
+        let results = evaluate(policies.clone(), &resources);
+        let report = Report::new(&policies, &resources, &results);
+
+        let text = render(&report, Severity::High);
+        assert_eq!(text, "\
+SEVERITY  POLICY             RESOURCE                    LOCATION
+high      sqs-short-timeout  aws_sqs_queue.slow          main.tf.json:2
+low       lambda-small       aws_lambda_function.worker  main.tf.json:3
+
+POLICY             SEVERITY  CHECKED  FAILED
+sqs-short-timeout  high      2        1
+lambda-small       low       1        1
+
+3 resources checked against 2 policies: 2 failures, 1 at or above high, failed
+");
+        assert!(render(&report, Severity::Critical).ends_with("2 failures, 0 at or above critical, passed\n"));
+    }
+}
@@ -0,0 +1,558 @@
+//! Resolver
+//! - collects `variable` defaults, `locals` blocks and the values given in .tfvars files or with `-var`
+//! - substitutes them for the `var.*` and `local.*` references in every attribute, so policies and
+//!   relationships see concrete values rather than references
+//! - folds whatever becomes constant once substituted, eg. `"${var.environment}-queue"` or `var.n > 1 ? 2 : 1`
+//! - substitutes the `output` values of the local modules expanded so far for `module.*` references,
+//!   see ModuleExpander
+//! - references it cannot resolve are left in place and reported as UnresolvedReferences
+//!
+//! Values given with `-var` take precedence over .tfvars files, which take precedence over defaults.
+
+use std::collections::HashMap;
+
+use crate::terraform::tfvars;
+use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::parse_error::ParseError;
+use crate::structs::span::Span;
+use crate::structs::template_string::{
+    BinaryOperator, Expression, Reference, TemplatePart, TemplateString, TraversalStep, UnaryOperator,
+};
+use crate::structs::terraform_block::TerraformBlock;
+use crate::structs::unresolved_reference::UnresolvedReference;
+
+#[derive(Debug, Default)]
+pub struct Resolver {
+    defaults: HashMap<String, Option<AttributeType>>,
+    overrides: HashMap<String, AttributeType>,
+    locals: HashMap<String, AttributeType>,
+    modules: HashMap<String, HashMap<String, AttributeType>>,
+}
+
+/// a reference which could not be substituted and why
+type Problem = (String, String);
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    /// record the variables declared, with their defaults, and the locals defined in `resources`
+    pub fn collect(&mut self, resources: &[TerraformBlock]) {
+        for resource in resources {
+            match resource {
+                TerraformBlock::WithOneIdentifier(block) if block.block_type == "variable" => {
+                    let default = block.attributes.iter().find(|attr| attr.key == "default").map(|attr| attr.value.clone());
+                    self.defaults.insert(block.first_identifier.to_string(), default);
+                },
+                TerraformBlock::NoIdentifiers(block) if block.block_type == "locals" => {
+                    for attr in &block.attributes {
+                        self.locals.insert(attr.key.to_string(), attr.value.clone());
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// override variables with the assignments read from a .tfvars file
+    pub fn set_variables(&mut self, assignments: Vec<Attribute>) {
+        for assignment in assignments {
+            self.overrides.insert(assignment.key, assignment.value);
+        }
+    }
+
+    /// override a variable from a `-var name=value` flag. As with terraform, a value which
+    /// isn't a literal is taken to be a string, so `-var environment=prod` needs no quotes.
+    pub fn set_variable(&mut self, assignment: &str) -> Result<(), ParseError> {
+        let (name, value) = match assignment.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => return Err(ParseError::at(assignment, 0, "a variable assignment (`name=value`)")),
+        };
+
+        let (mut assignments, errors) = tfvars(&format!("{} = {}", name, value));
+        let value = match assignments.pop() {
+            Some(Attribute { value: AttributeType::Expression(_), .. }) | Some(Attribute { value: AttributeType::TemplatedString(_), .. }) | None => {
+                AttributeType::Str(value.trim_matches('"').to_string())
+            },
+            Some(_) if !errors.is_empty() => AttributeType::Str(value.to_string()),
+            Some(assignment) => assignment.value,
+        };
+
+        self.overrides.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// record the `output` values of an expanded module. References to modules which were
+    /// never expanded, eg. those fetched from a registry, are left alone.
+    pub fn set_module_outputs(&mut self, name: &str, outputs: HashMap<String, AttributeType>) {
+        self.modules.insert(name.to_string(), outputs);
+    }
+
+    /// substitute variables, locals and module outputs into every block other than the `variable` declarations
+    pub fn resolve(&self, resources: Vec<TerraformBlock>) -> (Vec<TerraformBlock>, Vec<UnresolvedReference>) {
+        let mut unresolved = vec![];
+
+        let resolved = resources.into_iter().map(|mut resource| {
+            if resource.block_type() != "variable" {
+                let block = resource.address();
+                for attribute in resource.attributes_mut().iter_mut() {
+                    self.resolve_attribute(attribute, "", &block, &mut unresolved);
+                }
+            }
+            resource
+        }).collect();
+
+        (resolved, unresolved)
+    }
+
+    fn resolve_attribute(&self, attribute: &mut Attribute, parent: &str, block: &str, unresolved: &mut Vec<UnresolvedReference>) {
+        let path = if parent.is_empty() { attribute.key.to_string() } else { format!("{}.{}", parent, attribute.key) };
+
+        if let AttributeType::Block(attributes) = &mut attribute.value {
+            for nested in attributes.iter_mut() {
+                self.resolve_attribute(nested, &path, block, unresolved);
+            }
+            return;
+        }
+
+        let mut problems = vec![];
+        attribute.value = self.resolve_value(&attribute.value, &mut vec![], &mut problems);
+
+        let mut reported: Vec<Problem> = vec![];
+        for problem in problems {
+            if reported.contains(&problem) {
+                continue;
+            }
+            reported.push(problem.clone());
+            let (reference, reason) = problem;
+            unresolved.push(UnresolvedReference {
+                reference,
+                block: block.to_string(),
+                attribute: path.clone(),
+                reason,
+                span: attribute.span.clone(),
+            });
+        }
+    }
+
+    fn resolve_value(&self, value: &AttributeType, locals: &mut Vec<String>, problems: &mut Vec<Problem>) -> AttributeType {
+        match value {
+            AttributeType::TemplatedString(template_string) => {
+                let expression = template_string.to_expression();
+                if !self.refers_to_inputs(&expression) {
+                    return value.clone();
+                }
+                let resolved = self.resolve_expression(expression, locals, problems);
+                literal_value(&resolved).unwrap_or_else(|| AttributeType::TemplatedString(TemplateString::from_expression(resolved)))
+            },
+            AttributeType::Expression(expression) => {
+                if !self.refers_to_inputs(expression) {
+                    return value.clone();
+                }
+                let resolved = self.resolve_expression(expression.clone(), locals, problems);
+                literal_value(&resolved).unwrap_or(AttributeType::Expression(resolved))
+            },
+            AttributeType::Array(values) => AttributeType::Array(
+                values.iter().map(|value| self.resolve_value(value, locals, problems)).collect()
+            ),
+            AttributeType::Block(attributes) => AttributeType::Block(
+                attributes.iter().map(|attr| Attribute { value: self.resolve_value(&attr.value, locals, problems), ..attr.clone() }).collect()
+            ),
+            AttributeType::TFBlock(block) => {
+                let mut block = block.clone();
+                for attr in block.attributes_mut().iter_mut() {
+                    attr.value = self.resolve_value(&attr.value, locals, problems);
+                }
+                AttributeType::TFBlock(block)
+            },
+            _ => value.clone(),
+        }
+    }
+
+    /// whether the resolver has a value to substitute for the reference
+    fn is_input(&self, reference: &Reference) -> bool {
+        match reference {
+            Reference::Variable { .. } | Reference::Local { .. } => true,
+            Reference::Module { name, .. } => self.modules.contains_key(name),
+            _ => false,
+        }
+    }
+
+    fn refers_to_inputs(&self, expression: &Expression) -> bool {
+        expression.references().iter().any(|reference| self.is_input(reference))
+    }
+
+    fn variable(&self, name: &str) -> Result<&AttributeType, &'static str> {
+        match (self.overrides.get(name), self.defaults.get(name)) {
+            (Some(value), _) => Ok(value),
+            (None, Some(Some(default))) => Ok(default),
+            (None, Some(None)) => Err("the variable has no default and no value was given for it"),
+            (None, None) => Err("no such variable is declared"),
+        }
+    }
+
+    fn resolve_reference(&self, reference: &Reference, locals: &mut Vec<String>, problems: &mut Vec<Problem>) -> Result<Expression, String> {
+        let value = match reference {
+            Reference::Variable { name, .. } => self.variable(name).cloned().map_err(String::from)?,
+            Reference::Local { name, .. } => {
+                if locals.contains(name) {
+                    return Err(String::from("the local refers to itself"));
+                }
+                let value = self.locals.get(name).ok_or_else(|| String::from("no such local is defined"))?;
+
+                locals.push(name.to_string());
+                let resolved = self.resolve_value(value, locals, problems);
+                locals.pop();
+                resolved
+            },
+            Reference::Module { name, output, .. } => {
+                let outputs = self.modules.get(name).ok_or_else(|| String::from("the module was not expanded"))?;
+                match output {
+                    Some(output) => outputs.get(output).cloned().ok_or_else(|| String::from("the module has no such output"))?,
+                    None => {
+                        let mut names: Vec<&String> = outputs.keys().collect();
+                        names.sort();
+                        AttributeType::Block(names.into_iter()
+                            .map(|name| Attribute { key: name.to_string(), value: outputs[name].clone(), span: Span::default() })
+                            .collect())
+                    },
+                }
+            },
+            _ => unreachable!(),
+        };
+
+        value_expression(&value).ok_or_else(|| String::from("its value cannot be substituted into an expression"))
+    }
+
+    fn resolve_steps(&self, steps: Vec<TraversalStep>, locals: &mut Vec<String>, problems: &mut Vec<Problem>) -> Vec<TraversalStep> {
+        steps.into_iter().map(|step| match step {
+            TraversalStep::Index(index) => TraversalStep::Index(self.resolve_expression(index, locals, problems)),
+            step => step,
+        }).collect()
+    }
+
+    fn resolve_expression(&self, expression: Expression, locals: &mut Vec<String>, problems: &mut Vec<Problem>) -> Expression {
+        let resolved = match expression {
+            Expression::Variable(mut traversal) => {
+                let reference = traversal.reference();
+                traversal.steps = self.resolve_steps(traversal.steps, locals, problems);
+
+                match reference {
+                    Some(reference) if self.is_input(&reference) => {
+                        match self.resolve_reference(&reference, locals, problems) {
+                            Ok(value) => {
+                                let steps = traversal.steps[traversal.steps.len() - reference.steps().len()..].to_vec();
+                                apply_steps(value, steps)
+                            },
+                            Err(reason) => {
+                                problems.push((reference.address(), reason));
+                                Expression::Variable(traversal)
+                            },
+                        }
+                    },
+                    _ => Expression::Variable(traversal),
+                }
+            },
+            Expression::FunctionCall(mut function) => {
+                function.args = function.args.into_iter().map(|arg| self.resolve_expression(arg, locals, problems)).collect();
+                Expression::FunctionCall(function)
+            },
+            Expression::Access { target, steps } => {
+                let target = self.resolve_expression(*target, locals, problems);
+                apply_steps(target, self.resolve_steps(steps, locals, problems))
+            },
+            Expression::Tuple(items) => Expression::Tuple(items.into_iter().map(|item| self.resolve_expression(item, locals, problems)).collect()),
+            Expression::Object(items) => Expression::Object(
+                items.into_iter().map(|(key, value)| (key, self.resolve_expression(value, locals, problems))).collect()
+            ),
+            Expression::Unary { operator, operand } => Expression::Unary { operator, operand: Box::new(self.resolve_expression(*operand, locals, problems)) },
+            Expression::Binary { operator, left, right } => Expression::Binary {
+                operator,
+                left: Box::new(self.resolve_expression(*left, locals, problems)),
+                right: Box::new(self.resolve_expression(*right, locals, problems)),
+            },
+            Expression::Conditional { condition, true_result, false_result } => Expression::Conditional {
+                condition: Box::new(self.resolve_expression(*condition, locals, problems)),
+                true_result: Box::new(self.resolve_expression(*true_result, locals, problems)),
+                false_result: Box::new(self.resolve_expression(*false_result, locals, problems)),
+            },
+            Expression::Parenthesized(inner) => Expression::Parenthesized(Box::new(self.resolve_expression(*inner, locals, problems))),
+            Expression::Template(parts) => Expression::Template(parts.into_iter().map(|part| match part {
+                TemplatePart::Interpolation(expression) => TemplatePart::Interpolation(self.resolve_expression(expression, locals, problems)),
+                literal => literal,
+            }).collect()),
+            literal => literal,
+        };
+
+        fold(resolved)
+    }
+}
+
+
+fn object_key(key: &Expression) -> Option<String> {
+    match key {
+        Expression::Str(key) => Some(key.to_string()),
+        Expression::Variable(traversal) if traversal.steps.is_empty() => Some(traversal.root.to_string()),
+        _ => None,
+    }
+}
+
+/// the value of an attribute as an expression, None for the values expressions cannot hold
+fn value_expression(value: &AttributeType) -> Option<Expression> {
+    match value {
+        AttributeType::Str(value) => Some(Expression::Str(value.to_string())),
+        AttributeType::Num(value) => Some(Expression::Num(*value)),
+        AttributeType::Boolean(value) => Some(Expression::Boolean(*value)),
+        AttributeType::Array(values) => values.iter().map(value_expression).collect::<Option<Vec<Expression>>>().map(Expression::Tuple),
+        AttributeType::Block(attributes) => attributes.iter()
+            .map(|attr| value_expression(&attr.value).map(|value| (Expression::Str(attr.key.to_string()), value)))
+            .collect::<Option<Vec<(Expression, Expression)>>>()
+            .map(Expression::Object),
+        AttributeType::TemplatedString(template_string) => Some(template_string.to_expression()),
+        AttributeType::Expression(expression) => Some(expression.clone()),
+        AttributeType::TFBlock(_) | AttributeType::Json(_) => None,
+    }
+}
+
+/// the attribute value of a constant expression, None while anything in it is still unknown
+fn literal_value(expression: &Expression) -> Option<AttributeType> {
+    match expression {
+        Expression::Str(value) => Some(AttributeType::Str(value.to_string())),
+        Expression::Num(value) => Some(AttributeType::Num(*value)),
+        Expression::Boolean(value) => Some(AttributeType::Boolean(*value)),
+        Expression::Tuple(items) => items.iter().map(literal_value).collect::<Option<Vec<AttributeType>>>().map(AttributeType::Array),
+        Expression::Object(items) => items.iter()
+            .map(|(key, value)| Some(Attribute { key: object_key(key)?, value: literal_value(value)?, span: Span::default() }))
+            .collect::<Option<Vec<Attribute>>>()
+            .map(AttributeType::Block),
+        _ => None,
+    }
+}
+
+/// take `.name` and `[index]` steps into constant tuples and objects, leaving any steps that can't be taken
+fn apply_steps(target: Expression, steps: Vec<TraversalStep>) -> Expression {
+    let mut current = target;
+    let mut remaining = steps.into_iter().peekable();
+
+    while let Some(step) = remaining.peek() {
+        let next = match (&current, step) {
+            (Expression::Object(items), TraversalStep::Attribute(name)) | (Expression::Object(items), TraversalStep::Index(Expression::Str(name))) => {
+                items.iter().find(|(key, _)| object_key(key).as_ref() == Some(name)).map(|(_, value)| value.clone())
+            },
+            (Expression::Tuple(items), TraversalStep::Index(Expression::Num(index))) if *index >= 0.0 => items.get(*index as usize).cloned(),
+            _ => None,
+        };
+        match next {
+            Some(value) => {
+                current = value;
+                remaining.next();
+            },
+            None => break,
+        }
+    }
+
+    let steps: Vec<TraversalStep> = remaining.collect();
+    match current {
+        _ if steps.is_empty() => current,
+        Expression::Variable(mut traversal) => {
+            traversal.steps.extend(steps);
+            Expression::Variable(traversal)
+        },
+        target => Expression::Access { target: Box::new(target), steps },
+    }
+}
+
+fn is_constant(expression: &Expression) -> bool {
+    matches!(expression, Expression::Str(_) | Expression::Num(_) | Expression::Boolean(_) | Expression::Null)
+}
+
+/// evaluate the operators whose operands are now constant
+fn fold(expression: Expression) -> Expression {
+    use BinaryOperator::*;
+    use Expression::{Boolean, Num, Str};
+
+    match expression {
+        Expression::Parenthesized(inner) if is_constant(&inner) => *inner,
+        Expression::Unary { operator: UnaryOperator::Not, operand } if matches!(*operand, Boolean(_)) => match *operand {
+            Boolean(value) => Boolean(!value),
+            _ => unreachable!(),
+        },
+        Expression::Unary { operator: UnaryOperator::Negate, operand } if matches!(*operand, Num(_)) => match *operand {
+            Num(value) => Num(-value),
+            _ => unreachable!(),
+        },
+        Expression::Binary { operator, left, right } => {
+            let folded = match (operator, left.as_ref(), right.as_ref()) {
+                (Add, Num(l), Num(r)) => Some(Num(l + r)),
+                (Subtract, Num(l), Num(r)) => Some(Num(l - r)),
+                (Multiply, Num(l), Num(r)) => Some(Num(l * r)),
+                (Divide, Num(l), Num(r)) if *r != 0.0 => Some(Num(l / r)),
+                (Modulo, Num(l), Num(r)) if *r != 0.0 => Some(Num(l % r)),
+                (Less, Num(l), Num(r)) => Some(Boolean(l < r)),
+                (LessOrEqual, Num(l), Num(r)) => Some(Boolean(l <= r)),
+                (Greater, Num(l), Num(r)) => Some(Boolean(l > r)),
+                (GreaterOrEqual, Num(l), Num(r)) => Some(Boolean(l >= r)),
+                (And, Boolean(l), Boolean(r)) => Some(Boolean(*l && *r)),
+                (Or, Boolean(l), Boolean(r)) => Some(Boolean(*l || *r)),
+                (Equal, l, r) if is_constant(l) && is_constant(r) => Some(Boolean(l == r)),
+                (NotEqual, l, r) if is_constant(l) && is_constant(r) => Some(Boolean(l != r)),
+                _ => None,
+            };
+            folded.unwrap_or(Expression::Binary { operator, left, right })
+        },
+        Expression::Conditional { condition, true_result, false_result } => match *condition {
+            Boolean(true) => *true_result,
+            Boolean(false) => *false_result,
+            condition => Expression::Conditional { condition: Box::new(condition), true_result, false_result },
+        },
+        Expression::Template(parts) => {
+            let mut folded: Vec<TemplatePart> = vec![];
+            for part in parts {
+                let part = match part {
+                    TemplatePart::Interpolation(Str(text)) => TemplatePart::Literal(text),
+                    TemplatePart::Interpolation(Num(value)) => TemplatePart::Literal(value.to_string()),
+                    TemplatePart::Interpolation(Boolean(value)) => TemplatePart::Literal(value.to_string()),
+                    part => part,
+                };
+                match (folded.last_mut(), part) {
+                    (Some(TemplatePart::Literal(text)), TemplatePart::Literal(more)) => text.push_str(&more),
+                    (_, part) => folded.push(part),
+                }
+            }
+
+            match folded.as_slice() {
+                [] => Str(String::new()),
+                [TemplatePart::Literal(text)] => Str(text.to_string()),
+                _ => Expression::Template(folded),
+            }
+        },
+        expression => expression,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terraform::root;
+
+    fn resolve(data: &str, tfvars_data: &str, vars: &[&str]) -> (Vec<TerraformBlock>, Vec<UnresolvedReference>) {
+        let (resources, errors) = root(data);
+        assert_eq!(errors, vec![]);
+
+        let mut resolver = Resolver::new();
+        resolver.collect(&resources);
+        resolver.set_variables(tfvars(tfvars_data).0);
+        for var in vars {
+            resolver.set_variable(var).unwrap();
+        }
+        resolver.resolve(resources)
+    }
+
+    fn value<'a>(resources: &'a [TerraformBlock], address: &str, key: &str) -> &'a AttributeType {
+        let resource = resources.iter().find(|resource| resource.address() == address).unwrap();
+        &resource.attributes().iter().find(|attr| attr.key == key).unwrap().value
+    }
+
+    const TEMPLATE: &str = r#"
+variable "environment" {
+    default = "sandbox"
+}
+
+variable "instances" {
+    default = 1
+}
+
+variable "team" {
+    type = "string"
+}
+
+locals {
+    prefix = "discovery-${var.environment}"
+    tags = { Environment = var.environment }
+}
+
+resource "aws_sqs_queue" "queue" {
+    name     = "${local.prefix}-queue"
+    count    = var.instances > 1 ? var.instances : 1
+    tags     = local.tags
+    owner    = var.team
+    policy   = aws_iam_policy.queue.arn
+}
+"#;
+
+    #[test]
+    fn substitutes_defaults_and_locals() {
+        let (resources, unresolved) = resolve(TEMPLATE, "", &["team=platform"]);
+
+        assert_eq!(unresolved, vec![]);
+        assert_eq!(value(&resources, "aws_sqs_queue.queue", "name"), &AttributeType::Str(String::from("discovery-sandbox-queue")));
+        assert_eq!(value(&resources, "aws_sqs_queue.queue", "count"), &AttributeType::Num(1.0));
+        assert_eq!(value(&resources, "aws_sqs_queue.queue", "owner"), &AttributeType::Str(String::from("platform")));
+        assert_eq!(value(&resources, "aws_sqs_queue.queue", "tags"), &AttributeType::Block(vec![
+            Attribute { key: String::from("Environment"), value: AttributeType::Str(String::from("sandbox")), span: Span::default() }
+        ]));
+        assert!(matches!(value(&resources, "aws_sqs_queue.queue", "policy"), AttributeType::Expression(_)));
+    }
+
+    #[test]
+    fn var_flags_override_tfvars_which_override_defaults() {
+        let (resources, _) = resolve(TEMPLATE, "environment = \"prod\"\ninstances = 3\n", &["instances=5", "team=platform"]);
+
+        assert_eq!(value(&resources, "aws_sqs_queue.queue", "name"), &AttributeType::Str(String::from("discovery-prod-queue")));
+        assert_eq!(value(&resources, "aws_sqs_queue.queue", "count"), &AttributeType::Num(5.0));
+    }
+
+    #[test]
+    fn unresolved_references_are_reported() {
+        let data = format!("{}{}", TEMPLATE, r#"
+resource "aws_sns_topic" "topic" {
+    name = "${var.missing}-${local.prefix}"
+}
+"#);
+        let (resources, unresolved) = resolve(&data, "", &[]);
+
+        let reported: Vec<(&str, &str, &str)> = unresolved.iter()
+            .map(|problem| (problem.reference.as_str(), problem.block.as_str(), problem.attribute.as_str()))
+            .collect();
+        assert_eq!(reported, vec![
+            ("var.team", "aws_sqs_queue.queue", "owner"),
+            ("var.missing", "aws_sns_topic.topic", "name"),
+        ]);
+        assert_eq!(unresolved[0].span.start.line, 23);
+        assert_eq!(value(&resources, "aws_sns_topic.topic", "name").clone(), AttributeType::TemplatedString(TemplateString::Template(vec![
+            TemplatePart::Interpolation(Expression::Variable(crate::structs::template_string::Traversal::new(
+                "var",
+                vec![TraversalStep::Attribute(String::from("missing"))],
+            ))),
+            TemplatePart::Literal(String::from("-discovery-sandbox")),
+        ])));
+    }
+
+    #[test]
+    fn repeated_references_are_reported_once() {
+        let data = r#"
+resource "aws_sns_topic" "topic" {
+    name = "${var.missing}-${var.other}-${var.missing}"
+}
+"#;
+        let (_, unresolved) = resolve(data, "", &[]);
+
+        let reported: Vec<&str> = unresolved.iter().map(|problem| problem.reference.as_str()).collect();
+        assert_eq!(reported, vec!["var.missing", "var.other"]);
+    }
+
+    #[test]
+    fn self_referencing_locals_are_reported() {
+        let data = r#"
+locals {
+    a = local.b
+    b = local.a
+}
+"#;
+        let (_, unresolved) = resolve(data, "", &[]);
+
+        assert_eq!(unresolved.len(), 2);
+        assert_eq!(unresolved[0].reason, "the local refers to itself");
+    }
+}
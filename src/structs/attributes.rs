@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 use crate::structs::terraform_block::TerraformBlock;
-use crate::structs::template_string::{ TemplateString };
+use crate::structs::template_string::{ Expression, TemplateString };
 use crate::structs::json::JsonValue;
+use crate::structs::span::Span;
 
 use crate::structs::traits::query::Queryable;
 use crate::relationship_finders::tf_block_query::tf_block_query::{JmespathExpression, PathPart};
@@ -18,17 +19,47 @@ pub enum AttributeType {
     Block(Vec<Attribute>),
     TFBlock(TerraformBlock),
     Json(JsonValue),
+    /// an unquoted HCL2 expression, eg. `role = aws_iam_role.role.arn`
+    Expression(Expression),
 }
 
-#[derive(Deserialize, Serialize, PartialEq, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Attribute {
     pub key: String,
-    pub value: AttributeType
+    pub value: AttributeType,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
+}
+
+// spans are provenance: the same attribute parsed from two places is still the same attribute
+impl PartialEq for Attribute {
+    fn eq(&self, other: &Attribute) -> bool {
+        self.key == other.key && self.value == other.value
+    }
+}
+
+impl Attribute {
+    /// visit the span of this attribute and of everything nested in its value
+    pub fn for_each_span_mut<F: FnMut(&mut Span)>(&mut self, f: &mut F) {
+        f(&mut self.span);
+        self.value.for_each_span_mut(f);
+    }
+}
+
+impl AttributeType {
+    pub fn for_each_span_mut<F: FnMut(&mut Span)>(&mut self, f: &mut F) {
+        match self {
+            Self::Array(values) => values.iter_mut().for_each(|value| value.for_each_span_mut(f)),
+            Self::Block(attributes) => attributes.iter_mut().for_each(|attr| attr.for_each_span_mut(f)),
+            Self::TFBlock(block) => block.for_each_span_mut(f),
+            _ => (),
+        }
+    }
 }
 
 impl Queryable for Attribute {
     fn query(&self, expression: JmespathExpression) -> Option<AttributeType> {
-        println!("unhandled Attribute");
+        eprintln!("unhandled Attribute");
         None
     }
 }
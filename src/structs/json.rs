@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use crate::structs::traits::query::Queryable;
 
 use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::span::Span;
 use crate::relationship_finders::tf_block_query::tf_block_query::{JmespathExpression, PathPart};
 use PathPart::{ List, Scalar};
 
@@ -27,7 +28,7 @@ impl JsonValue {
             ),
             Self::Object(value) => AttributeType::Block(
                 value.into_iter().map(|(key, val)| 
-                    Attribute{ key: key.to_string(), value: Self::convert_to_attribute_type(val) } 
+                    Attribute{ key: key.to_string(), value: Self::convert_to_attribute_type(val), span: Span::default() } 
                 ).collect()
             ),
         }
@@ -74,7 +75,7 @@ impl Queryable for JsonValue {
             
             let attributes = queried_json_results.into_iter().map(|(key, json_val)| {
                 let clone = json_val.clone();
-                Attribute { key: key.to_string(), value: Self::convert_to_attribute_type(clone) }
+                Attribute { key: key.to_string(), value: Self::convert_to_attribute_type(clone), span: Span::default() }
             }).collect();
 
             let attribute_array = AttributeType::Block(attributes);
@@ -99,7 +100,7 @@ impl Queryable for JsonValue {
                     }
                 },
                 Scalar(_) => {
-                    println!("we're looking at an unhandled Scalar");
+                    eprintln!("we're looking at an unhandled Scalar");
                     None
                 },
             }
@@ -4,3 +4,5 @@ pub mod template_string;
 pub mod json;
 pub mod traits;
 pub mod policies;
+pub mod parse_error;
+pub mod span;pub mod unresolved_reference;
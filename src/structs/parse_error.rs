@@ -0,0 +1,42 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::structs::span::LineIndex;
+
+/// A located diagnostic for a piece of template that could not be parsed.
+/// Line and column are 1-based, the snippet is the offending source line.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+    pub expected: String,
+}
+
+impl ParseError {
+    /// build a ParseError for the byte `offset` within `input`
+    pub fn at(input: &str, offset: usize, expected: &str) -> ParseError {
+        let index = LineIndex::new(input);
+        let position = index.position(offset);
+        let snippet = index.line_text(position.line).to_string();
+
+        ParseError { file: None, line: position.line, column: position.column, snippet, expected: expected.to_string() }
+    }
+
+    pub fn with_file(mut self, file: &str) -> ParseError {
+        self.file = Some(file.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = match &self.file {
+            Some(file) => file.as_str(),
+            None => "<input>",
+        };
+        write!(f, "{}:{}:{}: expected {}\n    {}", file, self.line, self.column, self.expected, self.snippet)
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -1,43 +1,425 @@
+use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::clone::Clone;
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+/// how much a policy's failure matters, `check --severity` fails the run on those at or above a threshold
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Hash, Clone, Copy, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(severity: &str) -> Result<Severity, String> {
+        match severity {
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!("unknown severity '{}', expected one of low, medium, high or critical", severity)),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        f.pad(name)
+    }
+}
+
+/// how a filter compares the value its key finds with its own value. An op the evaluator doesn't know
+/// fails the policies as they're loaded.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Hash, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Op {
+    #[serde(alias = "equals")]
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// the value found is one of a list of values
+    In,
+    NotIn,
+    /// a string holds the value, an array holds an element equal to it or an object has it as a key
+    Contains,
+    StartsWith,
+    EndsWith,
+    Regex,
+    /// a shell style glob, see `glob::glob_match_text`
+    Glob,
+    Exists,
+    Absent,
+    /// an empty string, array or object, or null
+    Empty,
+    /// the length of a string, array or object compared with a number
+    LenEq,
+    LenNe,
+    LenGt,
+    LenGe,
+    LenLt,
+    LenLe,
+    /// the elements of the list a key ending in `[]` finds, each tested against the filters in the value,
+    /// counted by the filter's quantifier
+    Filter,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Op::Eq => "eq",
+            Op::Ne => "ne",
+            Op::Gt => "gt",
+            Op::Ge => "ge",
+            Op::Lt => "lt",
+            Op::Le => "le",
+            Op::In => "in",
+            Op::NotIn => "not-in",
+            Op::Contains => "contains",
+            Op::StartsWith => "starts-with",
+            Op::EndsWith => "ends-with",
+            Op::Regex => "regex",
+            Op::Glob => "glob",
+            Op::Exists => "exists",
+            Op::Absent => "absent",
+            Op::Empty => "empty",
+            Op::LenEq => "len-eq",
+            Op::LenNe => "len-ne",
+            Op::LenGt => "len-gt",
+            Op::LenGe => "len-ge",
+            Op::LenLt => "len-lt",
+            Op::LenLe => "len-le",
+            Op::Filter => "filter",
+        };
+        f.pad(name)
+    }
+}
+
+/// how many of the elements an `op: filter` looks at must pass its filters
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantifier {
+    Any,
+    All,
+    None,
+    /// the number which pass compared with a number, eg. `{op: ge, value: 2}`
+    Count(Count),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Count {
+    pub op: Op,
+    pub value: Value,
+}
+
+impl fmt::Display for Quantifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quantifier::Any => write!(f, "any"),
+            Quantifier::All => write!(f, "all"),
+            Quantifier::None => write!(f, "none"),
+            Quantifier::Count(count) => write!(f, "count {} {}", count.op, count.value),
+        }
+    }
+}
+
+/// which edges a filter on the graph follows from the resource, see `Filter::direction`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// edges from the resource, eg. from a queue to the function it triggers
+    Outgoing,
+    /// edges to the resource, eg. from a role granted access to a table
+    Incoming,
+    Either,
+}
+
+/// the deepest a filter on the graph may follow paths
+pub const MAX_DEPTH: usize = 8;
+
+/// what a filter compiles from its value the first time it's needed, which validating the filter does, so it's
+/// done once rather than for every resource. It's made from the value, so has no bearing on whether filters are equal.
+#[derive(Debug, Clone)]
+pub struct Compiled<T>(Box<OnceLock<T>>);
+
+impl<T> Default for Compiled<T> {
+    fn default() -> Compiled<T> {
+        Compiled(Box::default())
+    }
+}
+
+impl<T> PartialEq for Compiled<T> {
+    fn eq(&self, _: &Compiled<T>) -> bool {
+        true
+    }
+}
+
+impl<T> Compiled<T> {
+    /// what was compiled, if it has been
+    pub fn get(&self) -> Option<&T> {
+        self.0.get()
+    }
+
+    fn get_or_compile<F: FnOnce() -> Result<T, String>>(&self, compile: F) -> Result<&T, String> {
+        if let Some(compiled) = self.get() {
+            return Ok(compiled);
+        }
+        let compiled = compile()?;
+        Ok(self.0.get_or_init(|| compiled))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Filter {
     pub key: String,
-    pub op: String,
-    pub value: String,
+    pub op: Op,
+    /// a string, number, boolean, list or object. Strings are read as the type of the value found,
+    /// so `"30"` equals 30. `exists`, `absent` and `empty` need none.
+    #[serde(default)]
+    pub value: Value,
+    /// for `op: filter`, how many elements must pass, any when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantifier: Option<Quantifier>,
+    /// for keys on the graph, the most edges a path may follow, one when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    /// the pattern of `op: regex`
+    #[serde(skip)]
+    pub pattern: Compiled<Regex>,
+    /// the filters of `op: filter`
+    #[serde(skip)]
+    pub elements: Compiled<Vec<FilterTree>>,
 }
 
 impl Filter {
-    pub fn new(key: &str, op: &str, value: &str) -> Filter {
-        Filter { key: key.to_owned(), op: op.to_owned(), value: value.to_owned() }
+    pub fn new(key: &str, op: Op, value: &str) -> Filter {
+        Filter::with_value(key, op, Value::String(value.to_owned()))
+    }
+
+    pub fn with_value(key: &str, op: Op, value: Value) -> Filter {
+        Filter { key: key.to_owned(), op, value, quantifier: None, depth: None, pattern: Compiled::default(), elements: Compiled::default() }
+    }
+
+    /// an `op: filter` over the elements of the list `key` finds
+    pub fn elements(key: &str, quantifier: Quantifier, filters: Vec<FilterTree>) -> Filter {
+        let value = serde_json::to_value(filters).unwrap_or_default();
+        Filter { quantifier: Some(quantifier), ..Filter::with_value(key, Op::Filter, value) }
+    }
+
+    /// which edges the filter's key follows when it's one of the keys on the graph rather than the resource's
+    /// attributes: `outgoing[]`, `incoming[]` or `connected[]`, for either
+    pub fn direction(&self) -> Option<Direction> {
+        match self.key.as_str() {
+            "outgoing[]" => Some(Direction::Outgoing),
+            "incoming[]" => Some(Direction::Incoming),
+            "connected[]" => Some(Direction::Either),
+            _ => None,
+        }
+    }
+
+    /// the filters an `op: filter` tests each element against
+    pub fn sub_filters(&self) -> Result<&[FilterTree], String> {
+        let trees = self.elements.get_or_compile(|| match &self.value {
+            Value::Array(_) => serde_json::from_value(self.value.clone()).map_err(|error| error.to_string()),
+            _ => Err(format!("`{}` needs a list of filters", self.op)),
+        })?;
+        Ok(trees)
+    }
+
+    /// the pattern an `op: regex` matches strings against
+    pub fn regex(&self) -> Result<&Regex, String> {
+        self.pattern.get_or_compile(|| Regex::new(&self.value_text()).map_err(|error| error.to_string()))
+    }
+
+    /// the value as it's written in a policy, strings without their quotes
+    pub fn value_text(&self) -> String {
+        match &self.value {
+            Value::String(value) => value.to_string(),
+            Value::Null => String::new(),
+            value => value.to_string(),
+        }
+    }
+
+    /// whether the value suits the op, eg. `in` needs a list and `regex` a pattern which compiles, compiling it for evaluation
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.op, &self.quantifier) {
+            (Op::Filter, _) if !self.key.ends_with("[]") => return Err(format!("`{}` needs a key ending in []", self.op)),
+            (Op::Filter, Some(Quantifier::Count(count))) => match (count.op, &count.value) {
+                (Op::Eq, Value::Number(_)) | (Op::Ne, Value::Number(_)) | (Op::Gt, Value::Number(_))
+                | (Op::Ge, Value::Number(_)) | (Op::Lt, Value::Number(_)) | (Op::Le, Value::Number(_)) => (),
+                _ => return Err(String::from("a count compares with one of eq, ne, gt, ge, lt or le and a number")),
+            },
+            (Op::Filter, _) => (),
+            (_, Some(_)) => return Err(format!("only `{}` takes a quantifier", Op::Filter)),
+            (_, None) => (),
+        }
+        match (self.direction(), self.depth) {
+            (None, Some(_)) => return Err(String::from("only `outgoing[]`, `incoming[]` and `connected[]` take a depth")),
+            (Some(_), Some(depth)) if depth == 0 || depth > MAX_DEPTH => return Err(format!("a depth is from 1 to {}", MAX_DEPTH)),
+            _ => (),
+        }
+        match (self.op, &self.value) {
+            (Op::Filter, _) => {
+                for filter in self.sub_filters()?.iter().flat_map(|tree| tree.filters()) {
+                    filter.validate().map_err(|error| format!("filter on {}: {}", filter.key, error))?;
+                }
+                Ok(())
+            },
+            (Op::In, Value::Array(_)) | (Op::NotIn, Value::Array(_)) => Ok(()),
+            (Op::In, _) | (Op::NotIn, _) => Err(format!("`{}` needs a list of values", self.op)),
+            (Op::Gt, value) | (Op::Ge, value) | (Op::Lt, value) | (Op::Le, value) if !(value.is_number() || value.is_string()) => {
+                Err(format!("`{}` needs a number or a string", self.op))
+            },
+            (Op::StartsWith, value) | (Op::EndsWith, value) | (Op::Regex, value) | (Op::Glob, value) if !value.is_string() => {
+                Err(format!("`{}` needs a string", self.op))
+            },
+            (Op::Regex, Value::String(_)) => self.regex().map(|_| ()),
+            (Op::LenEq, value) | (Op::LenNe, value) | (Op::LenGt, value) | (Op::LenGe, value) | (Op::LenLt, value) | (Op::LenLe, value) => {
+                match value {
+                    Value::Number(_) => Ok(()),
+                    Value::String(length) if length.parse::<f64>().is_ok() => Ok(()),
+                    _ => Err(format!("`{}` needs a number", self.op)),
+                }
+            },
+            _ => Ok(()),
+        }
     }
 }
 
 impl fmt::Display for Filter {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{{\"key\":\"{}\",\"op\":\"{}\",\"value\":\"{}\"}}", self.key, self.op, self.value)
+        write!(f, "{{\"key\":\"{}\",\"op\":\"{}\",\"value\":{}}}", self.key, self.op, self.value)
     }
 }
 
 impl Clone for Filter {
     fn clone(&self) -> Filter {
-        Filter { key: self.key.clone(), op: self.op.clone(), value: self.value.clone() }
+        Filter {
+            key: self.key.clone(),
+            op: self.op,
+            value: self.value.clone(),
+            quantifier: self.quantifier.clone(),
+            depth: self.depth,
+            pattern: self.pattern.clone(),
+            elements: self.elements.clone(),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Hash, Clone)]
+/// a policy's filters, or a group of them. A resource passes an `and` group when it passes all of its filters,
+/// an `or` group when it passes any of them and a `not` when it fails its filter.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "FilterNode", into = "FilterNode")]
+pub enum FilterTree {
+    And(Vec<FilterTree>),
+    Or(Vec<FilterTree>),
+    Not(Box<FilterTree>),
+    Filter(Filter),
+}
+
+impl FilterTree {
+    /// every filter in the tree
+    pub fn filters(&self) -> Vec<&Filter> {
+        match self {
+            FilterTree::And(trees) | FilterTree::Or(trees) => trees.iter().flat_map(|tree| tree.filters()).collect(),
+            FilterTree::Not(tree) => tree.filters(),
+            FilterTree::Filter(filter) => vec![filter],
+        }
+    }
+}
+
+/// how a filter tree is written: one of `and`, `or` or `not`, or a filter's `key`, `op` and `value`
+#[derive(Serialize, Deserialize)]
+struct FilterNode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    and: Option<Vec<FilterTree>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    or: Option<Vec<FilterTree>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    not: Option<Box<FilterTree>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    op: Option<Op>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quantifier: Option<Quantifier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depth: Option<usize>,
+}
+
+impl TryFrom<FilterNode> for FilterTree {
+    type Error = String;
+
+    fn try_from(node: FilterNode) -> Result<FilterTree, String> {
+        match node {
+            FilterNode { and: Some(trees), or: None, not: None, key: None, op: None, .. } => Ok(FilterTree::And(trees)),
+            FilterNode { and: None, or: Some(trees), not: None, key: None, op: None, .. } => Ok(FilterTree::Or(trees)),
+            FilterNode { and: None, or: None, not: Some(tree), key: None, op: None, .. } => Ok(FilterTree::Not(tree)),
+            FilterNode { and: None, or: None, not: None, key: Some(key), op: Some(op), value, quantifier, depth } => {
+                Ok(FilterTree::Filter(Filter { quantifier, depth, ..Filter::with_value(&key, op, value) }))
+            },
+            _ => Err(String::from("a filter needs either a key and an op, or one of and, or or not")),
+        }
+    }
+}
+
+impl From<FilterTree> for FilterNode {
+    fn from(tree: FilterTree) -> FilterNode {
+        let node = FilterNode { and: None, or: None, not: None, key: None, op: None, value: Value::Null, quantifier: None, depth: None };
+        match tree {
+            FilterTree::And(trees) => FilterNode { and: Some(trees), ..node },
+            FilterTree::Or(trees) => FilterNode { or: Some(trees), ..node },
+            FilterTree::Not(tree) => FilterNode { not: Some(tree), ..node },
+            FilterTree::Filter(filter) => FilterNode { key: Some(filter.key), op: Some(filter.op), value: filter.value, quantifier: filter.quantifier, depth: filter.depth, ..node },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Policy {
     pub name: String,
     pub description: String,
     pub resource: String,
-    pub filters: Vec<Filter>,
+    /// policies which don't say are medium
+    #[serde(default)]
+    pub severity: Severity,
+    /// every one of which a resource must pass
+    pub filters: Vec<FilterTree>,
 }
 
 impl Policy {
     pub fn new(name: &str, description: &str, resource: &str, filters: Vec<Filter>) -> Policy {
-        Policy { name: name.to_owned(), description: description.to_owned(), resource: resource.to_owned(), filters }
+        let filters = filters.into_iter().map(FilterTree::Filter).collect();
+        Policy { name: name.to_owned(), description: description.to_owned(), resource: resource.to_owned(), severity: Severity::default(), filters }
+    }
+
+    pub fn with_filters(mut self, filters: Vec<FilterTree>) -> Policy {
+        self.filters = filters;
+        self
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Policy {
+        self.severity = severity;
+        self
     }
 }
 
@@ -46,6 +428,18 @@ pub struct Policies {
     pub policies: Vec<Policy>,
 }
 
+impl Policies {
+    /// check every filter's value suits its op, naming the policy and filter of the first which doesn't
+    pub fn validate(&self) -> Result<(), String> {
+        for policy in &self.policies {
+            for filter in policy.filters.iter().flat_map(|tree| tree.filters()) {
+                filter.validate().map_err(|error| format!("policy {}, filter on {}: {}", policy.name, filter.key, error))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct PolicyResult {
     policy: Policy,
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+/// A point in a template. `line` and `column` are 1-based, `offset` is in bytes.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The range of source a block or attribute was parsed from.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Span {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// While parsing, the parsers only see the input left to them, so a span is
+    /// first recorded as the amount of input remaining at its start and end.
+    /// `resolve` turns these into real positions once the whole input is known.
+    pub fn remaining(start: usize, end: usize) -> Span {
+        Span {
+            file: None,
+            start: Position { offset: start, line: 0, column: 0 },
+            end: Position { offset: end, line: 0, column: 0 },
+        }
+    }
+
+    pub fn resolve(&mut self, index: &LineIndex) {
+        self.start = index.position(index.input_len() - self.start.offset);
+        self.end = index.position(index.input_len() - self.end.offset);
+    }
+
+    /// spans of blocks and attributes which weren't read from a template are empty
+    pub fn is_empty(&self) -> bool {
+        self == &Span::default()
+    }
+}
+
+/// Byte offsets of the start of every line, for turning offsets into lines and columns.
+pub struct LineIndex<'a> {
+    input: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(input: &'a str) -> LineIndex<'a> {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(position, _)| position + 1))
+            .collect();
+
+        LineIndex { input, line_starts }
+    }
+
+    pub fn input_len(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.input.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        let column = self.input[self.line_starts[line]..offset].chars().count() + 1;
+
+        Position { offset, line: line + 1, column }
+    }
+
+    /// the text of the 1-based `line`, without its line ending
+    pub fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).map(|next| next - 1).unwrap_or(self.input.len());
+
+        self.input[start..end].trim_end()
+    }
+}
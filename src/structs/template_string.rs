@@ -1,13 +1,359 @@
+use std::fmt;
 use serde::{Deserialize, Serialize};
 
+use crate::structs::terraform_block::module_prefix;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct BuiltInFunction {
     pub name: String,
-    pub param: TemplateString,
+    pub args: Vec<Expression>,
+    /// the final argument was written with `...` to expand it into separate arguments
+    #[serde(default)]
+    pub expand_final: bool,
+}
+
+/// One step of a traversal: `.name`, `[index]`, `.*` or `[*]`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum TraversalStep {
+    Attribute(String),
+    Index(Expression),
+    AttributeSplat,
+    FullSplat,
+}
+
+/// A name followed by the steps taken from it, eg. `aws_sqs_queue.queue.arn` or `var.subnets[0]`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Traversal {
+    pub root: String,
+    pub steps: Vec<TraversalStep>,
+    /// the module whose resources the traversal refers to, set when a module is expanded
+    /// so that `aws_sqs_queue.queue.arn` written inside `module.queues` stays pointing there
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub module_path: Vec<String>,
+}
+
+impl Traversal {
+    pub fn new(root: &str, steps: Vec<TraversalStep>) -> Traversal {
+        Traversal { root: root.to_string(), steps, module_path: vec![] }
+    }
+
+    /// true when every step is a plain `.name`, as in "${aws_sqs_queue.queue.arn}"
+    pub fn is_dotted_path(&self) -> bool {
+        self.steps.iter().all(|step| matches!(step, TraversalStep::Attribute(_)))
+    }
+
+    /// classify the traversal by what it refers to. Returns None for the steps
+    /// terraform provides itself, eg. `count.index`, `each.key` or `path.module`,
+    /// and for traversals too short to name anything.
+    pub fn reference(&self) -> Option<Reference> {
+        let names: Vec<&str> = self.steps.iter()
+            .map_while(|step| match step {
+                TraversalStep::Attribute(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let rest = |taken: usize| self.steps[taken..].to_vec();
+        let module_path = self.module_path.clone();
+
+        match (self.root.as_str(), names.as_slice()) {
+            ("count" | "each" | "path" | "self" | "terraform", _) => None,
+            ("var", [name, ..]) => Some(Reference::Variable { name: name.to_string(), steps: rest(1) }),
+            ("local", [name, ..]) => Some(Reference::Local { name: name.to_string(), steps: rest(1) }),
+            ("module", [name, output, ..]) => Some(Reference::Module { name: name.to_string(), output: Some(output.to_string()), steps: rest(2) }),
+            ("module", [name]) => Some(Reference::Module { name: name.to_string(), output: None, steps: rest(1) }),
+            ("data", [data_type, name, ..]) => Some(Reference::Data { data_type: data_type.to_string(), name: name.to_string(), steps: rest(2), module_path }),
+            ("var" | "local" | "module" | "data", _) => None,
+            (resource_type, [name, ..]) => Some(Reference::Resource { resource_type: resource_type.to_string(), name: name.to_string(), steps: rest(1), module_path }),
+            _ => None,
+        }
+    }
+}
+
+/// What a traversal points at. `steps` are whatever follows the referenced object,
+/// eg. the `.arn` of `aws_iam_role.foo.arn`. Resources and data sources within a module
+/// carry the module's path.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Reference {
+    Resource { resource_type: String, name: String, steps: Vec<TraversalStep>, module_path: Vec<String> },
+    Data { data_type: String, name: String, steps: Vec<TraversalStep>, module_path: Vec<String> },
+    Variable { name: String, steps: Vec<TraversalStep> },
+    Local { name: String, steps: Vec<TraversalStep> },
+    Module { name: String, output: Option<String>, steps: Vec<TraversalStep> },
+}
+
+impl Reference {
+    /// the address of the referenced object, eg. `aws_iam_role.foo`, `module.m.aws_iam_role.foo`, `var.x` or `module.m.out`
+    pub fn address(&self) -> String {
+        match self {
+            Self::Resource { resource_type, name, module_path, .. } => format!("{}{}.{}", module_prefix(module_path), resource_type, name),
+            Self::Data { data_type, name, module_path, .. } => format!("{}data.{}.{}", module_prefix(module_path), data_type, name),
+            Self::Variable { name, .. } => format!("var.{}", name),
+            Self::Local { name, .. } => format!("local.{}", name),
+            Self::Module { name, output: Some(output), .. } => format!("module.{}.{}", name, output),
+            Self::Module { name, output: None, .. } => format!("module.{}", name),
+        }
+    }
+
+    pub fn steps(&self) -> &Vec<TraversalStep> {
+        match self {
+            Self::Resource { steps, .. } => steps,
+            Self::Data { steps, .. } => steps,
+            Self::Variable { steps, .. } => steps,
+            Self::Local { steps, .. } => steps,
+            Self::Module { steps, .. } => steps,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum UnaryOperator {
+    Not,
+    Negate,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+/// An HCL2 expression, as found inside "${...}"
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Expression {
+    Str(String),
+    Num(f64),
+    Boolean(bool),
+    Null,
+    Variable(Traversal),
+    FunctionCall(BuiltInFunction),
+    /// traversal steps applied to something other than a name, eg. `element(list, 0).id`
+    Access { target: Box<Expression>, steps: Vec<TraversalStep> },
+    Tuple(Vec<Expression>),
+    Object(Vec<(Expression, Expression)>),
+    Unary { operator: UnaryOperator, operand: Box<Expression> },
+    Binary { operator: BinaryOperator, left: Box<Expression>, right: Box<Expression> },
+    Conditional { condition: Box<Expression>, true_result: Box<Expression>, false_result: Box<Expression> },
+    Parenthesized(Box<Expression>),
+    Template(Vec<TemplatePart>),
+}
+
+/// A piece of a string template. Literal text holds the decoded text, eg. `"` rather than `\"`, and is escaped again
+/// when written back as HCL; template escapes such as `$${` are kept as written.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum TemplatePart {
+    Literal(String),
+    Interpolation(Expression),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum TemplateString {
+    /// "${aws_sqs_queue.queue.arn}", a single dotted path
     Variable(String),
+    /// "${md5(file("config.json"))}", a single function call
     BuiltInFunction(Box<BuiltInFunction>),
-}
\ No newline at end of file
+    /// "${var.count > 1 ? 2 : 1}", any other single interpolation
+    Expression(Box<Expression>),
+    /// "service/${aws_ecs_cluster.cluster.name}/${aws_ecs_service.service.name}", text mixed with interpolations
+    Template(Vec<TemplatePart>),
+}
+
+impl Expression {
+    /// every reference made anywhere within the expression, in the order they are written
+    pub fn references(&self) -> Vec<Reference> {
+        let mut references = vec![];
+        self.collect_references(&mut references);
+        references
+    }
+
+    fn collect_references(&self, references: &mut Vec<Reference>) {
+        let collect_steps = |steps: &Vec<TraversalStep>, references: &mut Vec<Reference>| {
+            for step in steps {
+                if let TraversalStep::Index(index) = step {
+                    index.collect_references(references);
+                }
+            }
+        };
+
+        match self {
+            Self::Variable(traversal) => {
+                references.extend(traversal.reference());
+                collect_steps(&traversal.steps, references);
+            },
+            Self::FunctionCall(function) => function.args.iter().for_each(|arg| arg.collect_references(references)),
+            Self::Access { target, steps } => {
+                target.collect_references(references);
+                collect_steps(steps, references);
+            },
+            Self::Tuple(items) => items.iter().for_each(|item| item.collect_references(references)),
+            Self::Object(items) => items.iter().for_each(|(key, value)| {
+                key.collect_references(references);
+                value.collect_references(references);
+            }),
+            Self::Unary { operand, .. } => operand.collect_references(references),
+            Self::Binary { left, right, .. } => {
+                left.collect_references(references);
+                right.collect_references(references);
+            },
+            Self::Conditional { condition, true_result, false_result } => {
+                condition.collect_references(references);
+                true_result.collect_references(references);
+                false_result.collect_references(references);
+            },
+            Self::Parenthesized(expression) => expression.collect_references(references),
+            Self::Template(parts) => parts.iter().for_each(|part| {
+                if let TemplatePart::Interpolation(expression) = part {
+                    expression.collect_references(references);
+                }
+            }),
+            Self::Str(_) | Self::Num(_) | Self::Boolean(_) | Self::Null => (),
+        }
+    }
+}
+
+impl TemplateString {
+    /// the single-interpolation forms are classified the same way the parser classifies them
+    pub fn from_expression(expression: Expression) -> TemplateString {
+        match expression {
+            Expression::Variable(traversal) if traversal.is_dotted_path() && traversal.module_path.is_empty() => Self::Variable(traversal.to_string()),
+            Expression::FunctionCall(function) => Self::BuiltInFunction(Box::new(function)),
+            Expression::Template(parts) => Self::Template(parts),
+            expression => Self::Expression(Box::new(expression)),
+        }
+    }
+
+    pub fn to_expression(&self) -> Expression {
+        match self {
+            Self::Variable(path) => {
+                let mut names = path.split('.').map(String::from);
+                let root = names.next().unwrap_or_default();
+                Expression::Variable(Traversal::new(&root, names.map(TraversalStep::Attribute).collect()))
+            },
+            Self::BuiltInFunction(function) => Expression::FunctionCall(*function.clone()),
+            Self::Expression(expression) => *expression.clone(),
+            Self::Template(parts) => Expression::Template(parts.clone()),
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Or => "||",
+            Self::And => "&&",
+            Self::Equal => "==",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Modulo => "%",
+        }
+    }
+}
+
+fn write_steps(f: &mut fmt::Formatter, steps: &[TraversalStep]) -> fmt::Result {
+    for step in steps {
+        match step {
+            TraversalStep::Attribute(name) => write!(f, ".{}", name)?,
+            TraversalStep::Index(index) => write!(f, "[{}]", index)?,
+            TraversalStep::AttributeSplat => write!(f, ".*")?,
+            TraversalStep::FullSplat => write!(f, "[*]")?,
+        }
+    }
+    Ok(())
+}
+
+/// a string's text as it's written between quotes in HCL
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+/// the parts of a template, with its literal text escaped when it's rendered between quotes
+fn write_parts(f: &mut fmt::Formatter, parts: &[TemplatePart], quoted: bool) -> fmt::Result {
+    for part in parts {
+        match part {
+            TemplatePart::Literal(text) if quoted => write!(f, "{}", escape(text))?,
+            TemplatePart::Literal(text) => write!(f, "{}", text)?,
+            TemplatePart::Interpolation(expression) => write!(f, "${{{}}}", expression)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Traversal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", module_prefix(&self.module_path), self.root)?;
+        write_steps(f, &self.steps)
+    }
+}
+
+impl fmt::Display for BuiltInFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|arg| format!("{}", arg)).collect();
+        let expansion = if self.expand_final { "..." } else { "" };
+        write!(f, "{}({}{})", self.name, args.join(", "), expansion)
+    }
+}
+
+/// renders the expression back into HCL
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Str(value) => write!(f, "\"{}\"", escape(value)),
+            Self::Num(value) => write!(f, "{}", value),
+            Self::Boolean(value) => write!(f, "{}", value),
+            Self::Null => write!(f, "null"),
+            Self::Variable(traversal) => write!(f, "{}", traversal),
+            Self::FunctionCall(function) => write!(f, "{}", function),
+            Self::Access { target, steps } => {
+                write!(f, "{}", target)?;
+                write_steps(f, steps)
+            },
+            Self::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|item| format!("{}", item)).collect();
+                write!(f, "[{}]", items.join(", "))
+            },
+            Self::Object(items) => {
+                let items: Vec<String> = items.iter().map(|(key, value)| format!("{} = {}", key, value)).collect();
+                write!(f, "{{{}}}", items.join(", "))
+            },
+            Self::Unary { operator: UnaryOperator::Not, operand } => write!(f, "!{}", operand),
+            Self::Unary { operator: UnaryOperator::Negate, operand } => write!(f, "-{}", operand),
+            Self::Binary { operator, left, right } => write!(f, "{} {} {}", left, operator.symbol(), right),
+            Self::Conditional { condition, true_result, false_result } => write!(f, "{} ? {} : {}", condition, true_result, false_result),
+            Self::Parenthesized(expression) => write!(f, "({})", expression),
+            Self::Template(parts) => {
+                write!(f, "\"")?;
+                write_parts(f, parts, true)?;
+                write!(f, "\"")
+            },
+        }
+    }
+}
+
+/// renders the contents of the template string, without its surrounding quotes
+impl fmt::Display for TemplateString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Variable(path) => write!(f, "${{{}}}", path),
+            Self::BuiltInFunction(function) => write!(f, "${{{}}}", function),
+            Self::Expression(expression) => write!(f, "${{{}}}", expression),
+            Self::Template(parts) => write_parts(f, parts, false),
+        }
+    }
+}
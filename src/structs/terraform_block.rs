@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::span::Span;
 use crate::structs::traits::query::Queryable;
 use crate::relationship_finders::tf_block_query::tf_block_query::{JmespathExpression, PathPart};
 use PathPart::{ List, Scalar};
@@ -12,25 +13,68 @@ pub enum TerraformBlock {
     WithTwoIdentifiers(TerraformBlockWithTwoIdentifiers)
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TerraformBlockWithNoIdentifiers {
     pub block_type: String,
-    pub attributes: Vec<Attribute>
+    pub attributes: Vec<Attribute>,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TerraformBlockWithOneIdentifier {
     pub block_type: String,
     pub first_identifier: String,
-    pub attributes: Vec<Attribute>
+    pub attributes: Vec<Attribute>,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TerraformBlockWithTwoIdentifiers {
     pub block_type: String,
     pub first_identifier: String,
     pub second_identifier: String,
-    pub attributes: Vec<Attribute>
+    pub attributes: Vec<Attribute>,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
+    /// the names of the modules the block was expanded from, outermost first. Empty in the root module.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub module_path: Vec<String>,
+    /// what a plan will do to the resource, eg. `["create"]` or `["delete", "create"]`. Empty unless read from a plan.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub planned_actions: Vec<String>,
+    /// the addresses of the resources terraform recorded this one as referring to, in a plan's configuration
+    /// or a state's `depends_on`. Empty for blocks read from templates, whose references are found in their attributes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+}
+
+// spans are provenance and take no part in equality, see Attribute
+impl PartialEq for TerraformBlockWithNoIdentifiers {
+    fn eq(&self, other: &Self) -> bool {
+        self.block_type == other.block_type && self.attributes == other.attributes
+    }
+}
+
+impl PartialEq for TerraformBlockWithOneIdentifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.block_type == other.block_type
+            && self.first_identifier == other.first_identifier
+            && self.attributes == other.attributes
+    }
+}
+
+impl PartialEq for TerraformBlockWithTwoIdentifiers {
+    fn eq(&self, other: &Self) -> bool {
+        self.block_type == other.block_type
+            && self.first_identifier == other.first_identifier
+            && self.second_identifier == other.second_identifier
+            && self.attributes == other.attributes
+            && self.module_path == other.module_path
+            && self.planned_actions == other.planned_actions
+            && self.references == other.references
+    }
 }
 
 impl TerraformBlock {
@@ -39,9 +83,72 @@ impl TerraformBlock {
         match self {
             Self::NoIdentifiers(resource) => resource.block_type.to_string(),
             Self::WithOneIdentifier(resource) => resource.block_type.to_string() + "_" + &resource.first_identifier,
-            Self::WithTwoIdentifiers(resource) => resource.first_identifier.to_string() + "_" + &resource.second_identifier,
+            Self::WithTwoIdentifiers(resource) => module_prefix(&resource.module_path) + &resource.first_identifier + "_" + &resource.second_identifier,
+        }
+    }
+
+    /// the terraform address of the block, eg. `aws_sqs_queue.queue`, `data.aws_iam_policy_document.doc`,
+    /// `variable.region` or, for a resource expanded from a module, `module.queues.aws_sqs_queue.queue`
+    pub fn address(&self) -> String {
+        match self {
+            Self::NoIdentifiers(resource) => resource.block_type.to_string(),
+            Self::WithOneIdentifier(resource) => format!("{}.{}", resource.block_type, resource.first_identifier),
+            Self::WithTwoIdentifiers(resource) if resource.block_type == "resource" => {
+                format!("{}{}.{}", module_prefix(&resource.module_path), resource.first_identifier, resource.second_identifier)
+            },
+            Self::WithTwoIdentifiers(resource) => {
+                format!("{}{}.{}.{}", module_prefix(&resource.module_path), resource.block_type, resource.first_identifier, resource.second_identifier)
+            },
         }
     }
+
+    pub fn block_type(&self) -> &str {
+        match self {
+            Self::NoIdentifiers(resource) => &resource.block_type,
+            Self::WithOneIdentifier(resource) => &resource.block_type,
+            Self::WithTwoIdentifiers(resource) => &resource.block_type,
+        }
+    }
+
+    pub fn attributes(&self) -> &Vec<Attribute> {
+        match self {
+            Self::NoIdentifiers(resource) => &resource.attributes,
+            Self::WithOneIdentifier(resource) => &resource.attributes,
+            Self::WithTwoIdentifiers(resource) => &resource.attributes,
+        }
+    }
+
+    pub fn attributes_mut(&mut self) -> &mut Vec<Attribute> {
+        match self {
+            Self::NoIdentifiers(resource) => &mut resource.attributes,
+            Self::WithOneIdentifier(resource) => &mut resource.attributes,
+            Self::WithTwoIdentifiers(resource) => &mut resource.attributes,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::NoIdentifiers(resource) => &resource.span,
+            Self::WithOneIdentifier(resource) => &resource.span,
+            Self::WithTwoIdentifiers(resource) => &resource.span,
+        }
+    }
+
+    /// visit the span of this block and of every attribute and block nested inside it
+    pub fn for_each_span_mut<F: FnMut(&mut Span)>(&mut self, f: &mut F) {
+        let (span, attributes) = match self {
+            Self::NoIdentifiers(resource) => (&mut resource.span, &mut resource.attributes),
+            Self::WithOneIdentifier(resource) => (&mut resource.span, &mut resource.attributes),
+            Self::WithTwoIdentifiers(resource) => (&mut resource.span, &mut resource.attributes),
+        };
+        f(span);
+        attributes.iter_mut().for_each(|attr| attr.for_each_span_mut(f));
+    }
+}
+
+/// `module.a.module.b.` for the module path `[a, b]`
+pub fn module_prefix(module_path: &[String]) -> String {
+    module_path.iter().map(|name| format!("module.{}.", name)).collect()
 }
 
 impl Queryable for TerraformBlock {
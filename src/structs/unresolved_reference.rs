@@ -0,0 +1,28 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::structs::span::Span;
+
+/// A `var.*`, `local.*` or `module.*` reference the resolver could not substitute a value for.
+/// `attribute` is the dotted path to the attribute within `block`, its span is the attribute's.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct UnresolvedReference {
+    pub reference: String,
+    pub block: String,
+    pub attribute: String,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Span::is_empty")]
+    pub span: Span,
+}
+
+impl fmt::Display for UnresolvedReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = match &self.span.file {
+            Some(file) => file.as_str(),
+            None => "<input>",
+        };
+        write!(f, "{}:{}:{}: cannot resolve {} in {}.{}: {}",
+            file, self.span.start.line, self.span.start.column, self.reference, self.block, self.attribute, self.reason)
+    }
+}
+
+impl std::error::Error for UnresolvedReference {}
@@ -3,9 +3,9 @@ extern crate nom;
 use nom::{
   branch::alt,
   bytes::complete::{escaped, is_not, tag, take, take_while, take_until},
-  character::complete::{alphanumeric1, char, one_of, multispace0, newline, not_line_ending, line_ending, space0, space1},
-  combinator::{map, opt, peek, value},
-  error::{ParseError},
+  character::complete::{alphanumeric1, char, multispace0, newline, not_line_ending, line_ending, space0, space1},
+  combinator::{map, opt, peek, value, verify},
+  error::{ErrorKind, ParseError as NomParseError, make_error},
   multi::{many0, many1, separated_list0, fold_many0},
   number::complete::double,
   sequence::{delimited, preceded, pair, separated_pair, terminated},
@@ -23,9 +23,12 @@ use crate::structs::attributes::{ Attribute, AttributeType };
 use AttributeType::{
     Array, Block, Boolean, Json, Num, Str, TFBlock, TemplatedString,
 };
-use crate::structs::template_string::{ TemplateString, BuiltInFunction };
+use crate::structs::template_string::{ Expression, TemplateString };
 use crate::structs::json::JsonValue;
+use crate::structs::parse_error::ParseError;
+use crate::structs::span::{ LineIndex, Span };
 use crate::json::{parse_json};
+use crate::expression::{expression, template_string, unescape};
 
 use std::str;
 
@@ -37,7 +40,7 @@ use std::str;
 // }
 
 #[allow(dead_code)]
-fn boolean<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, bool, E> {
+fn boolean<'a, E: NomParseError<&'a str>>(input: &'a str) -> IResult<&'a str, bool, E> {
   let parse_true = value(true, tag("true"));
   let parse_false = value(false, tag("false"));
 
@@ -45,7 +48,7 @@ fn boolean<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, bool,
 }
 
 fn parse_single_line_str(i: &str) -> IResult<&str, &str> {
-    preceded(space0, escaped(is_not("\""), '\\', one_of(r#"\"#)))(i)
+    preceded(space0, escaped(is_not("\"\\"), '\\', take(1usize)))(i)
     // take_while(is_not_newline)(i)
 }
 
@@ -69,7 +72,7 @@ fn parse_array(i: &str) -> IResult<&str, Vec<AttributeType>> {
 }
 
 fn valid_identifier_char(c: char) -> bool {
-  c.is_alphanumeric() || c == '_' || c == '/'
+  c.is_alphanumeric() || c == '_' || c == '-' || c == '/'
 }
 
 fn valid_identifier(i: &str) -> IResult<&str, &str> {
@@ -95,7 +98,8 @@ fn key_value(i: &str) -> IResult<&str, (&str, AttributeType)> {
                 ),
                 block_value
             ),
-            separated_pair(preceded(space0, valid_identifier), space0, block_value),
+            // a nested block, `tags {`, needs no `=` but an expression does
+            separated_pair(preceded(space0, valid_identifier), space0, verify(block_value, |value| !matches!(value, AttributeType::Expression(_)))),
             separated_pair(preceded(space0, valid_identifier), preceded(space0, char('=')), block_value),
         ))
     )(i)?;
@@ -135,13 +139,14 @@ fn block_value(i: &str) -> IResult<&str, AttributeType> {
   preceded(
     space0,
     alt((
-      map(templated_string, AttributeType::TemplatedString),
       map(serialised_json, AttributeType::Json),
+      map(templated_string, AttributeType::TemplatedString),
+      map(bare_expression, AttributeType::Expression),
       map(boolean, AttributeType::Boolean),
       map(double, AttributeType::Num),
       map(basic_block, AttributeType::Block),
       map(tf_block, AttributeType::TFBlock),
-      map(escaped_string, |s| AttributeType::Str(String::from(s))),
+      map(escaped_string, |s| AttributeType::Str(unescape(s))),
       map(parse_array, AttributeType::Array),
       map(json_value, AttributeType::Json),
     )),
@@ -156,19 +161,27 @@ fn escaped_string(i: &str) -> IResult<&str, &str> {
     Ok((rest, result))
 }
 
-fn separated_attributes(i: &str) -> IResult<&str, Vec<(&str, AttributeType)>> {
-    separated_list0(preceded(space0, newline), key_value)(i)
+fn attribute(i: &str) -> IResult<&str, Attribute> {
+    let (start, _) = multispace0(i)?;
+    let (rest, (key, value)) = key_value(start)?;
+
+    Ok((rest, Attribute { key: String::from(key), value, span: Span::remaining(start.len(), rest.len()) }))
 }
 
-fn build_tf_block(identifiers: Vec<&str>, attributes: Vec<Attribute>) -> TerraformBlock {
-    match identifiers.len() {
+fn separated_attributes(i: &str) -> IResult<&str, Vec<Attribute>> {
+    separated_list0(preceded(space0, newline), attribute)(i)
+}
+
+fn build_tf_block(identifiers: Vec<&str>, attributes: Vec<Attribute>, span: Span) -> Option<TerraformBlock> {
+    let block = match identifiers.len() {
         1 => {
-            let block_types = ["resource", "provider", "data", "terraform", "variable"];
+            let block_types = ["resource", "provider", "data", "terraform", "variable", "locals"];
             if block_types.contains(&identifiers[0]) {
                 TerraformBlock::NoIdentifiers(
                     TerraformBlockWithNoIdentifiers {
                         block_type: identifiers[0].to_string(),
-                        attributes
+                        attributes,
+                        span
                     }
                 )
             } else {
@@ -176,7 +189,8 @@ fn build_tf_block(identifiers: Vec<&str>, attributes: Vec<Attribute>) -> Terrafo
                     TerraformBlockWithOneIdentifier {
                         block_type: String::from("inner"),
                         first_identifier: identifiers[0].to_string(),
-                        attributes
+                        attributes,
+                        span
                     }
                 )
             }
@@ -186,7 +200,8 @@ fn build_tf_block(identifiers: Vec<&str>, attributes: Vec<Attribute>) -> Terrafo
                 TerraformBlockWithOneIdentifier {
                     block_type: identifiers[0].to_string(),
                     first_identifier: identifiers[1].to_string(),
-                    attributes
+                    attributes,
+                    span
                 }
             )
         },
@@ -196,12 +211,18 @@ fn build_tf_block(identifiers: Vec<&str>, attributes: Vec<Attribute>) -> Terrafo
                     block_type: identifiers[0].to_string(),
                     first_identifier: identifiers[1].to_string(),
                     second_identifier: identifiers[2].to_string(),
-                    attributes
+                    attributes,
+                    span,
+                    module_path: vec![],
+                    planned_actions: vec![],
+                    references: vec![],
                 }
             )
         },
-        _ => panic!("encountered terraform block with too many identifiers: {:?}", identifiers)
-    }
+        _ => return None,
+    };
+
+    Some(block)
 }
 
 fn inline_block(i: &str) -> IResult<&str, (&str, AttributeType)> {
@@ -214,12 +235,7 @@ fn basic_block(i: &str) -> IResult<&str, Vec<Attribute>> {
         preceded(
             multispace0,
             terminated(
-                map(
-                    separated_attributes, 
-                        |tuple_vec| { // tempted to see if we can do without this mapping
-                            tuple_vec.into_iter().map(|(k, v)| Attribute{key: String::from(k), value: v}).collect()
-                        }
-                    ),
+                separated_attributes,
                 preceded(multispace0, char('}')),
             )
         )
@@ -239,15 +255,18 @@ fn parse_identifiers(i: &str) -> IResult<&str, Vec<&str>> {
 fn tf_block(i: &str) -> IResult<&str, TerraformBlock> {
     // println!("potential tf block: {}", i);
     let (rest, _) = comments_and_blank_lines(i)?;
-    let (rest, identifiers) = parse_identifiers(rest)?;
+    let (start, _) = multispace0(rest)?;
+    let (rest, identifiers) = parse_identifiers(start)?;
     // println!("identifiers: {:?}", identifiers);
 
     let (rest, attributes) = basic_block(rest)?;
 
     // println!("attributes: {:?}", attributes);
 
-    let block = build_tf_block(identifiers, attributes);
-    Ok((rest, block))
+    match build_tf_block(identifiers, attributes, Span::remaining(start.len(), rest.len())) {
+        Some(block) => Ok((rest, block)),
+        None => Err(nom::Err::Error(make_error(i, ErrorKind::TooLarge))),
+    }
 }
 
 fn string_nl(i: &str) -> IResult<&str, &str> {
@@ -283,74 +302,195 @@ fn comments_and_blank_lines(i: &str) -> IResult<&str, &str> {
     }
 }
 
-#[allow(dead_code)]
-pub fn root(i: &str) -> IResult<&str, Vec<TerraformBlock>> {
-    many0(
-        tf_block
-    )(i)
+const TOP_LEVEL_BLOCK_TYPES: [&str; 8] = ["resource", "data", "provider", "variable", "output", "locals", "module", "terraform"];
+
+const EXPECTED_VALUE: &str = "a value (string, number, boolean, list, block, heredoc or \"${...}\" template)";
+
+/// skip whitespace and comment lines, tolerating the end of the input
+fn trivia(i: &str) -> &str {
+    let mut rest = i.trim_start();
+    while rest.starts_with('#') || rest.starts_with("//") {
+        rest = match rest.find('\n') {
+            Some(position) => rest[position..].trim_start(),
+            None => "",
+        };
+    }
+    rest
 }
 
-fn built_in_function(i: &str) -> IResult<&str, TemplateString> {
-    // println!("built_in_function input: {}", i);
+fn offset(input: &str, rest: &str) -> usize {
+    input.len() - rest.len()
+}
 
-    // peek for brackets
-    let (rest, result) = many0(
-            pair(
-            alphanumeric1, 
-            delimited(
-                char('('), 
-                valid_template_string,
-                char(')')
-            ),
-        )
-    )(i)?;
-    // TODO: use the same pattern that block_value uses to limit number 
-    // of things we can search for in a recursive structure
+/// find the start of the next line which opens a top-level block
+fn next_top_level_block(i: &str) -> Option<&str> {
+    let mut position = i.find('\n')?;
+
+    loop {
+        let line = &i[position + 1..];
+        let starts_block = TOP_LEVEL_BLOCK_TYPES.iter().any(|block_type| {
+            line.starts_with(block_type) && line[block_type.len()..].starts_with(|c: char| c == ' ' || c == '{' || c == '"')
+        });
+        if starts_block {
+            return Some(line);
+        }
+        position = position + 1 + line.find('\n')?;
+    }
+}
+
+/// work out where inside a value parsing went wrong, and what was expected there
+fn locate_value_failure(i: &str) -> (&str, &'static str) {
+    let value_start = trivia(i);
+
+    if value_start.starts_with('{') {
+        if let Ok((rest, _)) = basic_block(value_start) {
+            return (rest, "a newline after the block");
+        }
+        return locate_body_failure(&value_start[1..]);
+    }
+
+    match block_value(value_start) {
+        Ok((rest, _)) => (rest, "a newline after the attribute value"),
+        Err(_) => (value_start, EXPECTED_VALUE),
+    }
+}
+
+/// work out which attribute of a block body could not be parsed
+fn locate_body_failure(i: &str) -> (&str, &'static str) {
+    let rest = match preceded(multispace0, separated_attributes)(i) {
+        Ok((rest, _)) => rest,
+        Err(_) => i,
+    };
+    let attribute_start = trivia(rest);
 
-    // println!("result.0 type: {:?}, result.1 type: {:?}", result.0.type_name(), result.1.type_name());
-    // println!("built_in_function rest: {:?}, result: {:?}", rest, result);
+    if attribute_start.is_empty() {
+        return (attribute_start, "`}` to close the block");
+    }
+
+    let key = alt((escaped_string, valid_identifier))(attribute_start);
+    match key {
+        Ok((after_key, key)) if !key.is_empty() => {
+            let (after_key, _) = space0::<&str, (&str, ErrorKind)>(after_key).unwrap_or((after_key, ""));
+            if after_key.starts_with('=') {
+                locate_value_failure(&after_key[1..])
+            } else if after_key.starts_with('{') || after_key.starts_with('"') {
+                match parse_identifiers(after_key) {
+                    Ok((labels_end, _)) => locate_value_failure(labels_end),
+                    Err(_) => (after_key, "`{` to open the nested block"),
+                }
+            } else {
+                (after_key, "`=` or `{` after the attribute name")
+            }
+        },
+        _ => (attribute_start, "an attribute (`key = value`), a nested block or `}`"),
+    }
+}
 
-    // let blarp = if i.contains("(") {
-    //     let blap: BuiltInFunction = result.1;
+/// work out where a top-level block failed to parse, the block must start at `i`
+fn locate_block_failure(i: &str) -> (&str, &'static str) {
+    let (after_identifiers, identifiers) = match parse_identifiers(i) {
+        Ok(result) => result,
+        Err(_) => return (i, "a block type such as `resource`"),
+    };
 
-    //     BuiltInFunction {
-    //         name: String::from(result.0),
-    //         param: TemplateString::BuiltInFunction(Box::new(blap))
-    //     };
-    // } else {
-    //     let bleep: TemplateString = result.1;
-    //     println!("found string: {:?}", bleep);
-    // };
+    if identifiers.is_empty() {
+        return (i, "a block type such as `resource`");
+    }
+    if identifiers.len() > 3 {
+        return (i, "at most two labels after the block type");
+    }
+    if !after_identifiers.starts_with('{') {
+        return (after_identifiers, "`{` to open the block");
+    }
 
-    let blarp = TemplateString::Variable(String::from(""));
-    Ok((rest, blarp))
+    locate_body_failure(&after_identifiers[1..])
 }
 
-fn valid_template_string_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '_' || c == '-' || c == ':' || c == '/' || c == '.' || c == '(' || c == ')' || c == '\"'
+/// Parse a whole template.
+/// Each top-level block that cannot be parsed produces a ParseError and parsing
+/// resumes at the next top-level block, so one bad line doesn't lose the whole file.
+/// Every block and attribute returned carries the Span it was parsed from.
+pub fn root(i: &str) -> (Vec<TerraformBlock>, Vec<ParseError>) {
+    let mut blocks = vec![];
+    let mut errors = vec![];
+    let mut rest = i;
+
+    loop {
+        if let Ok((remaining, mut parsed)) = many0(tf_block)(rest) {
+            blocks.append(&mut parsed);
+            rest = remaining;
+        }
+
+        let block_start = trivia(rest);
+        if block_start.is_empty() {
+            break;
+        }
+
+        let (failure, expected) = locate_block_failure(block_start);
+        errors.push(ParseError::at(i, offset(i, failure), expected));
+
+        match next_top_level_block(block_start) {
+            Some(next_block) => rest = next_block,
+            None => break,
+        }
+    }
+
+    let index = LineIndex::new(i);
+    for block in blocks.iter_mut() {
+        block.for_each_span_mut(&mut |span| span.resolve(&index));
+    }
+
+    (blocks, errors)
 }
 
-fn valid_template_string(i: &str) -> IResult<&str, TemplateString> {
-    // println!("valid_template_string input: {}", i);
-    // let (rest, result) = delimited(tag("\""), take_while(valid_template_string_char), tag("\""))(i)?;
-    let (rest, result) = take_while(valid_template_string_char)(i)?;
-    let blarp = TemplateString::Variable(String::from(result));
+/// Parse the `name = value` assignments of a .tfvars file.
+/// An assignment which cannot be parsed produces a ParseError and parsing resumes on the next line.
+pub fn tfvars(i: &str) -> (Vec<Attribute>, Vec<ParseError>) {
+    let mut assignments = vec![];
+    let mut errors = vec![];
+    let mut rest = trivia(i);
+
+    while !rest.is_empty() {
+        match attribute(rest) {
+            Ok((remaining, assignment)) if !assignment.key.is_empty() => {
+                assignments.push(assignment);
+                rest = trivia(remaining);
+            },
+            _ => {
+                errors.push(ParseError::at(i, offset(i, rest), "an assignment (`name = value`)"));
+                rest = match rest.find('\n') {
+                    Some(position) => trivia(&rest[position..]),
+                    None => "",
+                };
+            },
+        }
+    }
+
+    let index = LineIndex::new(i);
+    for assignment in assignments.iter_mut() {
+        assignment.for_each_span_mut(&mut |span| span.resolve(&index));
+    }
 
-    // println!("string result: {:?}", result);
-    // Ok((rest, result))
-    Ok((rest, blarp))
+    (assignments, errors)
 }
 
 fn templated_string(i: &str) -> IResult<&str, TemplateString> {
-    // println!("templated_string: {:?}", i);
+    preceded(space0, template_string)(i)
+}
 
-    let (rest, result) = preceded(
-        preceded(space0, tag("\"${")),
-        terminated(valid_template_string, tag("}\""))
-    )(i)?;
-    
-    // println!("templated rest: {:?}, result: {:?}", rest, result);
-    Ok((rest, result))
+/// an unquoted expression such as `aws_iam_role.role.arn`, `var.n * 2` or `merge(local.tags, {...})`.
+/// Plain literals, blocks and arrays are left to the parsers which already handle them.
+fn bare_expression(i: &str) -> IResult<&str, Expression> {
+    if i.starts_with(|c: char| c == '\n' || c == '\r') {
+        return Err(nom::Err::Error(make_error(i, ErrorKind::Verify)));
+    }
+    let (rest, result) = expression(i)?;
+
+    match result {
+        Expression::Str(_) | Expression::Num(_) | Expression::Boolean(_) | Expression::Template(_)
+            | Expression::Tuple(_) | Expression::Object(_) => Err(nom::Err::Error(make_error(i, ErrorKind::Verify))),
+        _ => Ok((rest, result)),
+    }
 }
 
 // TODO: 
@@ -362,7 +502,8 @@ fn templated_string(i: &str) -> IResult<&str, TemplateString> {
 // [√] parse serialised json blocks
 // [√] handle these: request_templates = { "application/json" = "{ \"statusCode\": 200 }" }
 // [√] parse templated strings
-// [x] handle these: etag              = "${md5(file("default-config/cpsc-vmware-config.json"))}"
+// [√] handle these: etag              = "${md5(file("default-config/cpsc-vmware-config.json"))}"
+// [√] parse HCL2 expressions and multi-part templates in templated strings
 // [√] parse whole files from cli
 // [√] build relationships from templated attribute values
 // [√] build relationships json values
@@ -380,31 +521,74 @@ mod tests {
         assert_eq!(result, ("", expected))
     }
 
-    // #[test]
-    // fn built_in_function_test() {
-    //     // TODO: create stuct for built-in function that can contain an enum which is either 
-    //     // a built-in function or a string
-    //     let data = r#""${md5(file("/Users/james.n.wilson/code/work/repos/cd-pipeline/../service-discovery//infrastructure/default-config/cpsc-vmware-config.json"))}""#;
-    //     let expected = TemplateString::Variable(String::from("someVariable"));
-    //     let result = templated_string(data).unwrap();
-    //     assert_eq!(1, 2)
-    // }
+    #[test]
+    fn built_in_function_in_resource() {
+        let data = r#"
+resource "aws_s3_bucket_object" "discovery_cpsc-vmware-config" {
+    etag                 = "${md5(file("default-config/cpsc-vmware-config.json"))}"
+    key                  = "default-config/cpsc-vmware-config.json"
+    resource_id          = "service/${aws_ecs_cluster.cluster.name}/${aws_ecs_service.service.name}"
+}
+"#;
+        let (result, errors) = root(data);
+        assert_eq!(errors, vec![]);
 
-//     #[test]
-//     fn built_in_function_in_resource() {
-//         // TODO: create stuct for built-in function that can contain an enum which is either 
-//         // a built-in function or a string
-//         let data = r#"
-// resource "aws_s3_bucket_object" "discovery_cpsc-vmware-config" {
-//     bucket               = "acp-platform-s-discovery-sandbox1"
-//     source               = "/Users/james.n.wilson/code/work/repos/cd-pipeline/../service-discovery//infrastructure/default-config/cpsc-vmware-config.json"
-//     etag                 = "${md5(file("/Users/james.n.wilson/code/work/repos/cd-pipeline/../service-discovery//infrastructure/default-config/cpsc-vmware-config.json"))}"
-//     key                  = "default-config/cpsc-vmware-config.json"
-// }
-// "#;
-//         let result = root(data).unwrap();
-//         assert_eq!(1, 2)
-//     }
+        let attributes = result[0].attributes();
+        assert!(matches!(&attributes[0].value, TemplatedString(TemplateString::BuiltInFunction(function)) if function.name == "md5"));
+        assert_eq!(attributes[1].value, Str(String::from("default-config/cpsc-vmware-config.json")));
+        assert!(matches!(&attributes[2].value, TemplatedString(TemplateString::Template(parts)) if parts.len() == 4));
+    }
+
+    #[test]
+    fn bare_expressions_are_structured_references() {
+        let data = r#"
+resource "aws_iam_role_policy" "policy" {
+    role       = aws_iam_role.foo.arn
+    count      = var.n
+    tags       = merge(local.tags, { Name = "policy" })
+    policy     = data.aws_iam_policy_document.doc.json
+    depends_on = [module.roles.role_arn]
+    enabled    = true
+}
+"#;
+        let (result, errors) = root(data);
+        assert_eq!(errors, vec![]);
+
+        let addresses: Vec<Vec<String>> = result[0].attributes().iter().map(|attr| match &attr.value {
+            AttributeType::Expression(expression) => expression.references().iter().map(|reference| reference.address()).collect(),
+            Array(values) => values.iter().flat_map(|value| match value {
+                AttributeType::Expression(expression) => expression.references().iter().map(|reference| reference.address()).collect(),
+                _ => vec![],
+            }).collect(),
+            _ => vec![],
+        }).collect();
+
+        assert_eq!(addresses, vec![
+            vec![String::from("aws_iam_role.foo")],
+            vec![String::from("var.n")],
+            vec![String::from("local.tags")],
+            vec![String::from("data.aws_iam_policy_document.doc")],
+            vec![String::from("module.roles.role_arn")],
+            vec![],
+        ]);
+        assert_eq!(result[0].attributes()[5].value, Boolean(true));
+    }
+
+    #[test]
+    fn tfvars_assignments() {
+        let data = "# overrides\ncomponent_name = \"discovery\"\ninstances = 3\nnot an assignment\nsubnets = [\"a\", \"b\"]\n";
+        let (result, errors) = tfvars(data);
+
+        let values: Vec<(&str, &AttributeType)> = result.iter().map(|attr| (attr.key.as_str(), &attr.value)).collect();
+        assert_eq!(values, vec![
+            ("component_name", &Str(String::from("discovery"))),
+            ("instances", &Num(3.0)),
+            ("subnets", &Array(vec![Str(String::from("a")), Str(String::from("b"))])),
+        ]);
+        assert_eq!(result[1].span.start.line, 3);
+        assert_eq!(errors, vec![ParseError::at(data, data.find("not an").unwrap(), "an assignment (`name = value`)")]);
+        assert_eq!(errors[0].line, 4);
+    }
 
     #[test]
     fn inline_block_with_one_pair() {
@@ -421,7 +605,7 @@ resource "aws_api_gateway_integration" "discovery_thing" {
     request_templates = { "application/json" = "{ \"statusCode\": 200 }" }
 }
 "#;
-        let (_, result) = root(data).unwrap();
+        let (result, _) = root(data);
         // println!("inline_block_resource: {:?}", result);
         let expected = vec![TerraformBlock::WithTwoIdentifiers(
             TerraformBlockWithTwoIdentifiers {
@@ -434,11 +618,17 @@ resource "aws_api_gateway_integration" "discovery_thing" {
                         value: AttributeType::Block(vec![
                             Attribute {
                                 key: String::from("application/json"), 
-                                value: AttributeType::Json(JsonValue::Object(vec![(String::from("statusCode"), JsonValue::Num(200.0))]))
+                                value: AttributeType::Json(JsonValue::Object(vec![(String::from("statusCode"), JsonValue::Num(200.0))])),
+                                span: Span::default()
                             }
                         ]
-                    )
-                }]
+                    ),
+                        span: Span::default()
+                }],
+                span: Span::default(),
+                module_path: vec![],
+                planned_actions: vec![],
+                references: vec![],
             }
         )];
         assert_eq!(result, expected)
@@ -455,7 +645,7 @@ resource "aws_sqs_queue" "discovery_collector-queue" {
     }
 }
 "#;
-        let (rest, result) = root(data).unwrap();
+        let (result, _) = root(data);
         let expected = vec![TerraformBlock::WithTwoIdentifiers(
             TerraformBlockWithTwoIdentifiers {
                 block_type: String::from("resource"), 
@@ -464,24 +654,32 @@ resource "aws_sqs_queue" "discovery_collector-queue" {
                 attributes: vec![
                     Attribute {
                         key: String::from("visibility_timeout_seconds"), 
-                        value: AttributeType::Num(30.0)
+                        value: AttributeType::Num(30.0),
+                        span: Span::default()
                     },
                     Attribute {
                         key: String::from("policy"),
                         value: AttributeType::Json(JsonValue::Object(vec![
                             (String::from("deadLetterTargetArn"), JsonValue::Str(String::from("${aws_sqs_queue.discovery_collector-deadletter-queue.arn}"))),
-                            (String::from("maxReceiveCount"), JsonValue::Num(2.0))]))
+                            (String::from("maxReceiveCount"), JsonValue::Num(2.0))])),
+                        span: Span::default()
                     },
                     Attribute {
                         key: String::from("tags"), 
                         value: AttributeType::Block(vec![(
                             Attribute {
                                 key: String::from("Environment"), 
-                                value: AttributeType::Str(String::from("sandbox1"))
+                                value: AttributeType::Str(String::from("sandbox1")),
+                                span: Span::default()
                             }
-                            )])
+                            )]),
+                        span: Span::default()
                     }
-                ]
+                ],
+                span: Span::default(),
+                module_path: vec![],
+                planned_actions: vec![],
+                references: vec![],
             }
         )];
 
@@ -504,20 +702,26 @@ resource "aws_kms_key" "discovery_cache-master-key" {
         let result = root(data);
         let first_attr = Attribute {
             key: String::from("description"),
-            value: AttributeType::Str(String::from("Master key used for creating/decrypting cache token data keys"))
+            value: AttributeType::Str(String::from("Master key used for creating/decrypting cache token data keys")),
+            span: Span::default()
         };
         let second_attr = Attribute {
             key: String::from("enable_key_rotation"),
-            value: AttributeType::Boolean(true)
+            value: AttributeType::Boolean(true),
+            span: Span::default()
         };
         let block = TerraformBlockWithTwoIdentifiers {
             block_type: String::from("resource"),
             first_identifier: String::from("aws_kms_key"),
             second_identifier: String::from("discovery_cache-master-key"),
-            attributes: vec![first_attr, second_attr]
+            attributes: vec![first_attr, second_attr],
+            span: Span::default(),
+            module_path: vec![],
+            planned_actions: vec![],
+            references: vec![],
         };
         let expected = vec![TerraformBlock::WithTwoIdentifiers(block)];
-        assert_eq!(result, Ok(("\n", expected)))
+        assert_eq!(result, (expected, vec![]))
     }
 
     #[test] 
@@ -533,20 +737,26 @@ resource "aws_kms_key" "discovery_cache-master-key" {
         let result = root(data);
         let first_attr = Attribute {
             key: String::from("description"),
-            value: AttributeType::Str(String::from("Master key used for creating/decrypting cache token data keys"))
+            value: AttributeType::Str(String::from("Master key used for creating/decrypting cache token data keys")),
+            span: Span::default()
         };
         let second_attr = Attribute {
             key: String::from("enable_key_rotation"),
-            value: AttributeType::Boolean(true)
+            value: AttributeType::Boolean(true),
+            span: Span::default()
         };
         let block = TerraformBlockWithTwoIdentifiers {
             block_type: String::from("resource"),
             first_identifier: String::from("aws_kms_key"),
             second_identifier: String::from("discovery_cache-master-key"),
-            attributes: vec![first_attr, second_attr]
+            attributes: vec![first_attr, second_attr],
+            span: Span::default(),
+            module_path: vec![],
+            planned_actions: vec![],
+            references: vec![],
         };
         let expected = vec![TerraformBlock::WithTwoIdentifiers(block)];
-        assert_eq!(result, Ok(("\n", expected)))
+        assert_eq!(result, (expected, vec![]))
     }
 
     #[test] 
@@ -566,20 +776,26 @@ resource "aws_kms_key" "discovery_cache-master-key" {
         let result = root(data);
         let first_attr = Attribute {
             key: String::from("description"),
-            value: AttributeType::Str(String::from("Master key used for creating/decrypting cache token data keys"))
+            value: AttributeType::Str(String::from("Master key used for creating/decrypting cache token data keys")),
+            span: Span::default()
         };
         let second_attr = Attribute {
             key: String::from("enable_key_rotation"),
-            value: AttributeType::Boolean(true)
+            value: AttributeType::Boolean(true),
+            span: Span::default()
         };
         let block = TerraformBlockWithTwoIdentifiers {
             block_type: String::from("resource"),
             first_identifier: String::from("aws_kms_key"),
             second_identifier: String::from("discovery_cache-master-key"),
-            attributes: vec![first_attr, second_attr]
+            attributes: vec![first_attr, second_attr],
+            span: Span::default(),
+            module_path: vec![],
+            planned_actions: vec![],
+            references: vec![],
         };
         let expected = vec![TerraformBlock::WithTwoIdentifiers(block)];
-        assert_eq!(result, Ok(("\n", expected)))
+        assert_eq!(result, (expected, vec![]))
         // assert_eq!(1, 2)
     }
 
@@ -689,27 +905,108 @@ terraform {
   }
 }
 "#;
-        let (_, result) = root(data).unwrap();
+        let (result, _) = root(data);
         let block = TerraformBlockWithNoIdentifiers { 
             block_type: String::from("terraform"),
             attributes: vec![
-                Attribute { key: String::from("required_version"), value: AttributeType::Str(String::from("= 0.11.2")) },
+                Attribute { key: String::from("required_version"), value: AttributeType::Str(String::from("= 0.11.2")), span: Span::default() },
                 Attribute { key: String::from("backend"), value: AttributeType::TFBlock(TerraformBlock::WithOneIdentifier(TerraformBlockWithOneIdentifier {
                     block_type: String::from("inner"),
                     first_identifier: String::from("s3"),
                     attributes: vec![
-                        Attribute { key: String::from("bucket"), value: AttributeType::Str(String::from("acp-platform-s-discovery-sandbox1")) },
-                        Attribute { key: String::from("key"), value: AttributeType::Str(String::from("infrastructure/terraform.tfstate")) },
-                        Attribute { key: String::from("region"), value: AttributeType::Str(String::from("us-east-1")) }
-                    ]
-                }))}
-            ]
+                        Attribute { key: String::from("bucket"), value: AttributeType::Str(String::from("acp-platform-s-discovery-sandbox1")), span: Span::default() },
+                        Attribute { key: String::from("key"), value: AttributeType::Str(String::from("infrastructure/terraform.tfstate")), span: Span::default() },
+                        Attribute { key: String::from("region"), value: AttributeType::Str(String::from("us-east-1")), span: Span::default() }
+                    ],
+                    span: Span::default()
+                })), span: Span::default() }
+            ],
+            span: Span::default()
         };
 
         let expected = vec![TerraformBlock::NoIdentifiers(block)];
         assert_eq!(result, expected)
     }
 
+    #[test]
+    fn spans_on_blocks_and_attributes() {
+        let data = r#"
+terraform {
+  required_version = "= 0.11.2"
+  backend "s3" {
+    bucket  = "acp-platform-s-discovery-sandbox1"
+  }
+}
+"#;
+        let (result, _) = root(data);
+        let block_span = result[0].span();
+        let attributes = result[0].attributes();
+
+        assert_eq!((block_span.start.offset, block_span.start.line, block_span.start.column), (1, 2, 1));
+        assert_eq!((block_span.end.line, block_span.end.column), (7, 2));
+        assert_eq!((attributes[0].span.start.line, attributes[0].span.start.column), (3, 3));
+        assert_eq!((attributes[0].span.end.line, attributes[0].span.end.column), (3, 32));
+
+        match &attributes[1].value {
+            AttributeType::TFBlock(backend) => {
+                assert_eq!((backend.span().start.line, backend.span().start.column), (4, 11));
+                assert_eq!(backend.attributes()[0].span.start.line, 5)
+            },
+            other => panic!("expected a nested block, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn root_recovers_at_next_top_level_block() {
+        let data = r#"
+resource "aws_kms_key" "first" {
+    enable_key_rotation = true
+}
+
+resource "aws_kms_key" "broken" {
+    description = ,
+}
+
+resource "aws_kms_key" "second" {
+    enable_key_rotation = false
+}
+"#;
+        let (result, errors) = root(data);
+        let ids: Vec<String> = result.iter().map(|block| block.get_id()).collect();
+
+        assert_eq!(ids, vec![String::from("aws_kms_key_first"), String::from("aws_kms_key_second")]);
+        assert_eq!(errors, vec![ParseError {
+            file: None,
+            line: 7,
+            column: 19,
+            snippet: String::from("    description = ,"),
+            expected: String::from(EXPECTED_VALUE),
+        }])
+    }
+
+    #[test]
+    fn block_with_too_many_identifiers_is_a_diagnostic() {
+        let data = r#"resource "aws_kms_key" "key" "extra" {
+    enable_key_rotation = true
+}
+"#;
+        let (result, errors) = root(data);
+
+        assert_eq!(result, vec![]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (1, 1));
+        assert_eq!(errors[0].expected, "at most two labels after the block type")
+    }
+
+    #[test]
+    fn root_ignores_trailing_comments() {
+        let data = "resource \"aws_kms_key\" \"key\" {\n    enable_key_rotation = true\n}\n\n# Resources generated: 1";
+        let (result, errors) = root(data);
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(errors, vec![])
+    }
+
 //     #[test]
 //     fn parse_comments_and_blank_lines() {
 //         let data = r#"
@@ -875,7 +1172,7 @@ terraform {
 //             attributes: vec![first_attr, second_attr]
 //         };
 //         let expected = vec![TerraformBlock::WithTwoIdentifiers(block)];
-//         assert_eq!(result, Ok(("\n", expected)))
+//         assert_eq!(result, (expected, vec![]))
 //     }
 
 //     #[test]
@@ -953,7 +1250,7 @@ terraform {
 //             attributes: vec![first_attr1, second_attr2]
 //         };
 //         let expected = vec![TerraformBlock::WithTwoIdentifiers(block), TerraformBlock::WithTwoIdentifiers(block2)];
-//         assert_eq!(result, Ok(("\n", expected)))
+//         assert_eq!(result, (expected, vec![]))
 //     }
 
 //     #[test]
@@ -0,0 +1,465 @@
+//! Terraform's JSON configuration syntax, as found in `*.tf.json` and `*.tfvars.json` files.
+//! - the document is checked and read by serde_json, its values remembering where they were written
+//! - the top-level objects are turned into the same TerraformBlocks `terraform::root` produces, so
+//!   `{"resource": {"aws_sqs_queue": {"queue": {...}}}}` becomes the block `resource "aws_sqs_queue" "queue" {...}`
+//! - strings containing `${...}` are parsed as templates and strings holding a JSON document as Json,
+//!   everything else keeps its JSON type
+//! - an array of objects is a repeated nested block, eg. `"ingress": [{...}, {...}]`
+//! - `"//"` properties are comments and are skipped
+
+use serde::de::{ Deserialize, Deserializer, MapAccess, Visitor };
+use serde_json::value::RawValue;
+use std::fmt;
+
+use crate::structs::terraform_block::{
+    TerraformBlock,
+    TerraformBlockWithNoIdentifiers,
+    TerraformBlockWithOneIdentifier,
+    TerraformBlockWithTwoIdentifiers,
+};
+use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::template_string::Expression;
+use crate::structs::json::JsonValue;
+use crate::structs::parse_error::ParseError;
+use crate::structs::span::{ LineIndex, Span };
+use crate::expression::template_string;
+
+/// A JSON value with the input remaining at its start and end, see Span::remaining
+#[derive(Debug, Clone)]
+pub(crate) struct Located {
+    pub(crate) value: Value,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    Str(String),
+    Num(f64),
+    Boolean(bool),
+    Null,
+    Array(Vec<Located>),
+    /// properties in the order written, each with the input remaining at its key
+    Object(Vec<(String, usize, Located)>),
+}
+
+/// the properties of an object in the order written, their values left as the text serde_json read them from
+struct Properties<'a>(Vec<(String, &'a RawValue)>);
+
+impl<'de> Deserialize<'de> for Properties<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Properties<'de>, D::Error> {
+        struct PropertiesVisitor;
+
+        impl<'de> Visitor<'de> for PropertiesVisitor {
+            type Value = Properties<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an object")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Properties<'de>, M::Error> {
+                let mut properties = vec![];
+                while let Some(property) = map.next_entry()? {
+                    properties.push(property);
+                }
+                Ok(Properties(properties))
+            }
+        }
+
+        deserializer.deserialize_map(PropertiesVisitor)
+    }
+}
+
+/// where a value serde_json read lies within the document `i`, which its text is a slice of
+fn offset(i: &str, text: &str) -> usize {
+    text.as_ptr() as usize - i.as_ptr() as usize
+}
+
+/// read a value serde_json has checked, and the values within it, remembering where each was written
+fn located(i: &str, raw: &RawValue) -> serde_json::Result<Located> {
+    let text = raw.get();
+    let start = offset(i, text);
+
+    let value = match text.as_bytes().first() {
+        Some(b'{') => {
+            let Properties(properties) = serde_json::from_str(text)?;
+            // between the end of the previous value, or the brace, and a value are only a comma, its key and a colon
+            let mut previous_end = start + 1;
+            let mut located_properties = vec![];
+            for (key, value) in properties {
+                let value_start = offset(i, value.get());
+                let key_start = previous_end + i[previous_end..value_start].find('"').unwrap_or(0);
+                previous_end = value_start + value.get().len();
+                located_properties.push((key, i.len() - key_start, located(i, value)?));
+            }
+            Value::Object(located_properties)
+        },
+        Some(b'[') => {
+            let items: Vec<&RawValue> = serde_json::from_str(text)?;
+            Value::Array(items.into_iter().map(|item| located(i, item)).collect::<serde_json::Result<_>>()?)
+        },
+        _ => match serde_json::from_str(text)? {
+            serde_json::Value::String(text) => Value::Str(text),
+            serde_json::Value::Number(number) => Value::Num(number.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::Bool(boolean) => Value::Boolean(boolean),
+            _ => Value::Null,
+        },
+    };
+
+    Ok(Located { value, start: i.len() - start, end: i.len() - start - text.len() })
+}
+
+/// the offset of the byte a serde_json error was found at, from its line and the bytes read of that line
+fn error_offset(i: &str, error: &serde_json::Error) -> usize {
+    let line_start: usize = i.split('\n').take(error.line().saturating_sub(1)).map(|line| line.len() + 1).sum();
+    let mut offset = (line_start + error.column().saturating_sub(1)).min(i.len());
+    while !i.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// parse a whole JSON document, or say where it stops being JSON
+pub(crate) fn document(i: &str) -> Result<Located, ParseError> {
+    serde_json::from_str(i)
+        .and_then(|raw| located(i, raw))
+        .map_err(|error| ParseError::at(i, error_offset(i, &error), "a JSON value"))
+}
+
+/// how many labels, such as the resource type and name, each type of block has
+fn label_count(block_type: &str) -> usize {
+    match block_type {
+        "resource" | "data" => 2,
+        "variable" | "output" | "module" | "provider" => 1,
+        _ => 0,
+    }
+}
+
+fn is_comment(key: &str) -> bool {
+    key == "//"
+}
+
+/// Parse a whole `.tf.json` document into the blocks it declares.
+/// Anything shaped wrongly produces a ParseError and is skipped, the rest of the document is still read.
+pub fn root(i: &str) -> (Vec<TerraformBlock>, Vec<ParseError>) {
+    let mut blocks = vec![];
+    let mut errors = vec![];
+
+    let properties = match document(i) {
+        Ok(Located { value: Value::Object(properties), .. }) => properties,
+        Ok(other) => return (blocks, vec![ParseError::at(i, i.len() - other.start, "an object of block types")]),
+        Err(error) => return (blocks, vec![error]),
+    };
+
+    for (block_type, key_start, value) in properties.iter().filter(|(key, _, _)| !is_comment(key)) {
+        collect_blocks(i, block_type, vec![], label_count(block_type), *key_start, value, &mut blocks, &mut errors);
+    }
+
+    let index = LineIndex::new(i);
+    for block in blocks.iter_mut() {
+        block.for_each_span_mut(&mut |span| span.resolve(&index));
+    }
+
+    (blocks, errors)
+}
+
+/// Read the labels of a block from the nested objects keyed by them, then its body.
+/// At any level an array stands for several objects, eg. a provider configured with aliases.
+#[allow(clippy::too_many_arguments)]
+fn collect_blocks(
+    i: &str,
+    block_type: &str,
+    labels: Vec<String>,
+    remaining_labels: usize,
+    key_start: usize,
+    value: &Located,
+    blocks: &mut Vec<TerraformBlock>,
+    errors: &mut Vec<ParseError>,
+) {
+    match &value.value {
+        Value::Array(items) => {
+            for item in items {
+                collect_blocks(i, block_type, labels.clone(), remaining_labels, key_start, item, blocks, errors);
+            }
+        },
+        Value::Object(properties) if remaining_labels > 0 => {
+            for (label, label_start, value) in properties.iter().filter(|(key, _, _)| !is_comment(key)) {
+                let mut labels = labels.clone();
+                labels.push(label.to_string());
+                collect_blocks(i, block_type, labels, remaining_labels - 1, *label_start, value, blocks, errors);
+            }
+        },
+        Value::Object(properties) => {
+            let attributes = attributes(i, properties, errors);
+            blocks.push(build_tf_block(block_type, labels, attributes, Span::remaining(key_start, value.end)));
+        },
+        _ if remaining_labels > 0 => errors.push(ParseError::at(i, i.len() - value.start, "an object keyed by block label")),
+        _ => errors.push(ParseError::at(i, i.len() - value.start, "an object of block arguments")),
+    }
+}
+
+fn build_tf_block(block_type: &str, mut labels: Vec<String>, attributes: Vec<Attribute>, span: Span) -> TerraformBlock {
+    let block_type = block_type.to_string();
+
+    match labels.len() {
+        0 => TerraformBlock::NoIdentifiers(TerraformBlockWithNoIdentifiers { block_type, attributes, span }),
+        1 => TerraformBlock::WithOneIdentifier(TerraformBlockWithOneIdentifier {
+            block_type,
+            first_identifier: labels.remove(0),
+            attributes,
+            span,
+        }),
+        _ => TerraformBlock::WithTwoIdentifiers(TerraformBlockWithTwoIdentifiers {
+            block_type,
+            first_identifier: labels.remove(0),
+            second_identifier: labels.remove(0),
+            attributes,
+            span,
+            module_path: vec![],
+            planned_actions: vec![],
+            references: vec![],
+        }),
+    }
+}
+
+pub(crate) fn attributes(i: &str, properties: &[(String, usize, Located)], errors: &mut Vec<ParseError>) -> Vec<Attribute> {
+    let mut attributes = vec![];
+
+    for (key, key_start, value) in properties.iter().filter(|(key, _, _)| !is_comment(key)) {
+        let span = Span::remaining(*key_start, value.end);
+        match &value.value {
+            // an array of objects is a nested block written more than once
+            Value::Array(items) if !items.is_empty() && items.iter().all(|item| matches!(item.value, Value::Object(_))) => {
+                for item in items {
+                    attributes.push(Attribute {
+                        key: key.to_string(),
+                        value: attribute_value(i, item, errors),
+                        span: Span::remaining(item.start, item.end),
+                    });
+                }
+            },
+            _ => attributes.push(Attribute { key: key.to_string(), value: attribute_value(i, value, errors), span }),
+        }
+    }
+
+    attributes
+}
+
+fn attribute_value(i: &str, value: &Located, errors: &mut Vec<ParseError>) -> AttributeType {
+    match &value.value {
+        Value::Str(text) => string_value(text),
+        Value::Num(number) => AttributeType::Num(*number),
+        Value::Boolean(boolean) => AttributeType::Boolean(*boolean),
+        Value::Null => AttributeType::Expression(Expression::Null),
+        Value::Array(items) => AttributeType::Array(items.iter().map(|item| attribute_value(i, item, errors)).collect()),
+        Value::Object(properties) => AttributeType::Block(attributes(i, properties, errors)),
+    }
+}
+
+/// strings may hold templates, or whole JSON documents such as IAM policies
+fn string_value(text: &str) -> AttributeType {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        if let Ok(document) = document(text) {
+            return AttributeType::Json(json_value(&document));
+        }
+    }
+
+    if text.contains("${") {
+        if let Ok(("", template)) = template_string(&format!("\"{}\"", escape_literals(text))) {
+            return AttributeType::TemplatedString(template);
+        }
+    }
+
+    AttributeType::Str(text.to_string())
+}
+
+/// write the text outside of `${...}` interpolations the way it would appear within an HCL string,
+/// so the template parser sees quotes and backslashes in literal text as escapes
+fn escape_literals(text: &str) -> String {
+    let mut escaped = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if depth == 0 {
+            match c {
+                '$' if chars.peek() == Some(&'{') => {
+                    depth = 1;
+                    escaped.push(c);
+                    escaped.push(chars.next().unwrap());
+                },
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c => escaped.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '\\' if quoted => {
+                escaped.push(c);
+                if let Some(next) = chars.next() {
+                    escaped.push(next);
+                }
+                continue;
+            },
+            '"' => quoted = !quoted,
+            '{' if !quoted => depth += 1,
+            '}' if !quoted => depth -= 1,
+            _ => (),
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn json_value(value: &Located) -> JsonValue {
+    match &value.value {
+        Value::Str(text) => JsonValue::Str(text.to_string()),
+        Value::Num(number) => JsonValue::Num(*number),
+        Value::Boolean(boolean) => JsonValue::Boolean(*boolean),
+        Value::Null => JsonValue::Null(String::from("null")),
+        Value::Array(items) => JsonValue::Array(items.iter().map(json_value).collect()),
+        Value::Object(properties) => JsonValue::Object(properties.iter().map(|(key, _, value)| (key.to_string(), json_value(value))).collect()),
+    }
+}
+
+/// Parse the assignments of a `.tfvars.json` file, one per property of its top-level object.
+pub fn tfvars(i: &str) -> (Vec<Attribute>, Vec<ParseError>) {
+    let mut errors = vec![];
+
+    let mut assignments = match document(i) {
+        Ok(Located { value: Value::Object(properties), .. }) => attributes(i, &properties, &mut errors),
+        Ok(other) => return (vec![], vec![ParseError::at(i, i.len() - other.start, "an object of variable values")]),
+        Err(error) => return (vec![], vec![error]),
+    };
+
+    let index = LineIndex::new(i);
+    for assignment in assignments.iter_mut() {
+        assignment.for_each_span_mut(&mut |span| span.resolve(&index));
+    }
+
+    (assignments, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terraform;
+
+    #[test]
+    fn same_blocks_as_hcl() {
+        let hcl = r#"
+variable "environment" {
+    default = "prod"
+}
+
+resource "aws_sqs_queue" "queue" {
+    name                       = "discovery-${var.environment}"
+    delay_seconds              = 90
+    fifo_queue                 = false
+    redrive_policy             = "${aws_sqs_queue.deadletter.arn}"
+    tags = {
+        Environment = "production"
+    }
+}
+
+resource "aws_security_group" "group" {
+    ingress {
+        from_port = 443
+    }
+    ingress {
+        from_port = 80
+    }
+}
+
+locals {
+    queues = ["one", "two"]
+}
+"#;
+        let json = r#"{
+  "//": "generated",
+  "variable": {
+    "environment": { "default": "prod" }
+  },
+  "resource": {
+    "aws_sqs_queue": {
+      "queue": {
+        "name": "discovery-${var.environment}",
+        "delay_seconds": 90,
+        "fifo_queue": false,
+        "redrive_policy": "${aws_sqs_queue.deadletter.arn}",
+        "tags": { "Environment": "production" }
+      }
+    },
+    "aws_security_group": {
+      "group": {
+        "ingress": [{ "from_port": 443 }, { "from_port": 80 }]
+      }
+    }
+  },
+  "locals": {
+    "queues": ["one", "two"]
+  }
+}"#;
+        let (expected, errors) = terraform::root(hcl);
+        assert_eq!(errors, vec![]);
+
+        let (result, errors) = root(json);
+        assert_eq!(errors, vec![]);
+        assert_eq!(result, expected);
+        assert_eq!(result[1].span().start.line, 8);
+        assert_eq!(result[1].attributes()[1].span.start.line, 10);
+    }
+
+    #[test]
+    fn strings_holding_templates_and_documents() {
+        let json = r#"{"resource": {"aws_iam_policy": {"policy": {
+  "name": "${lookup(var.names, \"policy\")}-\"quoted\"",
+  "policy": "{\"Statement\": [{\"Effect\": \"Allow\", \"Resource\": [\"${aws_sqs_queue.queue.arn}\"]}]}"
+}}}}"#;
+        let (result, errors) = root(json);
+        assert_eq!(errors, vec![]);
+
+        let attributes = result[0].attributes();
+        match &attributes[0].value {
+            AttributeType::TemplatedString(template) => assert_eq!(template.to_string(), r#"${lookup(var.names, "policy")}-"quoted""#),
+            other => panic!("expected a template, got {:?}", other),
+        }
+        assert_eq!(attributes[1].value, AttributeType::Json(JsonValue::Object(vec![
+            (String::from("Statement"), JsonValue::Array(vec![JsonValue::Object(vec![
+                (String::from("Effect"), JsonValue::Str(String::from("Allow"))),
+                (String::from("Resource"), JsonValue::Array(vec![JsonValue::Str(String::from("${aws_sqs_queue.queue.arn}"))])),
+            ])])),
+        ])));
+    }
+
+    #[test]
+    fn errors_are_located() {
+        let (result, errors) = root("{\n  \"resource\": {\n    \"aws_sqs_queue\": \"queue\",\n  }\n}");
+        assert_eq!(result, vec![]);
+        // a trailing comma is found at the brace which follows it
+        assert_eq!((errors[0].line, errors[0].column), (4, 3));
+
+        let (_, errors) = root("{\"resource\": {\"aws_sqs_queue\": {\"queue\": {\"name\": \"a\\qb\"}}}}");
+        assert_eq!((errors[0].line, errors[0].column), (1, 54));
+
+        let (_, errors) = root("{\"resource\": {\"aws_sqs_queue\": {\"queue\": [1]}}}");
+        assert_eq!(errors[0].expected, "an object of block arguments");
+        assert_eq!(errors[0].column, 43);
+    }
+
+    #[test]
+    fn tfvars_assignments() {
+        let (assignments, errors) = tfvars("{\"environment\": \"prod\", \"instances\": 3}");
+        assert_eq!(errors, vec![]);
+        assert_eq!(assignments.iter().map(|assignment| assignment.key.as_str()).collect::<Vec<&str>>(), vec!["environment", "instances"]);
+        assert_eq!(assignments[1].value, AttributeType::Num(3.0));
+    }
+}
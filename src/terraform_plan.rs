@@ -0,0 +1,510 @@
+//! The JSON `terraform show -json` writes for a saved plan, or for the state.
+//! - every resource instance becomes a `resource` or `data` block holding its values: for a plan the values
+//!   it will deploy, for a state the values recorded, so policies run against what is actually deployed
+//! - the actions a plan will take on each instance, eg. `["create"]` or `["delete", "create"]`, are kept as its `planned_actions`
+//! - the resources an instance refers to, from the references in a plan's `configuration` and the `depends_on`
+//!   of a state, are kept as its `references`, resolved to the addresses of the instances read
+//! - values which are only known once the plan is applied are written `(known after apply)`, as terraform prints them
+
+use std::collections::HashMap;
+
+use crate::structs::terraform_block::{
+    TerraformBlock,
+    TerraformBlockWithTwoIdentifiers,
+    module_prefix,
+};
+use crate::structs::attributes::{ Attribute, AttributeType };
+use crate::structs::parse_error::ParseError;
+use crate::structs::span::{ LineIndex, Span };
+use crate::terraform_json::{ self, Located, Value };
+
+/// how terraform prints a value it won't know until the plan is applied
+pub const UNKNOWN: &str = "(known after apply)";
+
+/// the first words of references to things other than resources and data sources
+const NOT_RESOURCES: [&str; 8] = ["var", "local", "each", "count", "path", "terraform", "self", "module"];
+
+/// whether a JSON document was written by `terraform show -json`
+pub fn is_plan(contents: &str) -> bool {
+    contents.trim_start().starts_with('{') && contents.contains("\"format_version\"") && contents.contains("\"terraform_version\"")
+}
+
+fn get<'a>(value: &'a Located, key: &str) -> Option<&'a Located> {
+    match &value.value {
+        Value::Object(properties) => properties.iter().find(|(name, _, _)| name == key).map(|(_, _, value)| value),
+        _ => None,
+    }
+}
+
+fn as_str(value: Option<&Located>) -> Option<&str> {
+    match value {
+        Some(Located { value: Value::Str(text), .. }) => Some(text),
+        _ => None,
+    }
+}
+
+fn items(value: Option<&Located>) -> &[Located] {
+    match value {
+        Some(Located { value: Value::Array(items), .. }) => items,
+        _ => &[],
+    }
+}
+
+fn strings(value: Option<&Located>) -> Vec<String> {
+    items(value).iter().filter_map(|item| as_str(Some(item))).map(str::to_string).collect()
+}
+
+/// Parse the output of `terraform show -json` into a block for each resource instance.
+/// Plans are read from their `resource_changes`, states from their `values`.
+pub fn root(i: &str) -> (Vec<TerraformBlock>, Vec<ParseError>) {
+    let mut errors = vec![];
+
+    let document = match terraform_json::document(i) {
+        Ok(document) if matches!(document.value, Value::Object(_)) => document,
+        Ok(other) => return (vec![], vec![ParseError::at(i, i.len() - other.start, "the output of `terraform show -json`")]),
+        Err(error) => return (vec![], vec![error]),
+    };
+
+    let mut configured = HashMap::new();
+    if let Some(module) = get(&document, "configuration").and_then(|configuration| get(configuration, "root_module")) {
+        configured_references(module, "", &mut configured);
+    }
+
+    let mut instances = vec![];
+    match get(&document, "resource_changes") {
+        Some(changes) => {
+            for change in items(Some(changes)) {
+                planned_instance(i, change, &configured, &mut instances, &mut errors);
+            }
+        },
+        None => {
+            let values = get(&document, "values").or_else(|| get(&document, "planned_values"));
+            if let Some(module) = values.and_then(|values| get(values, "root_module")) {
+                state_instances(i, module, &configured, &mut instances, &mut errors);
+            }
+        },
+    }
+
+    let addresses: Vec<String> = instances.iter().map(|(block, _)| block.address()).collect();
+    let index = LineIndex::new(i);
+    let blocks = instances.into_iter().map(|(mut block, referred)| {
+        let address = block.address();
+        if let TerraformBlock::WithTwoIdentifiers(resource) = &mut block {
+            resource.references = resolve(&referred, &address, &addresses);
+        }
+        block.for_each_span_mut(&mut |span| span.resolve(&index));
+        block
+    }).collect();
+
+    (blocks, errors)
+}
+
+/// Gather what each resource of a module's configuration refers to, keyed by its address without instance keys,
+/// eg. `module.queues.aws_sqs_queue.queue`. References are kept as written, relative to the module.
+fn configured_references(module: &Located, prefix: &str, configured: &mut HashMap<String, Vec<String>>) {
+    for resource in items(get(module, "resources")) {
+        if let Some(address) = as_str(get(resource, "address")) {
+            let mut references = vec![];
+            // expressions, count and for_each expressions all list their references
+            collect_references(resource, &mut references);
+            references.extend(strings(get(resource, "depends_on")));
+            configured.insert(format!("{}{}", prefix, address), references);
+        }
+    }
+
+    if let Some(Located { value: Value::Object(calls), .. }) = get(module, "module_calls") {
+        for (name, _, call) in calls {
+            if let Some(child) = get(call, "module") {
+                configured_references(child, &format!("{}module.{}.", prefix, name), configured);
+            }
+        }
+    }
+}
+
+fn collect_references(value: &Located, references: &mut Vec<String>) {
+    match &value.value {
+        Value::Object(properties) => {
+            for (key, _, value) in properties {
+                if key == "references" {
+                    references.extend(strings(Some(value)));
+                } else {
+                    collect_references(value, references);
+                }
+            }
+        },
+        Value::Array(items) => items.iter().for_each(|item| collect_references(item, references)),
+        _ => (),
+    }
+}
+
+/// a resource change of a plan, with the values it will have afterwards, or for a delete the values it had
+fn planned_instance(
+    i: &str,
+    change: &Located,
+    configured: &HashMap<String, Vec<String>>,
+    instances: &mut Vec<(TerraformBlock, Vec<String>)>,
+    errors: &mut Vec<ParseError>,
+) {
+    let details = match get(change, "change") {
+        Some(details) => details,
+        None => return errors.push(ParseError::at(i, i.len() - change.start, "a resource change with a `change`")),
+    };
+    let values = match get(details, "after") {
+        Some(after) if matches!(after.value, Value::Object(_)) => Some(after),
+        _ => get(details, "before"),
+    };
+
+    let mut attributes = values_attributes(i, values, errors);
+    if let Some(Located { value: Value::Object(unknown), .. }) = get(details, "after_unknown") {
+        for (key, key_start, value) in unknown {
+            if matches!(value.value, Value::Boolean(true)) && !attributes.iter().any(|attr| &attr.key == key) {
+                attributes.push(Attribute {
+                    key: key.to_string(),
+                    value: AttributeType::Str(String::from(UNKNOWN)),
+                    span: Span::remaining(*key_start, value.end),
+                });
+            }
+        }
+    }
+
+    let module_address = as_str(get(change, "module_address"));
+    match build_block(change, module_address, attributes, strings(get(details, "actions"))) {
+        Some(block) => {
+            let referred = configured_for(&block, configured);
+            instances.push((block, referred));
+        },
+        None => errors.push(ParseError::at(i, i.len() - change.start, "a resource change with an `address`, `mode`, `type` and `name`")),
+    }
+}
+
+/// the resources of a state, or of a plan's planned values, and those of its child modules
+fn state_instances(
+    i: &str,
+    module: &Located,
+    configured: &HashMap<String, Vec<String>>,
+    instances: &mut Vec<(TerraformBlock, Vec<String>)>,
+    errors: &mut Vec<ParseError>,
+) {
+    let module_address = as_str(get(module, "address"));
+
+    for resource in items(get(module, "resources")) {
+        let attributes = values_attributes(i, get(resource, "values"), errors);
+        match build_block(resource, module_address, attributes, vec![]) {
+            Some(block) => {
+                let mut referred = configured_for(&block, configured);
+                // a state's depends_on are already addresses from the root module
+                referred.extend(strings(get(resource, "depends_on")));
+                instances.push((block, referred));
+            },
+            None => errors.push(ParseError::at(i, i.len() - resource.start, "a resource with an `address`, `mode`, `type` and `name`")),
+        }
+    }
+
+    for child in items(get(module, "child_modules")) {
+        state_instances(i, child, configured, instances, errors);
+    }
+}
+
+/// the known values of an instance as attributes. Terraform writes every attribute of the schema, those not set are null and left out.
+fn values_attributes(i: &str, values: Option<&Located>, errors: &mut Vec<ParseError>) -> Vec<Attribute> {
+    match values {
+        Some(Located { value: Value::Object(properties), .. }) => {
+            let set: Vec<(String, usize, Located)> = properties.iter()
+                .filter(|(_, _, value)| !matches!(value.value, Value::Null))
+                .cloned()
+                .collect();
+            terraform_json::attributes(i, &set, errors)
+        },
+        _ => vec![],
+    }
+}
+
+fn build_block(resource: &Located, module_address: Option<&str>, attributes: Vec<Attribute>, planned_actions: Vec<String>) -> Option<TerraformBlock> {
+    let address = as_str(get(resource, "address"))?;
+    let mode = as_str(get(resource, "mode"))?;
+    let resource_type = as_str(get(resource, "type"))?;
+    as_str(get(resource, "name"))?;
+
+    // the name, with any instance key, eg. `queue[0]`, is what follows the module and type in the address
+    let local = match module_address {
+        Some(module_address) => address.strip_prefix(module_address)?.strip_prefix('.')?,
+        None => address,
+    };
+    let local = if mode == "data" { local.strip_prefix("data.")? } else { local };
+    let name = local.strip_prefix(resource_type)?.strip_prefix('.')?;
+
+    Some(TerraformBlock::WithTwoIdentifiers(TerraformBlockWithTwoIdentifiers {
+        block_type: String::from(if mode == "data" { "data" } else { "resource" }),
+        first_identifier: resource_type.to_string(),
+        second_identifier: name.to_string(),
+        attributes,
+        span: Span::remaining(resource.start, resource.end),
+        module_path: module_address.map(module_path).unwrap_or_default(),
+        planned_actions,
+        references: vec![],
+    }))
+}
+
+/// the references configured for an instance, made relative to the root module
+fn configured_for(block: &TerraformBlock, configured: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let resource = match block {
+        TerraformBlock::WithTwoIdentifiers(resource) => resource,
+        _ => return vec![],
+    };
+    let without_keys: Vec<String> = resource.module_path.iter().map(|name| without_key(name).to_string()).collect();
+    let mode = if resource.block_type == "data" { "data." } else { "" };
+    let key = format!("{}{}{}.{}", module_prefix(&without_keys), mode, resource.first_identifier, without_key(&resource.second_identifier));
+
+    let prefix = module_prefix(&resource.module_path);
+    configured.get(&key).into_iter().flatten().map(|reference| format!("{}{}", prefix, reference)).collect()
+}
+
+fn without_key(name: &str) -> &str {
+    name.split('[').next().unwrap_or(name)
+}
+
+/// split an address on the dots outside of its instance keys, eg. `module.queues["a.b"].aws_sqs_queue.queue`
+fn segments(address: &str) -> Vec<String> {
+    let mut segments = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+
+    for c in address.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' if !quoted => depth += 1,
+            ']' if !quoted => depth -= 1,
+            '.' if !quoted && depth == 0 => {
+                segments.push(std::mem::take(&mut current));
+                continue;
+            },
+            _ => (),
+        }
+        current.push(c);
+    }
+    segments.push(current);
+    segments
+}
+
+/// the names of the modules in a module address, eg. `["queues", "dlq[0]"]` for `module.queues.module.dlq[0]`
+fn module_path(module_address: &str) -> Vec<String> {
+    segments(module_address).chunks(2)
+        .filter(|pair| pair.len() == 2 && pair[0] == "module")
+        .map(|pair| pair[1].to_string())
+        .collect()
+}
+
+/// The resource a reference from the root module names, both without and with any instance key it gives,
+/// eg. `aws_sqs_queue.queue[0].arn` names `aws_sqs_queue.queue` and `aws_sqs_queue.queue[0]`.
+/// References to variables, locals, module outputs and the like name no resource.
+fn referred_resource(reference: &str) -> Option<(String, String)> {
+    let segments = segments(reference);
+    let mut prefix = String::new();
+    let mut rest = &segments[..];
+    while rest.len() > 2 && rest[0] == "module" {
+        prefix.push_str(&format!("module.{}.", rest[1]));
+        rest = &rest[2..];
+    }
+
+    let (mode, rest) = match rest.first() {
+        Some(first) if first == "data" => ("data.", &rest[1..]),
+        Some(first) if NOT_RESOURCES.contains(&first.as_str()) => return None,
+        _ => ("", rest),
+    };
+    if rest.len() < 2 {
+        return None;
+    }
+
+    let base = format!("{}{}{}.{}", prefix, mode, rest[0], without_key(&rest[1]));
+    let keyed = format!("{}{}{}.{}", prefix, mode, rest[0], rest[1]);
+    Some((base, keyed))
+}
+
+/// The addresses of the instances read which the references name, each once and never the instance itself.
+/// A reference without an instance key, or with one which wasn't read, names every instance of the resource.
+fn resolve(referred: &[String], own: &str, addresses: &[String]) -> Vec<String> {
+    let mut resolved: Vec<String> = vec![];
+
+    for (base, keyed) in referred.iter().filter_map(|reference| referred_resource(reference)) {
+        let targets: Vec<&String> = match addresses.iter().find(|address| **address == keyed) {
+            Some(address) => vec![address],
+            None => addresses.iter().filter(|address| **address == base || address.starts_with(&format!("{}[", base))).collect(),
+        };
+
+        for target in targets {
+            if target != own && !resolved.contains(target) {
+                resolved.push(target.to_string());
+            }
+        }
+    }
+
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::json::JsonValue;
+
+    const PLAN: &str = r#"{
+  "format_version": "1.2",
+  "terraform_version": "1.5.7",
+  "resource_changes": [
+    {
+      "address": "aws_sqs_queue.orders[0]",
+      "mode": "managed",
+      "type": "aws_sqs_queue",
+      "name": "orders",
+      "index": 0,
+      "change": {
+        "actions": ["create"],
+        "before": null,
+        "after": { "name": "orders-0", "delay_seconds": 0, "fifo_queue": false, "policy": null },
+        "after_unknown": { "arn": true, "id": true }
+      }
+    },
+    {
+      "address": "aws_lambda_function.worker",
+      "mode": "managed",
+      "type": "aws_lambda_function",
+      "name": "worker",
+      "change": {
+        "actions": ["delete", "create"],
+        "before": { "function_name": "worker", "runtime": "nodejs12.x" },
+        "after": { "function_name": "worker", "runtime": "nodejs18.x", "environment": [{ "variables": { "QUEUE": "orders-0" } }] },
+        "after_unknown": {}
+      }
+    },
+    {
+      "address": "module.audit.aws_iam_role.role",
+      "module_address": "module.audit",
+      "mode": "managed",
+      "type": "aws_iam_role",
+      "name": "role",
+      "change": {
+        "actions": ["delete"],
+        "before": { "name": "audit", "assume_role_policy": "{\"Version\":\"2012-10-17\",\"Statement\":[]}" },
+        "after": null
+      }
+    }
+  ],
+  "configuration": {
+    "root_module": {
+      "resources": [
+        {
+          "address": "aws_lambda_function.worker",
+          "expressions": {
+            "function_name": { "constant_value": "worker" },
+            "environment": [{ "variables": { "references": ["aws_sqs_queue.orders[0].name", "aws_sqs_queue.orders"] } }],
+            "role": { "references": ["module.audit.role_arn", "module.audit"] }
+          },
+          "depends_on": ["var.region"]
+        }
+      ],
+      "module_calls": {
+        "audit": {
+          "module": {
+            "resources": [
+              { "address": "aws_iam_role.role", "expressions": { "name": { "references": ["var.name"] } } }
+            ]
+          }
+        }
+      }
+    }
+  }
+}"#;
+
+    const STATE: &str = r#"{
+  "format_version": "1.0",
+  "terraform_version": "1.5.7",
+  "values": {
+    "root_module": {
+      "resources": [
+        { "address": "aws_sqs_queue.orders", "mode": "managed", "type": "aws_sqs_queue", "name": "orders", "values": { "name": "orders" } },
+        { "address": "data.aws_iam_policy_document.read", "mode": "data", "type": "aws_iam_policy_document", "name": "read", "values": { "json": "{}" } }
+      ],
+      "child_modules": [
+        {
+          "address": "module.workers[\"a.b\"]",
+          "resources": [
+            {
+              "address": "module.workers[\"a.b\"].aws_lambda_function.worker",
+              "mode": "managed",
+              "type": "aws_lambda_function",
+              "name": "worker",
+              "values": { "function_name": "worker" },
+              "depends_on": ["aws_sqs_queue.orders", "data.aws_iam_policy_document.read"]
+            }
+          ]
+        }
+      ]
+    }
+  }
+}"#;
+
+    fn value<'a>(block: &'a TerraformBlock, key: &str) -> &'a AttributeType {
+        &block.attributes().iter().find(|attr| attr.key == key).unwrap().value
+    }
+
+    fn two_identifiers(block: &TerraformBlock) -> &TerraformBlockWithTwoIdentifiers {
+        match block {
+            TerraformBlock::WithTwoIdentifiers(resource) => resource,
+            _ => panic!("expected a resource, got {:?}", block),
+        }
+    }
+
+    #[test]
+    fn plans_become_resources_with_actions() {
+        let (blocks, errors) = root(PLAN);
+        assert_eq!(errors, vec![]);
+
+        let addresses: Vec<String> = blocks.iter().map(|block| block.address()).collect();
+        assert_eq!(addresses, vec!["aws_sqs_queue.orders[0]", "aws_lambda_function.worker", "module.audit.aws_iam_role.role"]);
+
+        let queue = two_identifiers(&blocks[0]);
+        assert_eq!(queue.planned_actions, vec!["create"]);
+        assert_eq!(value(&blocks[0], "delay_seconds"), &AttributeType::Num(0.0));
+        assert_eq!(value(&blocks[0], "arn"), &AttributeType::Str(String::from(UNKNOWN)));
+        assert!(blocks[0].attributes().iter().all(|attr| attr.key != "policy"));
+        assert_eq!(queue.span.start.line, 5);
+
+        // the planned values are used, and nested blocks look as they do in templates
+        assert_eq!(value(&blocks[1], "runtime"), &AttributeType::Str(String::from("nodejs18.x")));
+        assert!(matches!(value(&blocks[1], "environment"), AttributeType::Block(_)));
+        assert_eq!(two_identifiers(&blocks[1]).planned_actions, vec!["delete", "create"]);
+
+        // a deleted resource keeps the values it had
+        let role = two_identifiers(&blocks[2]);
+        assert_eq!(role.module_path, vec!["audit"]);
+        assert_eq!(role.planned_actions, vec!["delete"]);
+        assert!(matches!(value(&blocks[2], "assume_role_policy"), AttributeType::Json(JsonValue::Object(_))));
+    }
+
+    #[test]
+    fn configuration_references_become_references() {
+        let (blocks, _) = root(PLAN);
+
+        // references to variables and module outputs name no resource
+        assert_eq!(two_identifiers(&blocks[1]).references, vec!["aws_sqs_queue.orders[0]"]);
+        assert_eq!(two_identifiers(&blocks[0]).references, Vec::<String>::new());
+        assert_eq!(two_identifiers(&blocks[2]).references, Vec::<String>::new());
+    }
+
+    #[test]
+    fn states_become_resources_with_depends_on() {
+        let (blocks, errors) = root(STATE);
+        assert_eq!(errors, vec![]);
+
+        let worker = two_identifiers(&blocks[2]);
+        assert_eq!(blocks[1].address(), "data.aws_iam_policy_document.read");
+        assert_eq!(worker.module_path, vec!["workers[\"a.b\"]"]);
+        assert_eq!(blocks[2].address(), "module.workers[\"a.b\"].aws_lambda_function.worker");
+        assert_eq!(worker.references, vec!["aws_sqs_queue.orders", "data.aws_iam_policy_document.read"]);
+        assert_eq!(worker.planned_actions, Vec::<String>::new());
+
+        assert!(is_plan(STATE));
+        assert!(!is_plan(r#"{"resource": {}}"#));
+        assert_eq!(root("[]").1[0].expected, "the output of `terraform show -json`");
+    }
+}
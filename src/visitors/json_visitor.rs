@@ -1,219 +1,172 @@
 /// This Visitor will visit every stucture in the tree of Structs and generate json
+/// - values keep their types: numbers and booleans are written as JSON numbers and booleans, whole numbers without a fraction
+/// - strings hold their text with HCL's escapes decoded when parsed, and are escaped once by serde_json; templates and expressions are written as they appear in the template, eg. `"${md5(var.x)}"`
+/// - a key repeated within a block, such as two `ingress` blocks, is written once with an array of each of its values
 
 // Useful articles on the Visitor pattern:
 // https://michael-f-bryan.github.io/calc/book/html/parse/visit.html
 // https://www.lihaoyi.com/post/ZeroOverheadTreeProcessingwiththeVisitorPattern.html
-use std::cell::RefCell;
+use serde_json::{ Map, Number, Value };
 
 use crate::structs::attributes::{ Attribute, AttributeType };
 use AttributeType::{
     Array, Block, Boolean, Json, Num, Str, TFBlock, TemplatedString,
 };
-use crate::structs::template_string::{ TemplateString };
+use crate::structs::template_string::{ Expression, TemplateString };
 use crate::structs::json::JsonValue;
+use crate::structs::span::Span;
 
-use TemplateString::{ Variable, BuiltInFunction };
-
-use crate::structs::terraform_block::{
-    TerraformBlock,
-    TerraformBlock::{
-        NoIdentifiers,
-        WithOneIdentifier,
-        WithTwoIdentifiers,
-    },
-    TerraformBlockWithNoIdentifiers,
-    TerraformBlockWithOneIdentifier,
-    TerraformBlockWithTwoIdentifiers,
-};
+use crate::structs::terraform_block::TerraformBlock;
 
-use crate::relationship_finders::relationship_finder::{RelationshipFinder};
-use crate::visitors::visitor::{ PolicyEvaluator, Visitor };
-use crate::visitors::relationship_visitor::Relationship;
+use crate::graph::Node;
+use crate::visitors::visitor::Visitor;
 
 
-pub struct JsonVisitor {
-    pub relationships: RefCell<Vec<Relationship>>,
-}
+pub struct JsonVisitor {}
 
-impl RelationshipFinder for JsonVisitor {
-    fn add_relationship(&self, relationship: Relationship) {
-        self.relationships.borrow_mut().push(relationship);
-    }
+impl JsonVisitor {
+    /// where the block and its top level attributes came from, so the front end can highlight them.
+    /// Blocks which weren't read from a template have no span and get no span fields.
+    fn span_fields(span: &Span, attributes: &Vec<Attribute>, object: &mut Map<String, Value>) {
+        if span.is_empty() {
+            return;
+        }
 
-    fn output_relationships(&self) -> String {
-        let joined_relationships = self.relationships.borrow().iter().map(|rel| format!("{}", rel)).collect::<Vec<String>>().join(",");
-        format!("[{}]", joined_relationships)
+        let attribute_spans: Map<String, Value> = attributes.iter()
+            .filter(|attr| !attr.span.is_empty())
+            .map(|attr| (attr.key.to_string(), serde_json::to_value(&attr.span).unwrap()))
+            .collect();
+
+        object.insert(String::from("span"), serde_json::to_value(span).unwrap());
+        object.insert(String::from("attribute_spans"), Value::Object(attribute_spans));
     }
-}
 
-impl Visitor<String> for JsonVisitor {
-    fn visit_str(&self, value: &String) -> String {
-        let mut s = String::new();
-        s.push_str(r#"""#);
-        let res = &value.replace("'", "");
-        s.push_str(res);
-        s.push_str(r#"""#);
-        s
+    /// a node of the graph, as it appears in the `resources` of graph.json
+    pub fn visit_node(&self, node: &Node) -> Value {
+        let mut object = Map::new();
+        object.insert(String::from("type"), Value::String(node.node_type.to_string()));
+        if let Some(name) = &node.name {
+            object.insert(String::from("name"), Value::String(name.to_string()));
+        }
+        // resources expanded from a module say which one, eg. "module":"module.queues"
+        if let Some(module) = &node.module {
+            object.insert(String::from("module"), Value::String(module.to_string()));
+        }
+        // resources read from a plan say what it will do to them, eg. "actions":["delete","create"]
+        if !node.planned_actions.is_empty() {
+            object.insert(String::from("actions"), serde_json::to_value(&node.planned_actions).unwrap());
+        }
+        Self::span_fields(&node.span, &node.attributes, &mut object);
+        object.insert(String::from("body"), self.visit_block(&node.attributes));
+
+        Value::Object(object)
     }
 
-    fn visit_template_string(&self, value: &TemplateString) -> String {
-        let val = match value {
-            Variable(tstring) => tstring.to_owned(),
-            BuiltInFunction(bif) => String::from("builtinfunction"),
-        };
-        let mut s = String::new();
-        s.push_str(r#"""#);
-        let res = if val.contains("md5") {
-            ""
+    /// whole numbers are written without a fraction, numbers JSON can't hold, such as NaN, as null
+    fn number(value: f64) -> Value {
+        if value.fract() == 0.0 && value.abs() < 9_007_199_254_740_992.0 {
+            Value::Number(Number::from(value as i64))
         } else {
-            &val
-        };
-        s.push_str(res);
-        s.push_str(r#"""#);
-        s
+            Number::from_f64(value).map_or(Value::Null, Value::Number)
+        }
+    }
+
+    /// an attribute's value, eg. the result of a query
+    pub fn visit_value(&self, value: &AttributeType) -> Value {
+        match value {
+            Str(str_inside) => self.visit_str(str_inside),
+            TemplatedString(str_inside) => self.visit_template_string(str_inside),
+            Boolean(bool_inside) => self.visit_boolean(bool_inside),
+            Num(num_inside) => self.visit_num(num_inside),
+            Array(arr_inside) => self.visit_array(arr_inside),
+            Block(block_inside) => self.visit_block(block_inside),
+            TFBlock(tfblock_inside) => self.visit_tfblock(tfblock_inside),
+            Json(json_inside) => self.visit_json(json_inside),
+            AttributeType::Expression(expression) => self.visit_expression(expression),
+        }
     }
+}
 
-    fn visit_boolean(&self, value: &bool) -> String {
-        let bstring = match value {
-            true => "true",
-            false => "false",
-        };
-        let mut s = String::new();
-        s.push_str(r#"""#);
-        s.push_str(bstring);
-        s.push_str(r#"""#);
-        s
+impl Visitor<Value> for JsonVisitor {
+    fn visit_str(&self, value: &String) -> Value {
+        Value::String(value.to_string())
     }
 
-    fn visit_num(&self, value: &f64) -> String {
-        let mut s = String::new();
-        s.push_str(r#"""#);
-        let num_str = format!("{:.1}", &value);
-        s.push_str(&num_str);
-        s.push_str(r#"""#);
-        s
+    fn visit_template_string(&self, value: &TemplateString) -> Value {
+        Value::String(value.to_string())
     }
 
-    fn visit_block(&self, value: &Vec<Attribute>) -> String {
-        let mut s = String::new();
-        s.push_str("{");
-        let attributes_json: Vec<String> = value.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-        let attributes_joined = attributes_json.join(",");
-        s.push_str(&attributes_joined.to_string());
-        s.push_str("}");
-        s
+    fn visit_expression(&self, value: &Expression) -> Value {
+        Value::String(value.to_string())
     }
 
-    fn visit_array(&self, value: &Vec<AttributeType>) -> String {
-        let mut s = String::new();
-        let attributes_json: Vec<String> = value.into_iter().map(|attr| 
-            match attr {
-                Str(str_inside) => self.visit_str(str_inside),
-                TemplatedString(str_inside) => self.visit_template_string(str_inside),
-                Boolean(bool_inside) => self.visit_boolean(bool_inside),
-                Num(num_inside) => self.visit_num(num_inside),
-                Array(arr_inside) => self.visit_array(arr_inside),
-                Block(block_inside) => self.visit_block(block_inside),
-                TFBlock(tfblock_inside) => self.visit_tfblock(tfblock_inside),
-                Json(json_inside) => self.visit_json(json_inside),
-            }
-        ).collect();
+    fn visit_boolean(&self, value: &bool) -> Value {
+        Value::Bool(*value)
+    }
 
-        s.push_str("[");
-        let attributes_joined = attributes_json.join(",");
-        s.push_str(&attributes_joined.to_string());
-        s.push_str("]");
-        s
+    fn visit_num(&self, value: &f64) -> Value {
+        Self::number(*value)
     }
 
-    fn visit_json_array(&self, value: &Vec<JsonValue>) -> String {
-        let array_items: Vec<String> = value.into_iter().map(|val| self.visit_json(&val)).collect();
-        let array_items_joined = array_items.join(",");
+    fn visit_block(&self, value: &Vec<Attribute>) -> Value {
+        let mut object = Map::new();
+
+        for attr in value {
+            let visited = self.visit_value(&attr.value);
+            let repeated = value.iter().filter(|other| other.key == attr.key).count() > 1;
+
+            match object.get_mut(&attr.key) {
+                Some(Value::Array(values)) if repeated => values.push(visited),
+                _ if repeated => { object.insert(attr.key.to_string(), Value::Array(vec![visited])); },
+                _ => { object.insert(attr.key.to_string(), visited); },
+            }
+        }
 
-        format!("[{}]", &array_items_joined)
+        Value::Object(object)
     }
 
-    fn visit_json_object(&self, value: &Vec<(String, JsonValue)>) -> String {
-        let array_items: Vec<String> = value.into_iter()
-            .map(|(key, val)| {
-                format!(r#""{}":{}"#, key, self.visit_json(&val))
-            }).collect();
+    fn visit_array(&self, value: &Vec<AttributeType>) -> Value {
+        Value::Array(value.iter().map(|item| self.visit_value(item)).collect())
+    }
 
-        let array_items_joined = array_items.join(",");
+    fn visit_json_array(&self, value: &Vec<JsonValue>) -> Value {
+        Value::Array(value.iter().map(|val| self.visit_json(val)).collect())
+    }
 
-        format!("{{{}}}", &array_items_joined)
+    fn visit_json_object(&self, value: &Vec<(String, JsonValue)>) -> Value {
+        Value::Object(value.iter().map(|(key, val)| (key.to_string(), self.visit_json(val))).collect())
     }
 
-    fn visit_json(&self, value: &JsonValue) -> String {
+    fn visit_json(&self, value: &JsonValue) -> Value {
         match &value {
             JsonValue::Str(str_inside) => self.visit_str(str_inside),
             JsonValue::Boolean(bool_inside) => self.visit_boolean(bool_inside),
             JsonValue::Num(num_inside) => self.visit_num(num_inside),
-            JsonValue::Null(str_inside) => self.visit_str(str_inside),
+            JsonValue::Null(_) => Value::Null,
             JsonValue::Array(arr_inside) => self.visit_json_array(arr_inside),
             JsonValue::Object(obj_inside) => self.visit_json_object(obj_inside),
         }
     }
 
-    fn visit_attribute(&self, attr: &Attribute) -> String {
-        let value = match &attr.value {
-            Str(str_inside) => self.visit_str(str_inside),
-            TemplatedString(str_inside) => self.visit_template_string(str_inside),
-            Boolean(bool_inside) => self.visit_boolean(bool_inside),
-            Num(num_inside) => self.visit_num(num_inside),
-            Array(arr_inside) => self.visit_array(arr_inside),
-            Block(block_inside) => self.visit_block(block_inside),
-            TFBlock(tfblock_inside) => self.visit_tfblock(tfblock_inside),
-            Json(json_inside) => self.visit_json(json_inside),
-        };
-
-        format!(r#""{}":{}"#, &attr.key, value)
+    fn visit_attribute(&self, attr: &Attribute) -> Value {
+        let mut object = Map::new();
+        object.insert(attr.key.to_string(), self.visit_value(&attr.value));
+        Value::Object(object)
     }
 
-    fn visit_tfblock(&self, value: &TerraformBlock) -> String {
-        match value {
-            NoIdentifiers(
-                TerraformBlockWithNoIdentifiers {
-                    block_type,
-                    attributes
-                }
-            ) => {
-                let attributes_json: Vec<String> = attributes.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-                let attributes_joined = attributes_json.join(",");
-
-                format!(r#"{{"type":"{}","body":{{{}}}}}"#, block_type, attributes_joined)
-            },
-            WithOneIdentifier(
-                TerraformBlockWithOneIdentifier {
-                    block_type,
-                    first_identifier,
-                    attributes
-                }
-            ) => {
-                let attributes_json: Vec<String> = attributes.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-                let attributes_joined = attributes_json.join(",");
-
-                format!(r#"{{"type":"{}","name":"{}","body":{{{}}}}}"#, block_type, first_identifier, attributes_joined)
-            },
-            WithTwoIdentifiers(
-                TerraformBlockWithTwoIdentifiers {
-                    block_type,
-                    first_identifier,
-                    second_identifier,
-                    attributes
-                }
-            ) => {
-                let attributes_json: Vec<String> = attributes.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-                let attributes_joined = attributes_json.join(",");
-                format!(r#"{{"type":"{}","name":"{}","body":{{{}}}}}"#, first_identifier, second_identifier, attributes_joined)
-            },
-        }
+    fn visit_tfblock(&self, value: &TerraformBlock) -> Value {
+        self.visit_node(&Node::from_block(value))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::structs::terraform_block::{
+        TerraformBlock::WithOneIdentifier,
+        TerraformBlockWithOneIdentifier,
+        TerraformBlockWithTwoIdentifiers,
+    };
 
     #[test]
     fn json_visitor_test() {
@@ -224,20 +177,22 @@ mod tests {
                 attributes: vec![
                     Attribute {
                         key: String::from("backend"),
-                        value: Str(String::from("s3"))
+                        value: Str(String::from("s3")),
+                        span: Span::default()
                     },
                     Attribute {
                         key: String::from("bookend"),
-                        value: Boolean(true)
+                        value: Boolean(true),
+                        span: Span::default()
                     }
-                ]
+                ],
+                span: Span::default()
             }
         );
-        let expected = String::from(r#"{"type":"resource","name":"thing1","body":{"backend":"s3","bookend":"true"}}"#);
-        let mut vec = Vec::new();
-        let visitor = JsonVisitor{relationships: RefCell::new(vec)};
+        let expected = String::from(r#"{"type":"resource","name":"thing1","body":{"backend":"s3","bookend":true}}"#);
+        let visitor = JsonVisitor{};
         let result = visitor.visit_tfblock(&resource1);
-        assert_eq!(result, expected)
+        assert_eq!(result.to_string(), expected)
     }
 
     #[test]
@@ -252,16 +207,20 @@ mod tests {
                         key: String::from("policy"),
                         value: AttributeType::Json(JsonValue::Object(vec![
                             (String::from("deadLetterTargetArn"), JsonValue::Str(String::from("${aws_sqs_queue.discovery_collector-deadletter-queue.arn}"))),
-                            (String::from("maxReceiveCount"), JsonValue::Num(2.0))]))
+                            (String::from("maxReceiveCount"), JsonValue::Num(2.0))])),
+                        span: Span::default()
                     },
-                ]
+                ],
+                span: Span::default(),
+                module_path: vec![],
+                planned_actions: vec![],
+                references: vec![],
             }
         );
-        let expected = String::from(r#"{"type":"aws_sqs_queue","name":"discovery_collector-queue","body":{"policy":{"deadLetterTargetArn":"${aws_sqs_queue.discovery_collector-deadletter-queue.arn}","maxReceiveCount":"2.0"}}}"#);
-        let mut vec = Vec::new();
-        let visitor = JsonVisitor{relationships: RefCell::new(vec)};
+        let expected = String::from(r#"{"type":"aws_sqs_queue","name":"discovery_collector-queue","body":{"policy":{"deadLetterTargetArn":"${aws_sqs_queue.discovery_collector-deadletter-queue.arn}","maxReceiveCount":2}}}"#);
+        let visitor = JsonVisitor{};
         let result = visitor.visit_tfblock(&resource1);
-        assert_eq!(result, expected)
+        assert_eq!(result.to_string(), expected)
     }
 
     #[test]
@@ -272,21 +231,24 @@ mod tests {
                 first_identifier: String::from("aws_cloudwatch_metric_alarm"),
                 second_identifier: String::from("discovery_diff-engine-queue-cloudwatch-alaram-messages-high"),
                 attributes: vec![
-                    Attribute { key: String::from("alarm_name"), value: AttributeType::Str(String::from( "discovery_cloudwatch-diff-engine-queue-cloudwatch-alarm-messages-high")) },
+                    Attribute { key: String::from("alarm_name"), value: AttributeType::Str(String::from( "discovery_cloudwatch-diff-engine-queue-cloudwatch-alarm-messages-high")), span: Span::default() },
                     Attribute { key: String::from("alarm_actions"), value: AttributeType::Array(
                         vec![
                             AttributeType::TemplatedString(TemplateString::Variable(String::from("aws_appautoscaling_policy.discovery_diff-engine-autoscaling-up.arn"))),
                             AttributeType::TemplatedString(TemplateString::Variable(String::from("aws_appautoscaling_policy.discovery_diff-engine-autoscaling-down.arn")))
                         ]
-                    )}
-                ] 
+                    ), span: Span::default() }
+                ],
+                span: Span::default(),
+                module_path: vec![],
+                planned_actions: vec![],
+                references: vec![],
             }
         );
-        let expected = String::from(r#"{"type":"aws_cloudwatch_metric_alarm","name":"discovery_diff-engine-queue-cloudwatch-alaram-messages-high","body":{"alarm_name":"discovery_cloudwatch-diff-engine-queue-cloudwatch-alarm-messages-high","alarm_actions":["aws_appautoscaling_policy.discovery_diff-engine-autoscaling-up.arn","aws_appautoscaling_policy.discovery_diff-engine-autoscaling-down.arn"]}}"#);
-        let mut vec = Vec::new();
-        let visitor = JsonVisitor{relationships: RefCell::new(vec)};
+        let expected = String::from(r#"{"type":"aws_cloudwatch_metric_alarm","name":"discovery_diff-engine-queue-cloudwatch-alaram-messages-high","body":{"alarm_name":"discovery_cloudwatch-diff-engine-queue-cloudwatch-alarm-messages-high","alarm_actions":["${aws_appautoscaling_policy.discovery_diff-engine-autoscaling-up.arn}","${aws_appautoscaling_policy.discovery_diff-engine-autoscaling-down.arn}"]}}"#);
+        let visitor = JsonVisitor{};
         let result = visitor.visit_tfblock(&resource1);
-        assert_eq!(result, expected)
+        assert_eq!(result.to_string(), expected)
     }
 
     #[test]
@@ -297,31 +259,122 @@ mod tests {
                 first_identifier: String::from("aws_cloudwatch_log_metric_filter"),
                 second_identifier: String::from("discovery_diff-tagging-failed-event-error"),
                 attributes: vec![
-                    Attribute { key: String::from("name"), value: AttributeType::Str(String::from("diff_tagging_failed_event")) },
+                    Attribute { key: String::from("name"), value: AttributeType::Str(String::from("diff_tagging_failed_event")), span: Span::default() },
                     Attribute { key: String::from("metric_transformation"), value: AttributeType::Block(
                         vec![
                             Attribute {
                                 key: String::from("name"),
-                                value: AttributeType::Str(String::from("diff_tagging_failed_event"))
+                                value: AttributeType::Str(String::from("diff_tagging_failed_event")),
+                                span: Span::default()
                             },
                             Attribute {
                                 key: String::from("namespace"),
-                                value: AttributeType::Str(String::from("diff_tagging_log_metrics"))
+                                value: AttributeType::Str(String::from("diff_tagging_log_metrics")),
+                                span: Span::default()
                             },
                             Attribute {
                                 key: String::from("value"),
-                                value: AttributeType::Str(String::from("1"))
+                                value: AttributeType::Str(String::from("1")),
+                                span: Span::default()
                             }
                         ]
-                    )}
-                ] 
+                    ), span: Span::default() }
+                ],
+                span: Span::default(),
+                module_path: vec![],
+                planned_actions: vec![],
+                references: vec![],
             }
         );
 
         let expected = String::from(r#"{"type":"aws_cloudwatch_log_metric_filter","name":"discovery_diff-tagging-failed-event-error","body":{"name":"diff_tagging_failed_event","metric_transformation":{"name":"diff_tagging_failed_event","namespace":"diff_tagging_log_metrics","value":"1"}}}"#);
-        let mut vec = Vec::new();
-        let visitor = JsonVisitor{relationships: RefCell::new(vec)};
+        let visitor = JsonVisitor{};
         let result = visitor.visit_tfblock(&resource1);
-        assert_eq!(result, expected)
+        assert_eq!(result.to_string(), expected)
+    }
+
+    #[test]
+    fn json_visitor_spans() {
+        let data = "resource \"aws_kms_key\" \"key\" {\n    enable_key_rotation = true\n}\n";
+        let (resources, _) = crate::terraform::root(data);
+
+        let expected = String::from(r#"{"type":"aws_kms_key","name":"key","span":{"start":{"offset":0,"line":1,"column":1},"end":{"offset":63,"line":3,"column":2}},"attribute_spans":{"enable_key_rotation":{"start":{"offset":35,"line":2,"column":5},"end":{"offset":61,"line":2,"column":31}}},"body":{"enable_key_rotation":true}}"#);
+        let visitor = JsonVisitor{};
+        let result = visitor.visit_tfblock(&resources[0]);
+        assert_eq!(result.to_string(), expected)
+    }
+
+    #[test]
+    fn json_visitor_values_keep_their_types() {
+        let data = r#"resource "aws_security_group" "group" {
+    revoke_rules_on_delete = false
+    etag = "${md5(file("config.json"))}"
+    ratio = 0.5
+    ingress {
+        from_port = 443
+    }
+    ingress {
+        from_port = 80
+    }
+}
+"#;
+        let (resources, _) = crate::terraform::root(data);
+        let visitor = JsonVisitor{};
+        let result = visitor.visit_tfblock(&resources[0]);
+
+        assert_eq!(result["body"]["revoke_rules_on_delete"], Value::Bool(false));
+        assert_eq!(result["body"]["etag"], Value::String(String::from("${md5(file(\"config.json\"))}")));
+        assert_eq!(result["body"]["ratio"], serde_json::json!(0.5));
+        assert_eq!(result["body"]["ingress"], serde_json::json!([{"from_port": 443}, {"from_port": 80}]));
+
+        let escaped = visitor.visit_str(&String::from("it's \"quoted\"\\\n")).to_string();
+        assert_eq!(escaped, r#""it's \"quoted\"\\\n""#);
+    }
+
+    #[test]
+    fn strings_round_trip_with_quotes_and_backslashes() {
+        let data = r#"resource "aws_ssm_parameter" "parameter" {
+    value = "say \"hi\" to c:\\dir\\"
+    description = "tab\tand \u00e9"
+    template = "${var.prefix}\"quoted\"\\$${literal}"
+}
+"#;
+        let (resources, errors) = crate::terraform::root(data);
+        assert_eq!(errors, vec![]);
+
+        let visitor = JsonVisitor{};
+        let result = visitor.visit_tfblock(&resources[0]);
+        assert_eq!(result["body"]["value"], Value::String(String::from(r#"say "hi" to c:\dir\"#)));
+        assert_eq!(result["body"]["description"], Value::String(String::from("tab\tand \u{e9}")));
+        assert_eq!(result["body"]["template"], Value::String(String::from(r#"${var.prefix}"quoted"\$${literal}"#)));
+
+        let written = result["body"]["value"].to_string();
+        assert_eq!(written, r#""say \"hi\" to c:\\dir\\""#);
+        assert_eq!(serde_json::from_str::<Value>(&written).unwrap(), result["body"]["value"]);
+    }
+
+    #[test]
+    fn repeated_keys_become_arrays() {
+        let data = r#"resource "aws_security_group" "group" {
+    ingress {
+        from_port = 443
+    }
+    egress {
+        to_port = 0
+    }
+    ingress {
+        from_port = 80
+    }
+}
+"#;
+        let (resources, _) = crate::terraform::root(data);
+        let visitor = JsonVisitor{};
+        let result = visitor.visit_tfblock(&resources[0]);
+
+        // every value of a repeated key is kept, in the order written, while a key written once keeps its value
+        assert_eq!(result["body"], serde_json::json!({
+            "ingress": [{"from_port": 443}, {"from_port": 80}],
+            "egress": {"to_port": 0},
+        }));
     }
 }
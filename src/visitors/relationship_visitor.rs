@@ -2,16 +2,17 @@ use std::cell::RefCell;
 use std::fmt;
 use std::marker::Copy;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 
 use crate::structs::attributes::{ Attribute, AttributeType };
 use AttributeType::{
     Array, Block, Boolean, Json, Num, Str, TFBlock, TemplatedString,
 };
-use crate::structs::template_string::{ TemplateString };
+use crate::structs::template_string::{ Expression, Reference, TemplateString };
 use crate::structs::json::JsonValue;
+use crate::expression::template_string;
 
-use TemplateString::{ Variable, BuiltInFunction };
 
 use crate::structs::terraform_block::{
     TerraformBlock,
@@ -28,6 +29,7 @@ use crate::structs::terraform_block::{
 use crate::visitors::visitor::{ Visitor };
 use crate::visitors::json_visitor::JsonVisitor;
 use crate::relationship_finders::relationship_finder::{RelationshipFinder};
+use crate::graph::{ Edge, EdgeKind, InfrastructureGraph, Node, Provenance };
 
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -53,33 +55,79 @@ impl fmt::Display for Relationship {
     }
 }
 
+/// Visits each top level block, adding it to the graph as a node along with the edges found from it
 pub struct RelationshipVisitor {
     pub downstream_visitor: JsonVisitor,
     pub aws_relationship_specs: HashMap<String, Relationship>,
+    pub graph: RefCell<InfrastructureGraph>,
 }
 
 impl RelationshipVisitor {
+    pub fn new(downstream_visitor: JsonVisitor, aws_relationship_specs: HashMap<String, Relationship>) -> RelationshipVisitor {
+        RelationshipVisitor { downstream_visitor, aws_relationship_specs, graph: RefCell::new(InfrastructureGraph::new()) }
+    }
+
+    pub fn into_graph(self) -> InfrastructureGraph {
+        self.graph.into_inner()
+    }
+
     pub fn extract_value(attribute: Option<&Attribute>) -> Option<String> {
         match attribute {
-            Some(Attribute { key, value: TemplatedString( template_string ) }) => {
-                match template_string {
-                    Variable(v_string) => {
-                        Some(v_string.to_string().replace(".id", "").replace(".arn", ""))
-                        // Self::handle_resource(&v_string.to_string())
-                    },
-                    _ => Some(String::from(""))
-                }
+            Some(Attribute { value: TemplatedString( template_string ), .. }) => {
+                Some(Self::template_address(template_string))
             },
-            Some(Attribute { key, value: Str( str_val ) }) => {
-                // Self::handle_resource(&str_val.to_string())
-                Some(str_val.to_string().replace(".id", "").replace(".arn", ""))
+            Some(Attribute { value: AttributeType::Expression( expression ), .. }) => {
+                Some(Self::expression_address(expression))
             },
+            // a literal refers to nothing, it's a name or an ARN
+            Some(Attribute { value: Str( str_val ), .. }) => Some(str_val.to_string()),
             // TODO: match on Str() for ARNs which were not templated
             Some(_) => Some(String::from("")),
             None => Some(String::from(""))
         }
     }
 
+    /// the address of the resource a template refers to, eg. `aws_sqs_queue.q` for `"arn:${aws_sqs_queue.q.arn}/x"`,
+    /// otherwise of the first thing it refers to, or the template itself when it refers to nothing
+    fn template_address(template_string: &TemplateString) -> String {
+        let references = template_string.to_expression().references();
+        let resource = references.iter().find(|reference| matches!(reference, Reference::Resource { .. } | Reference::Data { .. }));
+
+        match resource.or_else(|| references.first()) {
+            Some(reference) => reference.address(),
+            None => template_string.to_string(),
+        }
+    }
+
+    /// the addresses of the resources referred to anywhere within a value, each once
+    fn reference_targets(value: &AttributeType, targets: &mut Vec<String>) {
+        let references = match value {
+            AttributeType::Expression(expression) => expression.references(),
+            TemplatedString(template_string) => template_string.to_expression().references(),
+            Array(values) => return values.iter().for_each(|value| Self::reference_targets(value, targets)),
+            Block(attributes) => return attributes.iter().for_each(|attr| Self::reference_targets(&attr.value, targets)),
+            _ => vec![],
+        };
+
+        for reference in references {
+            if let Reference::Resource { .. } = reference {
+                if !targets.contains(&reference.address()) {
+                    targets.push(reference.address());
+                }
+            }
+        }
+    }
+
+    fn expression_address(expression: &Expression) -> String {
+        match expression {
+            Expression::Variable(traversal) => match traversal.reference() {
+                Some(reference) => reference.address(),
+                None => traversal.to_string(),
+            },
+            _ => String::from("")
+        }
+    }
+
     /// ARNs are split into parts separated by a colon.
     /// From these six parts a terraform syntax name can be generated.
     pub fn convert_arn_to_dot_syntax(arn_resource: &String) -> Option<String> {
@@ -114,7 +162,7 @@ impl RelationshipVisitor {
             },
             "logs" => None, // log relationships are not wanted on a diagram
             anything => {
-                println!("unknown arn: {:?}, for resource: {}", anything, arn_resource);
+                eprintln!("unknown arn: {:?}, for resource: {}", anything, arn_resource);
                 None
             },
         };
@@ -126,15 +174,11 @@ impl RelationshipVisitor {
         if resource.len() > 2 {
             match &resource[..3] {
                 "arn" => Self::convert_arn_to_dot_syntax(resource),
-                // templated_string if &templated_string[..2] == "${" => {
-                templated_string => {
-                    let templating_chars_removed = &resource[2..&resource.len()-1];
-                    
-                    let attribute_suffix_removed = templating_chars_removed.replace(".id", "").replace(".arn", "");
-                    
-                    Some(attribute_suffix_removed)
+                // a template within a policy document, eg. `${aws_sqs_queue.queue.arn}`
+                _ => match template_string(&format!("\"{}\"", resource)) {
+                    Ok((_, template)) => Some(Self::template_address(&template)),
+                    Err(_) => Some(resource.to_string()),
                 },
-                // others => Some(others.replace(".id", "").replace(".arn", "")),
             }
         } else {
             // we have hit a '*' wildcard
@@ -158,7 +202,7 @@ impl RelationshipVisitor {
                             Self::handle_resource(val)
                         },
                         anything => {
-                            println!("unknown type: {:?}", anything);
+                            eprintln!("unknown type: {:?}", anything);
                             None
                         },
                     }
@@ -170,7 +214,7 @@ impl RelationshipVisitor {
         // loop through targets and add actions where a value is present
         let mut withDuplicates: Vec<TargetAndLabel> = targets.into_iter().map(|target| {
             let vals = match action {
-                Some((key, JsonValue::Array(strings))) => self.downstream_visitor.visit_json_array(strings).replace("\"", "'"),
+                Some((key, JsonValue::Array(strings))) => self.downstream_visitor.visit_json_array(strings).to_string().replace("\"", "'"),
                 _ => String::from(""),
             };
             let tget = match target {
@@ -191,7 +235,7 @@ impl RelationshipVisitor {
         let dot_split = collection_path.split(".").collect::<Vec<&str>>();
 
         match attribute {
-            Some(Attribute { key, value: AttributeType::Json(JsonValue::Object( json_attributes )) }) => {
+            Some(Attribute { key, value: AttributeType::Json(JsonValue::Object( json_attributes )), .. }) => {
 
                 let nested_attr = json_attributes.into_iter().find(|&(attr, _)| attr == dot_split[1]);
 
@@ -221,73 +265,62 @@ impl RelationshipVisitor {
 }
 
 impl RelationshipFinder for RelationshipVisitor {
-    fn add_relationship(&self, relationship: Relationship) {
-        self.downstream_visitor.relationships.borrow_mut().push(relationship);
-    }
-
-    fn output_relationships(&self) -> String {
-        self.downstream_visitor.output_relationships()
+    fn add_relationship(&self, relationship: Relationship, kind: EdgeKind, provenance: Provenance) {
+        let (source, target, label) = match relationship {
+            Relationship::BasicRelationship { source, target, label } => (source, target, label),
+            Relationship::NestedRelationship { source, targets } => (source, targets.target, targets.label),
+        };
+        self.graph.borrow_mut().add_edge(Edge { source, target, label, kind, provenance });
     }
 }
 
-impl Visitor<String> for RelationshipVisitor {
-    fn visit_str(&self, value: &String) -> String {
+impl Visitor<Value> for RelationshipVisitor {
+    fn visit_str(&self, value: &String) -> Value {
         self.downstream_visitor.visit_str(value)
     }
 
-    fn visit_template_string(&self, value: &TemplateString) -> String {
+    fn visit_template_string(&self, value: &TemplateString) -> Value {
         self.downstream_visitor.visit_template_string(value)
     }
 
-    fn visit_boolean(&self, value: &bool) -> String {
+    fn visit_expression(&self, value: &Expression) -> Value {
+        self.downstream_visitor.visit_expression(value)
+    }
+
+    fn visit_boolean(&self, value: &bool) -> Value {
         self.downstream_visitor.visit_boolean(value)
     }
 
-    fn visit_num(&self, value: &f64) -> String {
+    fn visit_num(&self, value: &f64) -> Value {
         self.downstream_visitor.visit_num(value)
     }
 
-    fn visit_block(&self, value: &Vec<Attribute>) -> String {
+    fn visit_block(&self, value: &Vec<Attribute>) -> Value {
         self.downstream_visitor.visit_block(value)
     }
 
-    fn visit_array(&self, value: &Vec<AttributeType>) -> String {
+    fn visit_array(&self, value: &Vec<AttributeType>) -> Value {
         self.downstream_visitor.visit_array(value)
     }
 
-    fn visit_tfblock(&self, value: &TerraformBlock) -> String {
-        let bla: String = match value {
-            NoIdentifiers(
-                TerraformBlockWithNoIdentifiers {
-                    block_type,
-                    attributes
-                }
-            ) => {
-                let attributes_json: Vec<String> = attributes.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-                let attributes_joined = attributes_json.join(",");
-
-                format!(r#"{{"type":"{}","body":{{{}}}}}"#, block_type, attributes_joined)
-            },
-            WithOneIdentifier(
-                TerraformBlockWithOneIdentifier {
-                    block_type,
-                    first_identifier,
-                    attributes
-                }
-            ) => {
-                let attributes_json: Vec<String> = attributes.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-                let attributes_joined = attributes_json.join(",");
+    fn visit_tfblock(&self, value: &TerraformBlock) -> Value {
+        self.graph.borrow_mut().add_node(Node::from_block(value));
 
-                format!(r#"{{"type":"{}","name":"{}","body":{{{}}}}}"#, block_type, first_identifier, attributes_joined)
-            },
+        match value {
             WithTwoIdentifiers(
                 TerraformBlockWithTwoIdentifiers {
-                    block_type,
                     first_identifier,
-                    second_identifier,
-                    attributes
+                    attributes,
+                    references,
+                    ..
                 }
             ) => {
+                // plans and states say what each resource refers to, the specs still find what it relates to and how
+                for target in references {
+                    let relationship = Relationship::BasicRelationship { source: value.address(), target: target.to_string(), label: String::from("") };
+                    self.add_relationship(relationship, EdgeKind::Reference, Provenance::new(&value.address(), None))
+                }
+
                 match self.aws_relationship_specs.get(first_identifier) {
                     Some(Relationship::BasicRelationship { source, target, label }) => {
                         // TODO: 
@@ -313,7 +346,7 @@ impl Visitor<String> for RelationshipVisitor {
                                 let new_target = hack_lambda_attributes(target, target_val);
                                 let new_source = hack_lambda_attributes(source, source_val);
                                 let relationship = Relationship::BasicRelationship { source: new_source, target: new_target, label: String::from("") };
-                                self.downstream_visitor.add_relationship(relationship)
+                                self.add_relationship(relationship, EdgeKind::Relationship, Provenance::new(&value.address(), target_attr))
                             }
                         }
                     },
@@ -330,35 +363,43 @@ impl Visitor<String> for RelationshipVisitor {
                             for target_and_label in targets_and_labels.into_iter() {
                                 let cloned_source = source_val.clone();
                                 let relationship = Relationship::NestedRelationship { source: cloned_source, targets: target_and_label };
-                                self.downstream_visitor.add_relationship(relationship)
+                                self.add_relationship(relationship, EdgeKind::Permission, Provenance::new(&value.address(), target_attr))
+                            }
+                        }
+                    },
+                    // CloudFormation resources have no specs, each resource they Ref or GetAtt is related to them
+                    None if first_identifier.contains("::") => {
+                        for attribute in attributes.iter() {
+                            let mut targets = vec![];
+                            Self::reference_targets(&attribute.value, &mut targets);
+                            for target in targets {
+                                let relationship = Relationship::BasicRelationship { source: value.address(), target, label: String::from("") };
+                                self.add_relationship(relationship, EdgeKind::Reference, Provenance::new(&value.address(), Some(attribute)))
                             }
                         }
                     },
                     None => print!(""),
                 };
-
-                let attributes_json: Vec<String> = attributes.into_iter().map(|attr| self.visit_attribute(&attr)).collect();
-                let attributes_joined = attributes_json.join(",");
-                format!(r#"{{"type":"{}","name":"{}","body":{{{}}}}}"#, first_identifier, second_identifier, attributes_joined)
             },
+            _ => (),
         };
 
         self.downstream_visitor.visit_tfblock(value)
     }
 
-    fn visit_attribute(&self, value: &Attribute) -> String {
+    fn visit_attribute(&self, value: &Attribute) -> Value {
         self.downstream_visitor.visit_attribute(value)
     }
 
-    fn visit_json(&self, value: &JsonValue) -> String {
+    fn visit_json(&self, value: &JsonValue) -> Value {
         self.downstream_visitor.visit_json(value)
     }
 
-    fn visit_json_array(&self, value: &Vec<JsonValue>) -> String {
+    fn visit_json_array(&self, value: &Vec<JsonValue>) -> Value {
         self.downstream_visitor.visit_json_array(value)
     }
 
-    fn visit_json_object(&self, value: &Vec<(String, JsonValue)>) -> String {
+    fn visit_json_object(&self, value: &Vec<(String, JsonValue)>) -> Value {
         self.downstream_visitor.visit_json_object(value)
     }
 }
@@ -366,6 +407,7 @@ impl Visitor<String> for RelationshipVisitor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::structs::span::Span;
 
     #[test]
     fn relationship_visitor_test() {
@@ -376,25 +418,69 @@ mod tests {
                 attributes: vec![
                     Attribute {
                         key: String::from("backend"),
-                        value: Str(String::from("s3"))
+                        value: Str(String::from("s3")),
+                        span: Span::default()
                     },
                     Attribute {
                         key: String::from("bookend"),
-                        value: Boolean(true)
+                        value: Boolean(true),
+                        span: Span::default()
                     }
-                ]
+                ],
+                span: Span::default()
             }
         );
-        let expected = String::from(r#"{"type":"resource","name":"thing1","body":{"backend":"s3","bookend":"true"}}"#);
+        let expected = String::from(r#"{"type":"resource","name":"thing1","body":{"backend":"s3","bookend":true}}"#);
         
-        let mut vec = Vec::new();
         let mut h_map = HashMap::new();
-        let visitor = RelationshipVisitor{
-            downstream_visitor: JsonVisitor{relationships: RefCell::new(vec)},
-            aws_relationship_specs: h_map,
-        };  
+        let visitor = RelationshipVisitor::new(JsonVisitor{}, h_map);
         let result = visitor.visit_tfblock(&resource1);
-        assert_eq!(result, expected)
+        assert_eq!(result.to_string(), expected);
+        assert_eq!(visitor.into_graph().node("resource.thing1").unwrap().name, Some(String::from("thing1")))
+    }
+
+    #[test]
+    fn templates_resolve_to_the_resources_they_refer_to() {
+        let attribute = |data: &str| {
+            let (_, template) = template_string(data).unwrap();
+            Attribute { key: String::from("event_source_arn"), value: TemplatedString(template), span: Span::default() }
+        };
+        let address = |data: &str| RelationshipVisitor::extract_value(Some(&attribute(data)));
+
+        assert_eq!(address(r#""${aws_sqs_queue.queue.arn}""#), Some(String::from("aws_sqs_queue.queue")));
+        assert_eq!(address(r#""arn:${aws_sqs_queue.identity.arn}/x""#), Some(String::from("aws_sqs_queue.identity")));
+        assert_eq!(address(r#""${var.region}-${aws_sqs_queue.queue.id}""#), Some(String::from("aws_sqs_queue.queue")));
+        assert_eq!(address(r#""${var.queue_arn}""#), Some(String::from("var.queue_arn")));
+        let literal = Attribute { key: String::from("function_name"), value: Str(String::from("orders.identity")), span: Span::default() };
+        assert_eq!(RelationshipVisitor::extract_value(Some(&literal)), Some(String::from("orders.identity")));
+
+        assert_eq!(RelationshipVisitor::handle_resource(&String::from("${aws_sqs_queue.queue.arn}")), Some(String::from("aws_sqs_queue.queue")));
+        assert_eq!(RelationshipVisitor::handle_resource(&String::from("${aws_s3_bucket.logs.arn}/*")), Some(String::from("aws_s3_bucket.logs")));
+    }
+
+    #[test]
+    fn resources_with_references_still_match_the_specs() {
+        let data = r#"{"resource": {"aws_iam_role_policy": {"read": {
+            "role": "${aws_iam_role.worker.id}",
+            "policy": "{\"Statement\": [{\"Effect\": \"Allow\", \"Action\": [\"sqs:ReceiveMessage\"], \"Resource\": [\"${aws_sqs_queue.orders.arn}\"]}]}"
+        }}}}"#;
+        let (mut resources, errors) = crate::terraform_json::root(data);
+        assert_eq!(errors, vec![]);
+        // as read from a plan, which records what the resource refers to
+        if let WithTwoIdentifiers(block) = &mut resources[0] {
+            block.references = vec![String::from("aws_iam_role.worker")];
+        }
+
+        let specs = serde_yaml::from_str(include_str!("../../example_files/aws_relationships.yaml")).unwrap();
+        let visitor = RelationshipVisitor::new(JsonVisitor{}, specs);
+        visitor.visit_tfblock(&resources[0]);
+        let graph = visitor.into_graph();
+
+        let edges: Vec<(&str, &str, &str, EdgeKind)> = graph.edges().iter()
+            .map(|edge| (edge.source.as_str(), edge.target.as_str(), edge.label.as_str(), edge.kind))
+            .collect();
+        assert!(edges.contains(&("aws_iam_role_policy.read", "aws_iam_role.worker", "", EdgeKind::Reference)), "{:?}", edges);
+        assert!(edges.iter().any(|(_, target, label, kind)| *target == "aws_sqs_queue.orders" && label.contains("sqs:ReceiveMessage") && *kind == EdgeKind::Permission), "{:?}", edges);
     }
 
     #[test]
@@ -1,4 +1,3 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
 
 use crate::structs::terraform_block::{
@@ -10,26 +9,28 @@ use crate::visitors::visitor::Visitor;
 use crate::visitors::json_visitor::JsonVisitor;
 use crate::structs::policies::Policies;
 use crate::visitors::relationship_visitor::{RelationshipVisitor, Relationship};
-use crate::relationship_finders::relationship_finder::RelationshipFinder;
+use crate::graph::InfrastructureGraph;
+use crate::exporters::{ self, ExportOptions, Format, Rendered };
 use crate::policy_evaluator;
 
 
-pub fn dispatch(resources: &Vec<TerraformBlock>, aws_relationship_specs: HashMap<String, Relationship>, policies: Policies) -> String {
-    let mut vec = Vec::new();
+/// visit every block, building the graph of the resources and the relationships found between them
+pub fn build_graph(resources: &Vec<TerraformBlock>, aws_relationship_specs: HashMap<String, Relationship>) -> InfrastructureGraph {
+    let visitor = RelationshipVisitor::new(JsonVisitor{}, aws_relationship_specs);
 
-    let json_visitor = JsonVisitor{relationships: RefCell::new(vec)};
+    for resource in resources {
+        visitor.visit_tfblock(resource);
+    }
 
-    let visitor = RelationshipVisitor{ downstream_visitor: json_visitor, aws_relationship_specs };
-
-    let json_resources: Vec<String> = resources.into_iter().map(|resource| visitor.visit_tfblock(resource)).collect();
-    let json_resources_joined = json_resources.join(",");
-    let relationships = visitor.output_relationships();
+    visitor.into_graph()
+}
 
-    let policy_results = policy_evaluator::evaluate(policies, resources);
+pub fn dispatch(resources: &Vec<TerraformBlock>, aws_relationship_specs: HashMap<String, Relationship>, policies: Policies, format: Format, options: &ExportOptions) -> Rendered {
+    let graph = build_graph(resources, aws_relationship_specs);
 
-    let serialized = serde_json::to_string(&policy_results).unwrap();
+    let policy_results = policy_evaluator::evaluate_graph(policies, resources, &graph);
 
-    format!(r#"{{"resources":[{}],"relationships":{},"policy_results":{}}}"#, json_resources_joined, relationships, serialized)
+    exporters::render(format, &graph, &policy_results, options)
 }
 
 #[cfg(test)]
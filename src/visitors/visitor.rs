@@ -3,13 +3,14 @@ use crate::structs::terraform_block::{
 };
 
 use crate::structs::attributes::{ Attribute, AttributeType };
-use crate::structs::template_string::{ TemplateString };
+use crate::structs::template_string::{ Expression, TemplateString };
 use crate::structs::json::JsonValue;
 
 
 pub trait Visitor<T> {
     fn visit_str(&self, value: &String) -> T;
     fn visit_template_string(&self, value: &TemplateString) -> T;
+    fn visit_expression(&self, value: &Expression) -> T;
     fn visit_boolean(&self, value: &bool) -> T;
     fn visit_num(&self, value: &f64) -> T;
     fn visit_block(&self, value: &Vec<Attribute>) -> T;